@@ -0,0 +1,45 @@
+use crate::GbaState;
+
+/// Version of `Api`. Fields are only ever appended, so mods built against an older version keep working: check `version` before using fields added later.
+pub const VERSION: u32 = 1;
+
+pub type OnGameLoadCallback =
+    unsafe extern "C" fn(userdata: *mut std::ffi::c_void, game: u32, gba_state: *mut GbaState);
+
+/// The signature of an exported `on_game_load` function.
+pub type OnGameLoadFunction = unsafe extern "C" fn(game: u32, gba_state: *mut GbaState);
+
+/// The host API passed to `chaudloader_mod_init`. Must match `ChaudloaderApi` in `chaudloader/include/chaudloader.h`.
+#[repr(C)]
+pub struct Api {
+    pub version: u32,
+    pub size: u32,
+
+    pub mod_name: *const std::ffi::c_char,
+    pub mod_path: *const std::ffi::c_char,
+    pub game_volume: u32,
+    pub exe_crc32: u32,
+
+    pub log: unsafe extern "C" fn(api: *const Api, level: u32, message: *const std::ffi::c_char),
+    pub gba_read: unsafe extern "C" fn(
+        gba_state: *mut GbaState,
+        address: u32,
+        buf: *mut u8,
+        len: usize,
+    ) -> bool,
+    pub gba_write: unsafe extern "C" fn(
+        gba_state: *mut GbaState,
+        address: u32,
+        buf: *const u8,
+        len: usize,
+    ) -> bool,
+    pub loaded_game: unsafe extern "C" fn(game: *mut u32, gba_state: *mut *mut GbaState) -> bool,
+    pub register_on_game_load: unsafe extern "C" fn(
+        api: *const Api,
+        callback: OnGameLoadCallback,
+        userdata: *mut std::ffi::c_void,
+    ) -> bool,
+}
+
+/// The signature of an exported `chaudloader_mod_init` function.
+pub type ModInitFunction = unsafe extern "C" fn(api: *const Api);
@@ -0,0 +1,92 @@
+use crate::{GbaState, Host};
+
+/// Bytes that could not be accessed: not all in one mapped region, or written to a read-only region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Error {
+    pub address: u32,
+    pub len: usize,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} byte(s) at {:#010x} are not in a mapped and accessible region",
+            self.len, self.address
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Bounds-checked access to the memory of a running game, by GBA bus address.
+///
+/// Bounds are checked by chaudloader against the memory map described in API.md.
+#[derive(Clone, Copy)]
+pub struct Gba<'a> {
+    host: &'a Host,
+    state: *mut GbaState,
+}
+
+impl<'a> Gba<'a> {
+    /// # Safety
+    /// `state` must be the GBA state of a running game, e.g. as passed to `on_game_load`.
+    pub unsafe fn new(host: &'a Host, state: *mut GbaState) -> Self {
+        Self { host, state }
+    }
+
+    pub fn state(&self) -> *mut GbaState {
+        self.state
+    }
+
+    pub fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        if !unsafe { (self.host.api().gba_read)(self.state, address, buf.as_mut_ptr(), buf.len()) }
+        {
+            return Err(Error {
+                address,
+                len: buf.len(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, address: u32, buf: &[u8]) -> Result<(), Error> {
+        if !unsafe { (self.host.api().gba_write)(self.state, address, buf.as_ptr(), buf.len()) } {
+            return Err(Error {
+                address,
+                len: buf.len(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn read_u8(&self, address: u32) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.read(address, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn read_u16(&self, address: u32) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        self.read(address, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&self, address: u32) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        self.read(address, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn write_u8(&self, address: u32, value: u8) -> Result<(), Error> {
+        self.write(address, &[value])
+    }
+
+    pub fn write_u16(&self, address: u32, value: u16) -> Result<(), Error> {
+        self.write(address, &value.to_le_bytes())
+    }
+
+    pub fn write_u32(&self, address: u32, value: u32) -> Result<(), Error> {
+        self.write(address, &value.to_le_bytes())
+    }
+}
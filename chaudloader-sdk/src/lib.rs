@@ -0,0 +1,350 @@
+//! SDK for writing native chaudloader mods in Rust.
+//!
+//! A mod DLL exports its entry points with [`export_mod!`]:
+//!
+//! ```ignore
+//! fn init(host: &chaudloader_sdk::Host) {
+//!     log::info!("loaded from {}", host.mod_path().display());
+//! }
+//!
+//! fn on_game_load(game: chaudloader_sdk::Game, gba: chaudloader_sdk::Gba) {
+//!     log::info!("{:?}: {:?}", game, gba.read_u32(0x02000000));
+//! }
+//!
+//! chaudloader_sdk::export_mod! {
+//!     init = init,
+//!     on_game_load = on_game_load,
+//! }
+//! ```
+
+pub mod ffi;
+mod gba;
+#[cfg(feature = "lua")]
+pub mod lua;
+
+pub use gba::{Error, Gba};
+
+/// A Battle Network game, with the values passed to `on_game_load`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u32)]
+pub enum Game {
+    #[cfg_attr(feature = "serde", serde(rename = "BN1"))]
+    Bn1 = 0,
+    #[cfg_attr(feature = "serde", serde(rename = "BN2"))]
+    Bn2 = 2,
+    #[cfg_attr(feature = "serde", serde(rename = "BN3_White"))]
+    Bn3White = 3,
+    #[cfg_attr(feature = "serde", serde(rename = "BN3_Blue"))]
+    Bn3Blue = 4,
+    #[cfg_attr(feature = "serde", serde(rename = "BN4_RedSun"))]
+    Bn4RedSun = 5,
+    #[cfg_attr(feature = "serde", serde(rename = "BN4_BlueMoon"))]
+    Bn4BlueMoon = 6,
+    #[cfg_attr(feature = "serde", serde(rename = "BN5_ProtoMan"))]
+    Bn5ProtoMan = 7,
+    #[cfg_attr(feature = "serde", serde(rename = "BN5_Colonel"))]
+    Bn5Colonel = 8,
+    #[cfg_attr(feature = "serde", serde(rename = "BN6_Gregar"))]
+    Bn6Gregar = 9,
+    #[cfg_attr(feature = "serde", serde(rename = "BN6_Falzar"))]
+    Bn6Falzar = 10,
+}
+
+impl Game {
+    pub fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0 => Game::Bn1,
+            2 => Game::Bn2,
+            3 => Game::Bn3White,
+            4 => Game::Bn3Blue,
+            5 => Game::Bn4RedSun,
+            6 => Game::Bn4BlueMoon,
+            7 => Game::Bn5ProtoMan,
+            8 => Game::Bn5Colonel,
+            9 => Game::Bn6Gregar,
+            10 => Game::Bn6Falzar,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum GameVolume {
+    Vol1,
+    Vol2,
+}
+
+/// The emulator's GBA state. Only the start of the struct is known.
+#[repr(C)]
+pub struct GbaState {
+    pub r: [u32; 16],
+    pub cpu_flags: u32,
+    pub flags_implicit_update: u32,
+
+    /// GBA memory, indexed directly by bus address. Prefer [`Gba`], which is bounds-checked.
+    pub memory: *mut u8,
+}
+
+/// The chaudloader host API, as passed to `chaudloader_mod_init`.
+pub struct Host {
+    api: &'static ffi::Api,
+}
+
+// The API never changes after it is passed to the mod, and all of its functions may be called from any thread.
+unsafe impl Send for Host {}
+unsafe impl Sync for Host {}
+
+impl Host {
+    pub fn api(&self) -> &'static ffi::Api {
+        self.api
+    }
+
+    pub fn mod_name(&self) -> &str {
+        unsafe { std::ffi::CStr::from_ptr(self.api.mod_name) }
+            .to_str()
+            .unwrap_or_default()
+    }
+
+    /// The mod's folder, relative to the game's exe folder.
+    pub fn mod_path(&self) -> &std::path::Path {
+        std::path::Path::new(
+            unsafe { std::ffi::CStr::from_ptr(self.api.mod_path) }
+                .to_str()
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn game_volume(&self) -> Option<GameVolume> {
+        match self.api.game_volume {
+            1 => Some(GameVolume::Vol1),
+            2 => Some(GameVolume::Vol2),
+            _ => None,
+        }
+    }
+
+    pub fn exe_crc32(&self) -> u32 {
+        self.api.exe_crc32
+    }
+
+    /// Logs to the chaudloader console, prefixed with the mod name. The `log` crate's macros also log here once the mod is initialized.
+    pub fn log(&self, level: log::Level, message: &str) {
+        let message = std::ffi::CString::new(message.replace('\0', "\\0")).unwrap();
+        unsafe { (self.api.log)(self.api, level as u32, message.as_ptr()) };
+    }
+
+    /// The running game, or None if no game has been loaded yet.
+    pub fn loaded_game(&self) -> Option<(Game, Gba<'_>)> {
+        let (mut game, mut gba_state) = (0, std::ptr::null_mut());
+        if !unsafe { (self.api.loaded_game)(&mut game, &mut gba_state) } {
+            return None;
+        }
+        Some((Game::from_id(game)?, unsafe { Gba::new(self, gba_state) }))
+    }
+
+    /// Registers a raw callback for when a game is loaded. This may only be called from `chaudloader_mod_init`, and returns false otherwise.
+    ///
+    /// # Safety
+    /// `userdata` must be valid for however `callback` uses it, for the rest of the process.
+    pub unsafe fn register_on_game_load(
+        &self,
+        callback: ffi::OnGameLoadCallback,
+        userdata: *mut std::ffi::c_void,
+    ) -> bool {
+        unsafe { (self.api.register_on_game_load)(self.api, callback, userdata) }
+    }
+}
+
+static HOST: std::sync::OnceLock<Host> = std::sync::OnceLock::new();
+
+/// Returns the host API.
+///
+/// # Panics
+/// Panics if `chaudloader_mod_init` has not been called yet.
+pub fn host() -> &'static Host {
+    HOST.get()
+        .expect("chaudloader_mod_init has not been called yet")
+}
+
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        if let Some(host) = HOST.get() {
+            host.log(record.level(), &record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Logger = Logger;
+
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    pub unsafe fn init(api: *const ffi::Api) -> &'static Host {
+        let host = HOST.get_or_init(|| Host {
+            api: unsafe { &*api },
+        });
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+        host
+    }
+
+    /// Calls a game callback from its raw arguments.
+    pub unsafe fn call(game: u32, gba_state: *mut GbaState, f: fn(Game, Gba<'_>)) {
+        let Some(game) = Game::from_id(game) else {
+            log::warn!("unknown game {}", game);
+            return;
+        };
+        f(game, unsafe { Gba::new(host(), gba_state) });
+    }
+}
+
+/// Exports `chaudloader_mod_init` for a mod DLL, which sets up the host API and the `log` crate, then sets up each of:
+///
+/// - `init = f`: calls `f(&Host)`.
+/// - `on_game_load = f`: calls `f(Game, Gba)` whenever a game is loaded.
+#[macro_export]
+macro_rules! export_mod {
+    ($($kind:ident = $f:path),* $(,)?) => {
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn chaudloader_mod_init(api: *const $crate::ffi::Api) {
+            let host = unsafe { $crate::__private::init(api) };
+            $($crate::export_mod!(@init host, $kind, $f);)*
+        }
+    };
+
+    (@init $host:ident, init, $f:path) => {
+        $f($host);
+    };
+
+    (@init $host:ident, on_game_load, $f:path) => {{
+        // Named so it can't shadow `$f`.
+        unsafe extern "C" fn __chaudloader_on_game_load(
+            _: *mut std::ffi::c_void,
+            game: u32,
+            gba_state: *mut $crate::GbaState,
+        ) {
+            unsafe { $crate::__private::call(game, gba_state, $f) };
+        }
+        unsafe { $host.register_on_game_load(__chaudloader_on_game_load, std::ptr::null_mut()) };
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EWRAM_START: u32 = 0x02000000;
+    static EWRAM: std::sync::Mutex<[u8; 0x100]> = std::sync::Mutex::new([0; 0x100]);
+    static CALLBACK: std::sync::Mutex<Option<ffi::OnGameLoadCallback>> =
+        std::sync::Mutex::new(None);
+
+    unsafe extern "C" fn log(_: *const ffi::Api, _: u32, _: *const std::ffi::c_char) {}
+
+    fn ewram_range(address: u32, len: usize) -> Option<std::ops::Range<usize>> {
+        let start = address.checked_sub(EWRAM_START)? as usize;
+        (start + len <= 0x100).then_some(start..start + len)
+    }
+
+    unsafe extern "C" fn gba_read(
+        _: *mut GbaState,
+        address: u32,
+        buf: *mut u8,
+        len: usize,
+    ) -> bool {
+        let Some(range) = ewram_range(address, len) else {
+            return false;
+        };
+        unsafe { std::slice::from_raw_parts_mut(buf, len) }
+            .copy_from_slice(&EWRAM.lock().unwrap()[range]);
+        true
+    }
+
+    unsafe extern "C" fn gba_write(
+        _: *mut GbaState,
+        address: u32,
+        buf: *const u8,
+        len: usize,
+    ) -> bool {
+        let Some(range) = ewram_range(address, len) else {
+            return false;
+        };
+        EWRAM.lock().unwrap()[range]
+            .copy_from_slice(unsafe { std::slice::from_raw_parts(buf, len) });
+        true
+    }
+
+    unsafe extern "C" fn loaded_game(_: *mut u32, _: *mut *mut GbaState) -> bool {
+        false
+    }
+
+    unsafe extern "C" fn register_on_game_load(
+        _: *const ffi::Api,
+        callback: ffi::OnGameLoadCallback,
+        _: *mut std::ffi::c_void,
+    ) -> bool {
+        *CALLBACK.lock().unwrap() = Some(callback);
+        true
+    }
+
+    fn init(host: &Host) {
+        assert_eq!(host.mod_name(), "test");
+        assert_eq!(host.mod_path(), std::path::Path::new("mods/test"));
+        assert_eq!(host.game_volume(), Some(GameVolume::Vol2));
+        assert!(host.loaded_game().is_none());
+    }
+
+    fn on_game_load(game: Game, gba: Gba) {
+        assert_eq!(game, Game::Bn4RedSun);
+        gba.write_u32(0x02000010, 0x12345678).unwrap();
+        assert_eq!(gba.read_u16(0x02000012), Ok(0x1234));
+        assert_eq!(
+            gba.write_u32(0x020000fe, 0),
+            Err(Error {
+                address: 0x020000fe,
+                len: 4
+            })
+        );
+    }
+
+    export_mod! {
+        init = init,
+        on_game_load = on_game_load,
+    }
+
+    #[test]
+    fn test_export_mod() {
+        let api = ffi::Api {
+            version: ffi::VERSION,
+            size: std::mem::size_of::<ffi::Api>() as u32,
+            mod_name: c"test".as_ptr(),
+            mod_path: c"mods/test".as_ptr(),
+            game_volume: 2,
+            exe_crc32: 0,
+            log,
+            gba_read,
+            gba_write,
+            loaded_game,
+            register_on_game_load,
+        };
+        unsafe { chaudloader_mod_init(Box::leak(Box::new(api))) };
+
+        let callback = CALLBACK.lock().unwrap().unwrap();
+        unsafe { callback(std::ptr::null_mut(), 5, std::ptr::null_mut()) };
+        assert_eq!(EWRAM.lock().unwrap()[0x10..0x14], [0x78, 0x56, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_gba_state_layout() {
+        assert_eq!(std::mem::offset_of!(GbaState, memory), 72);
+    }
+}
@@ -0,0 +1,26 @@
+pub use mlua;
+
+/// Exports a `luaopen_*` function for a Lua DLL loaded with `require`, calling `f(&Lua)` and returning its result to Lua.
+///
+/// The function must be named after the DLL, e.g. `luaopen_foo` for `require("foo.dll")`. See `require` in API.md.
+///
+/// ```ignore
+/// fn open(lua: &mlua::Lua) -> mlua::Result<mlua::Table<'_>> {
+///     let exports = lua.create_table()?;
+///     exports.set("answer", 42)?;
+///     Ok(exports)
+/// }
+///
+/// chaudloader_sdk::lua_module!(luaopen_foo, open);
+/// ```
+#[macro_export]
+macro_rules! lua_module {
+    ($name:ident, $f:path) => {
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C-unwind" fn $name(
+            state: *mut $crate::lua::mlua::lua_State,
+        ) -> std::ffi::c_int {
+            unsafe { $crate::lua::mlua::Lua::init_from_ptr(state).entrypoint1(state, $f) }
+        }
+    };
+}
@@ -0,0 +1,95 @@
+/*
+ * Host API for chaudloader DLL mods. See the "DLL mod functions" section of API.md.
+ *
+ * Export this from your DLL to receive the API:
+ *
+ *     __declspec(dllexport) void chaudloader_mod_init(const ChaudloaderApi* api);
+ *
+ * The API, and every string in it, stays valid for the lifetime of the process.
+ */
+#ifndef CHAUDLOADER_H
+#define CHAUDLOADER_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Fields are only ever appended: check `version` before using fields added after version 1. */
+#define CHAUDLOADER_API_VERSION 1
+
+typedef enum ChaudloaderLogLevel {
+    CHAUDLOADER_LOG_ERROR = 1,
+    CHAUDLOADER_LOG_WARN = 2,
+    CHAUDLOADER_LOG_INFO = 3,
+    CHAUDLOADER_LOG_DEBUG = 4,
+    CHAUDLOADER_LOG_TRACE = 5,
+} ChaudloaderLogLevel;
+
+typedef enum ChaudloaderGameVolume {
+    CHAUDLOADER_VOL1 = 1,
+    CHAUDLOADER_VOL2 = 2,
+} ChaudloaderGameVolume;
+
+typedef enum ChaudloaderGame {
+    CHAUDLOADER_BN1 = 0,
+    CHAUDLOADER_BN2 = 2,
+    CHAUDLOADER_BN3_WHITE = 3,
+    CHAUDLOADER_BN3_BLUE = 4,
+    CHAUDLOADER_BN4_RED_SUN = 5,
+    CHAUDLOADER_BN4_BLUE_MOON = 6,
+    CHAUDLOADER_BN5_PROTOMAN = 7,
+    CHAUDLOADER_BN5_COLONEL = 8,
+    CHAUDLOADER_BN6_GREGAR = 9,
+    CHAUDLOADER_BN6_FALZAR = 10,
+} ChaudloaderGame;
+
+/* The emulator's GBA state. Only the start of the struct is known. */
+typedef struct GBAState {
+    uint32_t r[16];
+    uint32_t cpuFlags;
+    uint32_t flagsImplicitUpdate;
+    uint8_t* memory;
+} GBAState;
+
+typedef void (*ChaudloaderOnGameLoad)(void* userdata, uint32_t game, GBAState* gba_state);
+
+typedef struct ChaudloaderApi ChaudloaderApi;
+
+struct ChaudloaderApi {
+    /* CHAUDLOADER_API_VERSION of the running chaudloader. */
+    uint32_t version;
+    /* sizeof(ChaudloaderApi) of the running chaudloader. */
+    uint32_t size;
+
+    /* The mod's name, as in the mods folder (UTF-8). */
+    const char* mod_name;
+    /* The mod's folder, relative to the game's exe folder (UTF-8), e.g. "mods\\my_mod". */
+    const char* mod_path;
+    /* A ChaudloaderGameVolume. */
+    uint32_t game_volume;
+    /* CRC32 of the game executable. */
+    uint32_t exe_crc32;
+
+    /* Logs a UTF-8 message to the chaudloader console and log, attributed to the mod. */
+    void (*log)(const ChaudloaderApi* api, uint32_t level, const char* message);
+
+    /* Bounds-checked GBA memory access, as chaudloader_gba_read and chaudloader_gba_write. */
+    bool (*gba_read)(GBAState* gba_state, uint32_t address, uint8_t* buf, size_t len);
+    bool (*gba_write)(GBAState* gba_state, uint32_t address, const uint8_t* buf, size_t len);
+
+    /* Gets the running game and its GBA state. Returns false if no game has been loaded yet. */
+    bool (*loaded_game)(uint32_t* game, GBAState** gba_state);
+
+    /* Registers a callback for when a game is loaded. Only valid from chaudloader_mod_init; returns false otherwise. */
+    bool (*register_on_game_load)(const ChaudloaderApi* api, ChaudloaderOnGameLoad callback, void* userdata);
+};
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* CHAUDLOADER_H */
@@ -0,0 +1,166 @@
+use crate::{gba, mods};
+use chaudloader_sdk::{GbaState, ffi};
+
+pub use chaudloader_sdk::ffi::{Api, OnGameLoadCallback};
+
+/// Per-mod state behind an `Api`, which is always its first field.
+#[repr(C)]
+struct Context {
+    api: Api,
+    name: String,
+    _mod_name: std::ffi::CString,
+    _mod_path: std::ffi::CString,
+}
+
+/// Callbacks can only be registered while mods are being initialized, since hooks are installed right after.
+static INITIALIZING: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_initializing(initializing: bool) {
+    INITIALIZING.store(initializing, std::sync::atomic::Ordering::SeqCst);
+}
+
+unsafe fn context<'a>(api: *const Api) -> &'a Context {
+    unsafe { &*(api as *const Context) }
+}
+
+unsafe extern "C" fn log(api: *const Api, level: u32, message: *const std::ffi::c_char) {
+    let (context, message) = unsafe { (context(api), std::ffi::CStr::from_ptr(message)) };
+    let level = match level {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        4 => log::Level::Debug,
+        5 => log::Level::Trace,
+        _ => log::Level::Info,
+    };
+    log::log!(
+        level,
+        "[mod: {}] {}",
+        context.name,
+        message.to_string_lossy()
+    );
+}
+
+unsafe extern "C" fn gba_read(
+    gba_state: *mut GbaState,
+    address: u32,
+    buf: *mut u8,
+    len: usize,
+) -> bool {
+    unsafe { gba::chaudloader_gba_read(gba_state.cast(), address, buf, len) }
+}
+
+unsafe extern "C" fn gba_write(
+    gba_state: *mut GbaState,
+    address: u32,
+    buf: *const u8,
+    len: usize,
+) -> bool {
+    unsafe { gba::chaudloader_gba_write(gba_state.cast(), address, buf, len) }
+}
+
+unsafe extern "C" fn loaded_game(game: *mut u32, gba_state: *mut *mut GbaState) -> bool {
+    let Some((loaded_game, loaded_gba_state)) = gba::loaded_state() else {
+        return false;
+    };
+    unsafe {
+        *game = loaded_game;
+        *gba_state = loaded_gba_state.cast();
+    }
+    true
+}
+
+unsafe extern "C" fn register_on_game_load(
+    api: *const Api,
+    callback: OnGameLoadCallback,
+    userdata: *mut std::ffi::c_void,
+) -> bool {
+    let context = unsafe { context(api) };
+    if !INITIALIZING.load(std::sync::atomic::Ordering::SeqCst) {
+        log::error!(
+            "[mod: {}] on_game_load callbacks can only be registered from chaudloader_mod_init",
+            context.name
+        );
+        return false;
+    }
+    mods::MODFUNCTIONS
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .on_game_load_callbacks
+        .push((callback, userdata.expose_provenance()));
+    true
+}
+
+/// Creates the API for a mod. It lives for the rest of the process, as the mod's DLLs do.
+pub fn new(name: &str, game_env: &mods::GameEnv) -> &'static Api {
+    let mod_name = std::ffi::CString::new(name).unwrap();
+    let mod_path = std::ffi::CString::new(
+        std::path::Path::new("mods")
+            .join(name)
+            .to_string_lossy()
+            .into_owned(),
+    )
+    .unwrap();
+    let context = Box::leak(Box::new(Context {
+        api: Api {
+            version: ffi::VERSION,
+            size: std::mem::size_of::<Api>() as u32,
+            mod_name: mod_name.as_ptr(),
+            mod_path: mod_path.as_ptr(),
+            game_volume: match game_env.volume {
+                crate::GameVolume::Vol1 => 1,
+                crate::GameVolume::Vol2 => 2,
+            },
+            exe_crc32: game_env.exe_crc32,
+            log,
+            gba_read,
+            gba_write,
+            loaded_game,
+            register_on_game_load,
+        },
+        name: name.to_string(),
+        _mod_name: mod_name,
+        _mod_path: mod_path,
+    }));
+    &context.api
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() {
+        let api = new(
+            "test",
+            &mods::GameEnv {
+                volume: crate::GameVolume::Vol2,
+                exe_crc32: 0x1a2b3c4d,
+                sections: Default::default(),
+                image: Default::default(),
+            },
+        );
+        assert_eq!(api.version, ffi::VERSION);
+        assert_eq!(api.game_volume, 2);
+        assert_eq!(api.exe_crc32, 0x1a2b3c4d);
+        unsafe {
+            assert_eq!(std::ffi::CStr::from_ptr(api.mod_name), c"test");
+            assert_eq!(
+                std::ffi::CStr::from_ptr(api.mod_path).to_str().unwrap(),
+                std::path::Path::new("mods").join("test").to_str().unwrap()
+            );
+            (api.log)(api, 3, c"hello".as_ptr());
+
+            unsafe extern "C" fn callback(_: *mut std::ffi::c_void, _: u32, _: *mut GbaState) {}
+            assert!(!(api.register_on_game_load)(
+                api,
+                callback,
+                std::ptr::null_mut()
+            ));
+
+            let (mut game, mut gba_state) = (0, std::ptr::null_mut());
+            assert!(!(api.loaded_game)(&mut game, &mut gba_state));
+        }
+    }
+}
@@ -1,5 +1,5 @@
 pub mod exedat;
-pub mod mpak;
+pub use ::mpak;
 pub mod msg;
 
 pub trait ReadSeek: std::io::Read + std::io::Seek {}
@@ -8,17 +8,135 @@ impl<T: std::io::Read + std::io::Seek> ReadSeek for T {}
 pub trait WriteSeek: std::io::Write + std::io::Seek {}
 impl<T: std::io::Write + std::io::Seek> WriteSeek for T {}
 
+/// Splits a Windows path into lowercased components, resolving `.` and `..` along the way.
+///
+/// Both `/` and `\` are accepted as separators. Returns `None` if `..` would go above the start of the path.
+fn split_path_components(path: &str) -> Option<Vec<String>> {
+    let mut components = vec![];
+    for component in path.split(['\\', '/']) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component.to_lowercase()),
+        }
+    }
+    Some(components)
+}
+
+/// Normalizes a path, as passed to `CreateFile`, into a key relative to the game root.
+///
+/// Paths are matched case-insensitively and without regard to the separator used. Relative paths are resolved against the game root. Returns `None` if the path does not point inside the game root.
+fn normalize_path(root: &[String], path: &str) -> Option<String> {
+    let path = path.strip_prefix("\\\\?\\").unwrap_or(path);
+
+    let is_drive_absolute = path
+        .as_bytes()
+        .get(..2)
+        .map(|prefix| prefix[0].is_ascii_alphabetic() && prefix[1] == b':')
+        .unwrap_or(false);
+
+    let components = if is_drive_absolute {
+        split_path_components(path)?
+    } else if path.starts_with(['\\', '/']) {
+        // Relative to the root of the current drive.
+        let mut components = root.first()?.clone();
+        components.push('\\');
+        components.push_str(path);
+        split_path_components(&components)?
+    } else {
+        let mut components = root.join("\\");
+        components.push('\\');
+        components.push_str(path);
+        split_path_components(&components)?
+    };
+
+    let rest = components.strip_prefix(root)?;
+    if rest.is_empty() {
+        return None;
+    }
+    Some(rest.join("\\"))
+}
+
+/// A replacement that is being generated in the background.
+#[derive(Clone)]
+pub struct Pending(
+    std::sync::Arc<(
+        std::sync::Mutex<Option<Result<std::path::PathBuf, std::io::Error>>>,
+        std::sync::Condvar,
+    )>,
+);
+
+impl Pending {
+    /// Creates `dest_path` on the calling thread, then fills it in with `pack_cb` on a background thread.
+    ///
+    /// The file is created here rather than in the background so the worker never calls `CreateFile` while the loader is installing or toggling its hooks on it.
+    fn spawn(
+        dest_path: std::path::PathBuf,
+        pack_cb: impl FnOnce(&mut dyn WriteSeek) -> Result<(), std::io::Error> + Send + 'static,
+    ) -> Self {
+        let pending = Self(std::sync::Arc::new((
+            std::sync::Mutex::new(None),
+            std::sync::Condvar::new(),
+        )));
+        let mut dest_f = match std::fs::File::create(&dest_path) {
+            Ok(dest_f) => dest_f,
+            Err(e) => {
+                *pending.0.0.lock().unwrap() = Some(Err(e));
+                return pending;
+            }
+        };
+        std::thread::spawn({
+            let pending = pending.clone();
+            move || {
+                let result = pack_cb(&mut dest_f).map(|_| dest_path);
+                let (result_slot, cvar) = &*pending.0;
+                *result_slot.lock().unwrap() = Some(result);
+                cvar.notify_all();
+            }
+        });
+        pending
+    }
+
+    /// Blocks until the replacement has been generated.
+    pub fn wait(&self) {
+        let (result_slot, cvar) = &*self.0;
+        let _result = cvar
+            .wait_while(result_slot.lock().unwrap(), |result| result.is_none())
+            .unwrap();
+    }
+
+    /// Blocks until the replacement has been generated, and returns its path. A failure is kept, so it is returned every time.
+    fn wait_result(&self) -> Result<std::path::PathBuf, std::io::Error> {
+        let (result_slot, cvar) = &*self.0;
+        match cvar
+            .wait_while(result_slot.lock().unwrap(), |result| result.is_none())
+            .unwrap()
+            .as_ref()
+            .unwrap()
+        {
+            Ok(dest_path) => Ok(dest_path.clone()),
+            Err(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+        }
+    }
+}
+
 pub struct Replacer {
     temp_dir: std::path::PathBuf,
+    root: Vec<String>,
     replacers: std::collections::HashMap<
-        std::path::PathBuf,
+        String,
         Box<dyn Fn(&mut dyn WriteSeek) -> Result<(), std::io::Error> + Send>,
     >,
-    replacement_paths: std::collections::HashMap<std::path::PathBuf, std::path::PathBuf>,
+    replacement_paths: std::collections::HashMap<String, std::path::PathBuf>,
+    pending: std::collections::HashMap<String, Pending>,
+    owners: std::collections::HashMap<String, String>,
 }
 
 impl Replacer {
-    pub fn new(game_name: &str) -> Result<Self, std::io::Error> {
+    /// Creates a new replacer. All paths are keyed relative to `game_root`, which should be the directory the game is running from.
+    pub fn new(game_name: &str, game_root: &std::path::Path) -> Result<Self, std::io::Error> {
         let temp_dir = std::env::temp_dir().join("chaudloader").join(game_name);
 
         // Wipe existing temp directory, if possible.
@@ -38,54 +156,136 @@ impl Replacer {
             }
         };
 
+        let game_root = game_root.to_string_lossy();
+        let root = split_path_components(game_root.strip_prefix("\\\\?\\").unwrap_or(&game_root))
+            .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("cannot use {} as game root", game_root),
+            )
+        })?;
+
         Ok(Self {
             temp_dir,
+            root,
             replacers: std::collections::HashMap::new(),
             replacement_paths: std::collections::HashMap::new(),
+            pending: std::collections::HashMap::new(),
+            owners: std::collections::HashMap::new(),
         })
     }
 
+    fn key(&self, path: &std::path::Path) -> Result<String, std::io::Error> {
+        normalize_path(&self.root, &path.to_string_lossy()).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} is not inside the game directory", path.display()),
+            )
+        })
+    }
+
+    fn temp_path(temp_dir: &std::path::Path, key: &str) -> std::path::PathBuf {
+        temp_dir.join(std::path::Path::new(
+            &key.replace("_", "__").replace("\\", "_SLASH_"),
+        ))
+    }
+
+    fn set_owner(&mut self, key: &str, owner: &str) {
+        if let Some(previous_owner) = self.owners.insert(key.to_string(), owner.to_string()) {
+            log::warn!(
+                "{} was already replaced by {}, replacing again with {}",
+                key,
+                previous_owner,
+                owner
+            );
+        }
+    }
+
+    /// Registers a callback that generates the replacement for `path`. Any spelling of a path inside the game directory is accepted.
     pub fn add(
         &mut self,
+        owner: &str,
         path: &std::path::Path,
         pack_cb: impl Fn(&mut dyn WriteSeek) -> Result<(), std::io::Error> + Send + 'static,
-    ) {
-        self.replacers.insert(path.to_path_buf(), Box::new(pack_cb));
+    ) -> Result<(), std::io::Error> {
+        let key = self.key(path)?;
+        self.set_owner(&key, owner);
+        self.replacement_paths.remove(&key);
+        self.pending.remove(&key);
+        self.replacers.insert(key, Box::new(pack_cb));
+        Ok(())
     }
 
-    pub fn add_path(&mut self, path: &std::path::Path, dest_path: &std::path::Path) {
-        self.replacement_paths
-            .insert(path.to_path_buf(), dest_path.to_path_buf());
+    /// Registers a file that replaces `path`. Any spelling of a path inside the game directory is accepted.
+    pub fn add_path(
+        &mut self,
+        owner: &str,
+        path: &std::path::Path,
+        dest_path: &std::path::Path,
+    ) -> Result<(), std::io::Error> {
+        let key = self.key(path)?;
+        self.set_owner(&key, owner);
+        self.replacers.remove(&key);
+        self.pending.remove(&key);
+        self.replacement_paths.insert(key, dest_path.to_path_buf());
+        Ok(())
+    }
+
+    /// Like `add`, but starts generating the replacement on a background thread immediately instead of waiting for the game to open the file.
+    pub fn add_eager(
+        &mut self,
+        owner: &str,
+        path: &std::path::Path,
+        pack_cb: impl FnOnce(&mut dyn WriteSeek) -> Result<(), std::io::Error> + Send + 'static,
+    ) -> Result<(), std::io::Error> {
+        let key = self.key(path)?;
+        self.set_owner(&key, owner);
+        self.replacers.remove(&key);
+        self.replacement_paths.remove(&key);
+        let dest_path = Self::temp_path(&self.temp_dir, &key);
+        log::info!("replacing {} -> {}", key, dest_path.display());
+        self.pending.insert(key, Pending::spawn(dest_path, pack_cb));
+        Ok(())
+    }
+
+    /// Gets the background generation of the replacement for `path`, if it is still in progress.
+    ///
+    /// Callers should wait on this without holding the lock on the replacer, so other files can still be opened in the meantime.
+    pub fn pending(&self, path: &std::path::Path) -> Option<Pending> {
+        let key = normalize_path(&self.root, &path.to_string_lossy())?;
+        self.pending.get(&key).cloned()
     }
 
     pub fn get<'a>(
         &'a mut self,
         path: &std::path::Path,
     ) -> Result<Option<&'a std::path::Path>, std::io::Error> {
-        Ok(match self.replacement_paths.entry(path.to_path_buf()) {
+        let key = if let Some(key) = normalize_path(&self.root, &path.to_string_lossy()) {
+            key
+        } else {
+            return Ok(None);
+        };
+
+        // A failed replacement stays pending, so every open of the path fails instead of falling back to the original file.
+        if let Some(pending) = self.pending.get(&key) {
+            let dest_path = pending.wait_result()?;
+            self.pending.remove(&key);
+            self.replacement_paths.insert(key.clone(), dest_path);
+        }
+
+        Ok(match self.replacement_paths.entry(key) {
             std::collections::hash_map::Entry::Occupied(entry) => Some(entry.into_mut().as_path()),
             std::collections::hash_map::Entry::Vacant(entry) => {
-                let replacer = if let Some(replacer) = self.replacers.get(path) {
+                let replacer = if let Some(replacer) = self.replacers.get(entry.key()) {
                     replacer
                 } else {
                     return Ok(None);
                 };
 
-                let dest_path = self.temp_dir.join(std::path::Path::new(
-                    &path
-                        .as_os_str()
-                        .to_string_lossy()
-                        .replace("_", "__")
-                        .replace("../", "_DOTDOTSLASH_")
-                        .replace("./", "_DOTSLASH_")
-                        .replace("/", "_SLASH_")
-                        .replace("..\\", "_DOTDOTSLASH_")
-                        .replace(".\\", "_DOTSLASH_")
-                        .replace("\\", "_SLASH_"),
-                ));
+                let dest_path = Self::temp_path(&self.temp_dir, entry.key());
                 {
                     let mut dest_f = std::fs::File::create(&dest_path)?;
-                    log::info!("replacing {} -> {}", path.display(), dest_path.display());
+                    log::info!("replacing {} -> {}", entry.key(), dest_path.display());
                     replacer(&mut dest_f)?;
                 }
                 Some(entry.insert(dest_path).as_path())
@@ -95,3 +295,87 @@ impl Replacer {
 }
 
 pub static REPLACER: std::sync::OnceLock<std::sync::Mutex<Replacer>> = std::sync::OnceLock::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> Vec<String> {
+        split_path_components(r"C:\Program Files (x86)\Steam\steamapps\common\MegaMan_BattleNetwork_LegacyCollection_Vol2\exe").unwrap()
+    }
+
+    #[test]
+    fn test_normalize_path_game_spellings() {
+        let root = root();
+        for path in [
+            r"..\exe\data\exe6.dat",
+            r"data\exe6.dat",
+            r".\data\exe6.dat",
+            r"data/exe6.dat",
+            r"..\exe\data\..\data\exe6.dat",
+            r"DATA\EXE6.DAT",
+            r"C:\Program Files (x86)\Steam\steamapps\common\MegaMan_BattleNetwork_LegacyCollection_Vol2\exe\data\exe6.dat",
+            r"c:/program files (x86)/steam/steamapps/common/megaman_battlenetwork_legacycollection_vol2/exe/data/exe6.dat",
+            r"\\?\C:\Program Files (x86)\Steam\steamapps\common\MegaMan_BattleNetwork_LegacyCollection_Vol2\exe\data\exe6.dat",
+            r"\Program Files (x86)\Steam\steamapps\common\MegaMan_BattleNetwork_LegacyCollection_Vol2\exe\data\exe6.dat",
+        ] {
+            assert_eq!(
+                normalize_path(&root, path).as_deref(),
+                Some(r"data\exe6.dat"),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            normalize_path(&root, r"..\exe\audio\Vol2.pck").as_deref(),
+            Some(r"audio\vol2.pck")
+        );
+    }
+
+    #[test]
+    fn test_normalize_path_outside_root() {
+        let root = root();
+        for path in [
+            r"..\launcher\data\exe6.dat",
+            r"..\..\..\..\..\..\..\..\..\data\exe6.dat",
+            r"D:\Program Files (x86)\Steam\steamapps\common\MegaMan_BattleNetwork_LegacyCollection_Vol2\exe\data\exe6.dat",
+            r"C:\Windows\System32\kernel32.dll",
+            r"..\exe",
+            r".",
+        ] {
+            assert_eq!(normalize_path(&root, path), None, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_replacer_add_eager() {
+        let root = std::env::temp_dir().join("chaudloader_test_root");
+        let mut replacer = Replacer::new("test_replacer_add_eager", &root).unwrap();
+        replacer
+            .add_eager("test", std::path::Path::new("data/exe6.dat"), |w| {
+                w.write_all(b"hello")
+            })
+            .unwrap();
+        let path = std::path::Path::new(r"..\chaudloader_test_root\data\exe6.dat");
+        replacer.pending(path).unwrap().wait();
+        let new_path = replacer.get(path).unwrap().unwrap().to_path_buf();
+        assert_eq!(std::fs::read(new_path).unwrap(), b"hello");
+        assert!(replacer.pending(path).is_none());
+    }
+
+    #[test]
+    fn test_replacer_add_eager_error() {
+        let root = std::env::temp_dir().join("chaudloader_test_root");
+        let mut replacer = Replacer::new("test_replacer_add_eager_error", &root).unwrap();
+        replacer
+            .add_eager("test", std::path::Path::new("data/exe6.dat"), |_| {
+                Err(std::io::Error::other("oops"))
+            })
+            .unwrap();
+        let path = std::path::Path::new(r"..\chaudloader_test_root\data\exe6.dat");
+        for _ in 0..2 {
+            let e = replacer.get(path).unwrap_err();
+            assert_eq!(e.to_string(), "oops");
+        }
+    }
+}
@@ -21,54 +21,122 @@ impl Reader {
     }
 }
 
+/// Normalizes a path inside a .dat archive for lookup: archives mix `/` and `\\` separators, and the game doesn't care about case.
+fn normalize_name(path: &str) -> String {
+    path.replace("\\", "/").to_lowercase()
+}
+
 pub struct Overlay {
     base: Reader,
+    names: std::collections::HashMap<String, String>,
+    cache: std::collections::HashMap<String, Vec<u8>>,
+    mpaks: std::collections::HashMap<(String, String), super::mpak::Mpak>,
     overlaid_files: std::collections::HashMap<String, Vec<u8>>,
 }
 
 impl Overlay {
     pub fn new(base: Reader) -> Self {
+        let names = base
+            .zr
+            .file_names()
+            .map(|name| (normalize_name(name), name.to_string()))
+            .collect();
         Self {
             base,
+            names,
+            cache: std::collections::HashMap::new(),
+            mpaks: std::collections::HashMap::new(),
             overlaid_files: std::collections::HashMap::new(),
         }
     }
-    fn correct_path(&mut self, path: &str) -> String {
-        let filecheck = self.base.get(path);
-
-        match filecheck {
-            Ok(_) => path.into(),
-            Err(_) => {
-                if path.contains("/") {
-                    return path.replace("/", "\\");
-                }
 
-                path.replace("\\", "/")
-            }
-        }
+    /// Resolves a path to the name of the entry as it is spelled in the archive.
+    fn resolve_name(&self, path: &str) -> Result<String, std::io::Error> {
+        self.names
+            .get(&normalize_name(path))
+            .cloned()
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("no such file in archive: {}", path),
+                )
+            })
     }
 
     pub fn read(&mut self, path: &str) -> Result<std::borrow::Cow<'_, [u8]>, std::io::Error> {
-        let correctpath = self.correct_path(path);
+        let name = self.resolve_name(path)?;
 
-        if let Some(contents) = self.overlaid_files.get(correctpath.as_str()) {
+        if let Some(contents) = self.overlaid_files.get(&name) {
             return Ok(std::borrow::Cow::Borrowed(contents));
         }
-        let mut zf = self.base.get(correctpath.as_str())?;
-        let mut buf = vec![];
-        zf.read_to_end(&mut buf)?;
-        Ok(std::borrow::Cow::Owned(buf))
+
+        // Decompressed entries are cached, since many mods tend to read the same (large) files.
+        Ok(std::borrow::Cow::Borrowed(match self.cache.entry(name) {
+            std::collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            std::collections::hash_map::Entry::Vacant(entry) => {
+                let mut zf = self.base.get(entry.key())?;
+                let mut buf = vec![];
+                zf.read_to_end(&mut buf)?;
+                entry.insert(buf)
+            }
+        }))
     }
 
     pub fn write(&mut self, path: &str, contents: Vec<u8>) -> Result<(), std::io::Error> {
-        let correctpath = self.correct_path(path);
-        if self.base.get(correctpath.as_str())?.is_dir() {
+        let name = self.resolve_name(path)?;
+        if name.ends_with('/') || name.ends_with('\\') {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 "cannot replace directory",
             ));
         }
-        self.overlaid_files.insert(correctpath, contents);
+        // The cached copy will never be read again, so don't keep it around.
+        self.cache.remove(&name);
+        self.mpaks
+            .retain(|(map_name, mpak_name), _| *map_name != name && *mpak_name != name);
+        self.overlaid_files.insert(name, contents);
+        Ok(())
+    }
+
+    /// Parses a .map + .mpak pair.
+    ///
+    /// Parsed pairs are cached until either file is written, since many mods tend to edit the same mpaks. Mpaks are cheap to
+    /// clone, so each caller gets its own copy.
+    pub fn read_mpak(
+        &mut self,
+        map_path: &str,
+        mpak_path: &str,
+    ) -> Result<super::mpak::Mpak, super::mpak::Error> {
+        let key = (self.resolve_name(map_path)?, self.resolve_name(mpak_path)?);
+        if let Some(mpak) = self.mpaks.get(&key) {
+            return Ok(mpak.clone());
+        }
+
+        let map_contents = self.read(map_path)?.into_owned();
+        let mpak = super::mpak::Mpak::read_from(
+            std::io::Cursor::new(map_contents),
+            std::io::Cursor::new(self.read(mpak_path)?),
+        )?;
+        self.mpaks.insert(key, mpak.clone());
+        Ok(mpak)
+    }
+
+    /// Packs an mpak into a .map + .mpak pair, keeping it cached for `read_mpak`.
+    pub fn write_mpak(
+        &mut self,
+        map_path: &str,
+        mpak_path: &str,
+        mpak: &super::mpak::Mpak,
+    ) -> Result<(), std::io::Error> {
+        let mut map_contents = vec![];
+        let mut mpak_contents = vec![];
+        mpak.write_into(&mut map_contents, &mut mpak_contents)?;
+        self.write(map_path, map_contents)?;
+        self.write(mpak_path, mpak_contents)?;
+        self.mpaks.insert(
+            (self.resolve_name(map_path)?, self.resolve_name(mpak_path)?),
+            mpak.clone(),
+        );
         Ok(())
     }
 
@@ -119,3 +187,109 @@ pub fn scan() -> Result<std::collections::HashMap<String, Overlay>, std::io::Err
     }
     Ok(overlays)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_overlay() -> Overlay {
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zw.start_file("msg\\Mess.bin", zip::write::FileOptions::default())
+            .unwrap();
+        zw.write_all(b"hello").unwrap();
+        zw.start_file("data/exe6.map", zip::write::FileOptions::default())
+            .unwrap();
+        zw.write_all(b"world").unwrap();
+        let buf = zw.finish().unwrap();
+        Overlay::new(Reader::new(std::io::Cursor::new(buf.into_inner())).unwrap())
+    }
+
+    #[test]
+    fn test_overlay_read_any_spelling() {
+        let mut overlay = make_overlay();
+        for path in ["msg\\Mess.bin", "msg/Mess.bin", "MSG/mess.BIN"] {
+            assert_eq!(&*overlay.read(path).unwrap(), b"hello");
+        }
+        for path in ["data/exe6.map", "data\\exe6.map", "Data\\EXE6.map"] {
+            assert_eq!(&*overlay.read(path).unwrap(), b"world");
+        }
+        assert_eq!(
+            overlay.read("nope.bin").unwrap_err().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn test_overlay_write_and_pack() {
+        let mut overlay = make_overlay();
+        assert_eq!(&*overlay.read("msg/mess.bin").unwrap(), b"hello");
+        overlay.write("MSG/MESS.BIN", b"goodbye".to_vec()).unwrap();
+        assert_eq!(&*overlay.read("msg\\Mess.bin").unwrap(), b"goodbye");
+
+        let mut buf = std::io::Cursor::new(vec![]);
+        overlay.pack_into(&mut buf).unwrap();
+        let mut repacked =
+            Overlay::new(Reader::new(std::io::Cursor::new(buf.into_inner())).unwrap());
+        assert_eq!(&*repacked.read("msg\\Mess.bin").unwrap(), b"goodbye");
+        assert_eq!(&*repacked.read("data/exe6.map").unwrap(), b"world");
+    }
+
+    #[test]
+    fn test_overlay_mpak_cache() {
+        let mut map = vec![];
+        for word in [1u32, 0x08000000, 0x08000000, 0x08000000, 0, 2] {
+            map.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut zw = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zw.start_file("rom.map", zip::write::FileOptions::default())
+            .unwrap();
+        zw.write_all(&map).unwrap();
+        zw.start_file("rom.mpak", zip::write::FileOptions::default())
+            .unwrap();
+        zw.write_all(b"ab").unwrap();
+        let buf = zw.finish().unwrap();
+        let mut overlay =
+            Overlay::new(Reader::new(std::io::Cursor::new(buf.into_inner())).unwrap());
+
+        let mut mpak = overlay.read_mpak("rom.map", "rom.mpak").unwrap();
+        assert_eq!(mpak.get(0x08000000), Some(&b"ab"[..]));
+        assert_eq!(
+            overlay
+                .read_mpak("ROM.map", "rom.MPAK")
+                .unwrap()
+                .get(0x08000000),
+            Some(&b"ab"[..])
+        );
+        assert_eq!(overlay.mpaks.len(), 1);
+
+        // Edits are only shared once they are written back.
+        mpak.insert(0x08000000, b"cd".to_vec());
+        assert_eq!(
+            overlay
+                .read_mpak("rom.map", "rom.mpak")
+                .unwrap()
+                .get(0x08000000),
+            Some(&b"ab"[..])
+        );
+        overlay.write_mpak("rom.map", "rom.mpak", &mpak).unwrap();
+        assert_eq!(
+            overlay
+                .read_mpak("rom.map", "rom.mpak")
+                .unwrap()
+                .get(0x08000000),
+            Some(&b"cd"[..])
+        );
+        assert_eq!(&*overlay.read("rom.mpak").unwrap(), b"cd");
+
+        // Writing either file directly drops the parsed pair.
+        overlay.write("rom.mpak", b"ef".to_vec()).unwrap();
+        assert!(overlay.mpaks.is_empty());
+        assert_eq!(
+            overlay
+                .read_mpak("rom.map", "rom.mpak")
+                .unwrap()
+                .get(0x08000000),
+            Some(&b"ef"[..])
+        );
+    }
+}
@@ -1,54 +1,94 @@
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, WriteBytesExt};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("offset table size {first_offset:#x} is invalid: it must be a non-zero multiple of 2")]
+    InvalidOffsetTable { first_offset: usize },
+
+    #[error("entry {index}: offset {offset:#x} went backwards (entry {} is at {previous:#x})", index - 1)]
+    OffsetWentBackwards {
+        index: usize,
+        offset: usize,
+        previous: usize,
+    },
+
+    #[error("entry {index}: offset {offset:#x} is past the end of the data ({len:#x} bytes)")]
+    OffsetOutOfBounds {
+        index: usize,
+        offset: usize,
+        len: usize,
+    },
+
+    #[error("entry {index}: offset {offset:#x} does not fit in 16 bits, the msg data is too large")]
+    OffsetTooLarge { index: usize, offset: usize },
+}
+
+pub fn unpack(mut r: impl std::io::Read) -> Result<Vec<Vec<u8>>, Error> {
+    let mut buf = vec![];
+    r.read_to_end(&mut buf)?;
 
-pub fn unpack(mut r: impl std::io::Read) -> Result<Vec<Vec<u8>>, std::io::Error> {
     // Read offsets table.
-    let first_offset = r.read_u16::<byteorder::LittleEndian>()? as usize;
+    let first_offset = byteorder::LittleEndian::read_u16(
+        buf.get(..std::mem::size_of::<u16>())
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::UnexpectedEof))?,
+    ) as usize;
+    if first_offset == 0 || first_offset % std::mem::size_of::<u16>() != 0 {
+        return Err(Error::InvalidOffsetTable { first_offset });
+    }
     let n = first_offset / std::mem::size_of::<u16>();
 
     let mut offsets = Vec::with_capacity(n);
-    offsets.push(first_offset);
-    for _ in 1..n {
-        offsets.push(r.read_u16::<byteorder::LittleEndian>()? as usize);
+    for index in 0..n {
+        let offset = byteorder::LittleEndian::read_u16(
+            buf.get(index * std::mem::size_of::<u16>()..(index + 1) * std::mem::size_of::<u16>())
+                .ok_or_else(|| Error::OffsetOutOfBounds {
+                    index: 0,
+                    offset: first_offset,
+                    len: buf.len(),
+                })?,
+        ) as usize;
+        if offset > buf.len() {
+            return Err(Error::OffsetOutOfBounds {
+                index,
+                offset,
+                len: buf.len(),
+            });
+        }
+        if let Some(previous) = offsets.last().copied() {
+            if offset < previous {
+                return Err(Error::OffsetWentBackwards {
+                    index,
+                    offset,
+                    previous,
+                });
+            }
+        }
+        offsets.push(offset);
     }
 
     // Read entries.
-    let mut entries = Vec::with_capacity(n);
-    for (i, len) in std::iter::zip(offsets.iter(), offsets[1..].iter())
-        .map(|(x, y)| y.checked_sub(*x).map(Some))
-        .chain(std::iter::once(Some(None)))
-        .enumerate()
-    {
-        let len = if let Some(len) = len {
-            len
-        } else {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("offset {} went backwards", i),
-            ));
-        };
-
-        entries.push(if let Some(len) = len {
-            let mut buf = vec![0; len];
-            r.read_exact(&mut buf)?;
-            buf
-        } else {
-            let mut buf = vec![];
-            r.read_to_end(&mut buf)?;
-            buf
-        });
-    }
-
-    Ok(entries)
+    Ok(offsets
+        .iter()
+        .zip(offsets[1..].iter().chain(std::iter::once(&buf.len())))
+        .map(|(start, end)| buf[*start..*end].to_vec())
+        .collect())
 }
 
-pub fn pack(entries: &[&[u8]], mut w: impl std::io::Write) -> Result<(), std::io::Error> {
+pub fn pack(entries: &[&[u8]], mut w: impl std::io::Write) -> Result<(), Error> {
     let mut offset = entries.len() * std::mem::size_of::<u16>();
 
     // Write offsets table.
-    for entry in entries.iter() {
-        w.write_u16::<byteorder::LittleEndian>(offset as u16)?;
+    let mut offsets = Vec::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        offsets.push(u16::try_from(offset).map_err(|_| Error::OffsetTooLarge { index, offset })?);
         offset += entry.len();
     }
+    for offset in offsets {
+        w.write_u16::<byteorder::LittleEndian>(offset)?;
+    }
 
     // Write entries.
     for entry in entries.iter() {
@@ -76,9 +116,41 @@ mod tests {
     #[test]
     fn test_pack_unpack_bad_offsets() {
         let buf = b"\x04\x00\x03\x00uhoh";
-        assert_eq!(
-            unpack(std::io::Cursor::new(&buf)).unwrap_err().kind(),
-            std::io::ErrorKind::InvalidData
-        );
+        assert!(matches!(
+            unpack(std::io::Cursor::new(&buf)).unwrap_err(),
+            Error::OffsetWentBackwards {
+                index: 1,
+                offset: 3,
+                previous: 4
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unpack_bad_offset_table() {
+        assert!(matches!(
+            unpack(std::io::Cursor::new(b"\x03\x00uhoh")).unwrap_err(),
+            Error::InvalidOffsetTable { first_offset: 3 }
+        ));
+        assert!(matches!(
+            unpack(std::io::Cursor::new(b"\x04\x00\x09\x00uhoh")).unwrap_err(),
+            Error::OffsetOutOfBounds {
+                index: 1,
+                offset: 9,
+                len: 8
+            }
+        ));
+    }
+
+    #[test]
+    fn test_pack_too_large() {
+        let big = vec![0u8; 0x10000];
+        assert!(matches!(
+            pack(&[&big, b"hello"], &mut vec![]).unwrap_err(),
+            Error::OffsetTooLarge {
+                index: 1,
+                offset: 0x10004
+            }
+        ));
     }
 }
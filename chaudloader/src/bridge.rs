@@ -0,0 +1,259 @@
+use crate::gba;
+
+/// Where the memory bridge gets the running game from.
+pub trait Target: Send + Sync {
+    fn volume(&self) -> crate::GameVolume;
+
+    /// The running game and its memory, or None if no game has been loaded yet.
+    fn loaded(&self) -> Option<(u32, gba::Memory)>;
+}
+
+/// The game running in this process, as last passed to `on_game_load`.
+pub struct Live {
+    volume: crate::GameVolume,
+}
+
+impl Target for Live {
+    fn volume(&self) -> crate::GameVolume {
+        self.volume
+    }
+
+    fn loaded(&self) -> Option<(u32, gba::Memory)> {
+        gba::loaded()
+    }
+}
+
+/// The most bytes a single READ may return.
+const MAX_READ_LEN: usize = 0x10000;
+
+fn parse_address(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("invalid address: {}", s))
+}
+
+fn parse_hex(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) {
+        return Err("hex data must have an even number of digits".to_string());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| format!("invalid hex data: {}", s))
+        })
+        .collect()
+}
+
+fn loaded_memory(target: &dyn Target) -> Result<gba::Memory, String> {
+    target
+        .loaded()
+        .map(|(_, memory)| memory)
+        .ok_or_else(|| "no game loaded".to_string())
+}
+
+fn handle_command(line: &str, target: &dyn Target) -> Result<String, String> {
+    let args = line.split_whitespace().collect::<Vec<_>>();
+    match args[..] {
+        ["GAME"] => {
+            let volume = serde_plain::to_string(&target.volume()).unwrap();
+            Ok(match target.loaded() {
+                Some((game, _)) => format!(
+                    "{} {} {}",
+                    volume,
+                    game,
+                    chaudloader_sdk::Game::from_id(game)
+                        .map(|game| serde_plain::to_string(&game).unwrap())
+                        .unwrap_or_else(|| "unknown".to_string())
+                ),
+                None => format!("{} none", volume),
+            })
+        }
+        ["READ", address, len] => {
+            let address = parse_address(address)?;
+            let len = len
+                .parse::<usize>()
+                .map_err(|_| format!("invalid length: {}", len))?;
+            if len > MAX_READ_LEN {
+                return Err(format!("length must be at most {}", MAX_READ_LEN));
+            }
+            let mut buf = vec![0u8; len];
+            loaded_memory(target)?
+                .read(address, &mut buf)
+                .map_err(|e| e.to_string())?;
+            Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        ["WRITE", address, data] => {
+            let address = parse_address(address)?;
+            let data = parse_hex(data)?;
+            loaded_memory(target)?
+                .write(address, &data)
+                .map_err(|e| e.to_string())?;
+            Ok(String::new())
+        }
+        _ => Err(format!("unknown command: {}", line)),
+    }
+}
+
+/// Handles one request line, returning the response line without its newline.
+pub fn handle(line: &str, target: &dyn Target) -> String {
+    match handle_command(line.trim(), target) {
+        Ok(response) if response.is_empty() => "OK".to_string(),
+        Ok(response) => format!("OK {}", response),
+        Err(e) => format!("ERR {}", e),
+    }
+}
+
+fn handle_connection(
+    stream: std::net::TcpStream,
+    target: &dyn Target,
+) -> Result<(), std::io::Error> {
+    use std::io::{BufRead, Write};
+
+    let mut writer = stream.try_clone()?;
+    let mut lines = std::io::BufReader::new(stream).lines();
+    let Some(first_line) = lines.next().transpose()? else {
+        return Ok(());
+    };
+    // A web page can POST to this port, with commands in the request body.
+    if crate::repl::is_http_request_line(first_line.trim()) {
+        log::warn!("memory bridge: closing connection that sent an HTTP request");
+        return Ok(());
+    }
+    writeln!(writer, "{}", handle(&first_line, target))?;
+    for line in lines {
+        writeln!(writer, "{}", handle(&line?, target))?;
+    }
+    Ok(())
+}
+
+/// Serves clients on `listener` in the background, each on its own thread.
+pub fn serve(listener: std::net::TcpListener, target: std::sync::Arc<dyn Target>) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("memory bridge: {}", e);
+                    continue;
+                }
+            };
+            std::thread::spawn({
+                let target = std::sync::Arc::clone(&target);
+                move || {
+                    if let Err(e) = handle_connection(stream, &*target) {
+                        log::warn!("memory bridge: connection closed: {}", e);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Starts the memory bridge for the game running in this process on `127.0.0.1:<port>`.
+pub fn start(port: u16, volume: crate::GameVolume) -> Result<(), std::io::Error> {
+    let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?;
+    log::info!("memory bridge listening on {}", listener.local_addr()?);
+    serve(listener, std::sync::Arc::new(Live { volume }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake {
+        gba_state: usize,
+        loaded: std::sync::atomic::AtomicBool,
+    }
+
+    impl Fake {
+        fn new() -> Self {
+            let ewram = Box::leak(vec![0u8; 0x40000].into_boxed_slice());
+            let gba_state = Box::leak(Box::new([0u8; 80]));
+            gba_state[72..].copy_from_slice(
+                &(ewram.as_mut_ptr().wrapping_sub(0x02000000) as usize).to_le_bytes(),
+            );
+            Self {
+                gba_state: gba_state.as_mut_ptr().expose_provenance(),
+                loaded: std::sync::atomic::AtomicBool::new(false),
+            }
+        }
+    }
+
+    impl Target for Fake {
+        fn volume(&self) -> crate::GameVolume {
+            crate::GameVolume::Vol2
+        }
+
+        fn loaded(&self) -> Option<(u32, gba::Memory)> {
+            if !self.loaded.load(std::sync::atomic::Ordering::SeqCst) {
+                return None;
+            }
+            Some((5, unsafe {
+                gba::Memory::from_state(std::ptr::with_exposed_provenance_mut(self.gba_state))
+            }))
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let fake = Fake::new();
+        assert_eq!(handle("GAME", &fake), "OK Vol2 none");
+        assert_eq!(handle("READ 2000000 4", &fake), "ERR no game loaded");
+
+        fake.loaded.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(handle("GAME", &fake), "OK Vol2 5 BN4_RedSun");
+        assert_eq!(handle("WRITE 0x02000010 deadbeef", &fake), "OK");
+        assert_eq!(handle("READ 0x0200000f 6\r\n", &fake), "OK 00deadbeef00");
+        assert_eq!(
+            handle("READ 0x0203ffff 2", &fake),
+            "ERR 2 byte(s) at 0x0203ffff are not in a mapped region"
+        );
+        assert_eq!(
+            handle("WRITE 0x02000000 abc", &fake),
+            "ERR hex data must have an even number of digits"
+        );
+        assert_eq!(handle("PEEK", &fake), "ERR unknown command: PEEK");
+    }
+
+    #[test]
+    fn test_serve() {
+        use std::io::{BufRead, Write};
+
+        let fake = Fake::new();
+        fake.loaded.store(true, std::sync::atomic::Ordering::SeqCst);
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, std::sync::Arc::new(fake));
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"WRITE 2000000 0102\nREAD 2000000 2\n")
+            .unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "OK");
+        assert_eq!(lines.next().unwrap().unwrap(), "OK 0102");
+    }
+
+    #[test]
+    fn test_serve_http() {
+        use std::io::{Read, Write};
+
+        let fake = std::sync::Arc::new(Fake::new());
+        fake.loaded.store(true, std::sync::atomic::Ordering::SeqCst);
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, fake.clone());
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\nWRITE 2000000 ff\n")
+            .unwrap();
+        // The connection is closed with the rest of the request unread, which may reset it instead.
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        assert_eq!(response, "");
+        assert_eq!(handle("READ 2000000 1", &*fake), "OK 00");
+    }
+}
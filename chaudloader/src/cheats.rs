@@ -0,0 +1,817 @@
+use crate::{assets, gba};
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    Codebreaker,
+    GamesharkV3,
+    ActionReplayV3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Width {
+    U8,
+    U16,
+    U32,
+}
+
+impl Width {
+    fn size(self) -> usize {
+        match self {
+            Width::U8 => 1,
+            Width::U16 => 2,
+            Width::U32 => 4,
+        }
+    }
+
+    fn mask(self) -> u32 {
+        match self {
+            Width::U8 => 0xff,
+            Width::U16 => 0xffff,
+            Width::U32 => 0xffffffff,
+        }
+    }
+
+    fn sign_extend(self, v: u32) -> i32 {
+        match self {
+            Width::U8 => v as u8 as i8 as i32,
+            Width::U16 => v as u16 as i16 as i32,
+            Width::U32 => v as i32,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    SignedLt,
+    SignedGt,
+    And,
+}
+
+impl Cond {
+    fn test(self, width: Width, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Cond::Eq => lhs == rhs,
+            Cond::Ne => lhs != rhs,
+            Cond::Lt => lhs < rhs,
+            Cond::Gt => lhs > rhs,
+            Cond::SignedLt => width.sign_extend(lhs) < width.sign_extend(rhs),
+            Cond::SignedGt => width.sign_extend(lhs) > width.sign_extend(rhs),
+            Cond::And => lhs & rhs != 0,
+        }
+    }
+}
+
+/// A RAM operation, applied to the running game by GBA bus address.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    /// Writes `value` to `count` consecutive values starting at `address`.
+    Write {
+        address: u32,
+        width: Width,
+        value: u32,
+        count: u32,
+    },
+    /// Writes `value` at `offset` from the address stored at `pointer`.
+    WriteIndirect {
+        pointer: u32,
+        offset: u32,
+        width: Width,
+        value: u32,
+    },
+    Or {
+        address: u32,
+        width: Width,
+        value: u32,
+    },
+    And {
+        address: u32,
+        width: Width,
+        value: u32,
+    },
+    Add {
+        address: u32,
+        width: Width,
+        value: u32,
+    },
+    /// Skips the next `skip` operations unless the condition holds.
+    If {
+        address: u32,
+        width: Width,
+        cond: Cond,
+        value: u32,
+        skip: usize,
+    },
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Code {
+    pub ops: Vec<Op>,
+
+    /// Unconditional writes to ROM, by GBA bus address.
+    pub rom_patches: Vec<(u32, Vec<u8>)>,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+#[error("line {line}: {message}")]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+fn error(line: usize, message: impl Into<String>) -> Error {
+    Error {
+        line,
+        message: message.into(),
+    }
+}
+
+const ROM_START: u32 = 0x08000000;
+const ROM_END: u32 = 0x0a000000;
+
+/// Seeds for GameShark v3 and Action Replay v3 codes, which share the same encryption.
+const PAR_V3_SEEDS: [u32; 4] = [0x7aa9648f, 0x7fae6994, 0xc0efaad5, 0x42712c57];
+
+const TEA_DELTA: u32 = 0x9e3779b9;
+
+fn decrypt(op1: &mut u32, op2: &mut u32, seeds: &[u32; 4]) {
+    let mut sum = TEA_DELTA.wrapping_mul(32);
+    for _ in 0..32 {
+        *op2 = op2.wrapping_sub(
+            (*op1 << 4).wrapping_add(seeds[2])
+                ^ op1.wrapping_add(sum)
+                ^ (*op1 >> 5).wrapping_add(seeds[3]),
+        );
+        *op1 = op1.wrapping_sub(
+            (*op2 << 4).wrapping_add(seeds[0])
+                ^ op2.wrapping_add(sum)
+                ^ (*op2 >> 5).wrapping_add(seeds[1]),
+        );
+        sum = sum.wrapping_sub(TEA_DELTA);
+    }
+}
+
+/// Splits a code into lines of hex words, skipping blank lines. Each line is returned with its line number.
+fn parse_lines(s: &str, widths: [usize; 2]) -> Result<Vec<(usize, u32, u32)>, Error> {
+    let mut lines = vec![];
+    for (i, line) in s.lines().enumerate() {
+        let line_num = i + 1;
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        if words.is_empty() {
+            continue;
+        }
+        if words.len() != 2
+            || words
+                .iter()
+                .zip(widths)
+                .any(|(word, width)| word.len() != width)
+        {
+            return Err(error(
+                line_num,
+                format!(
+                    "expected {} and {} hex digits, separated by a space",
+                    widths[0], widths[1]
+                ),
+            ));
+        }
+        let parse = |word: &str| {
+            u32::from_str_radix(word, 16)
+                .map_err(|_| error(line_num, format!("invalid hex: {}", word)))
+        };
+        lines.push((line_num, parse(words[0])?, parse(words[1])?));
+    }
+    Ok(lines)
+}
+
+fn parse_codebreaker(s: &str) -> Result<Vec<(usize, Op)>, Error> {
+    let mut ops = vec![];
+    for (line, op1, value) in parse_lines(s, [8, 4])? {
+        let address = op1 & 0x0fffffff;
+        let cond = |cond| Op::If {
+            address,
+            width: Width::U16,
+            cond,
+            value,
+            skip: 1,
+        };
+        ops.push((
+            line,
+            match op1 >> 28 {
+                // Master codes, which only matter on real hardware.
+                0x0 | 0x1 => continue,
+                0x2 => Op::Or {
+                    address,
+                    width: Width::U16,
+                    value,
+                },
+                0x3 => Op::Write {
+                    address,
+                    width: Width::U8,
+                    value: value & 0xff,
+                    count: 1,
+                },
+                0x6 => Op::And {
+                    address,
+                    width: Width::U16,
+                    value,
+                },
+                0x7 => cond(Cond::Eq),
+                0x8 => Op::Write {
+                    address,
+                    width: Width::U16,
+                    value,
+                    count: 1,
+                },
+                0x9 => return Err(error(line, "encrypted CodeBreaker codes are not supported")),
+                0xa => cond(Cond::Ne),
+                0xb => cond(Cond::Gt),
+                0xc => cond(Cond::Lt),
+                0xe => Op::Add {
+                    address,
+                    width: Width::U16,
+                    value,
+                },
+                0xf => cond(Cond::And),
+                kind => {
+                    return Err(error(
+                        line,
+                        format!("unsupported CodeBreaker code type {:X}", kind),
+                    ));
+                }
+            },
+        ));
+    }
+    Ok(ops)
+}
+
+/// Decodes the 24-bit address of an Action Replay v3 code, whose top 4 bits select the GBA memory region.
+fn par_v3_address(x: u32) -> u32 {
+    (x & 0xfffff) | ((x << 4) & 0x0f000000)
+}
+
+fn parse_par_v3(s: &str) -> Result<Vec<(usize, Op)>, Error> {
+    let mut lines = parse_lines(s, [8, 8])?;
+    for (_, op1, op2) in lines.iter_mut() {
+        decrypt(op1, op2, &PAR_V3_SEEDS);
+    }
+
+    let mut ops = vec![];
+    let mut lines = lines.into_iter();
+    while let Some((line, op1, op2)) = lines.next() {
+        if op1 == 0 {
+            match op2 >> 24 {
+                0x00 if op2 == 0 => {}
+                0x18 | 0x1a | 0x1c | 0x1e => {
+                    let (_, value, _) = lines
+                        .next()
+                        .ok_or_else(|| error(line, "ROM patch is missing its value line"))?;
+                    ops.push((
+                        line,
+                        Op::Write {
+                            address: ROM_START | ((op2 & 0xffffff) << 1),
+                            width: Width::U16,
+                            value: value & 0xffff,
+                            count: 1,
+                        },
+                    ));
+                }
+                _ => {
+                    return Err(error(line, format!("unsupported special code {:08X}", op2)));
+                }
+            }
+            continue;
+        }
+
+        let opcode = op1 >> 24;
+        let address = par_v3_address(op1 & 0xffffff);
+
+        // Writes to I/O registers use the address as an offset.
+        if opcode == 0xc6 || opcode == 0xc7 {
+            let width = if opcode == 0xc6 {
+                Width::U16
+            } else {
+                Width::U32
+            };
+            ops.push((
+                line,
+                Op::Write {
+                    address: 0x04000000 | (op1 & 0xffffff),
+                    width,
+                    value: op2 & width.mask(),
+                    count: 1,
+                },
+            ));
+            continue;
+        }
+
+        let width = match (opcode >> 1) & 0x3 {
+            0 => Width::U8,
+            1 => Width::U16,
+            2 => Width::U32,
+            _ => return Err(error(line, format!("unsupported code type {:02X}", opcode))),
+        };
+        let base = opcode >> 6;
+        let cond = match (opcode >> 3) & 0x7 {
+            0 => None,
+            1 => Some(Cond::Eq),
+            2 => Some(Cond::Ne),
+            3 => Some(Cond::SignedLt),
+            4 => Some(Cond::SignedGt),
+            5 => Some(Cond::Lt),
+            6 => Some(Cond::Gt),
+            _ => Some(Cond::And),
+        };
+
+        ops.push((
+            line,
+            match (cond, base) {
+                (Some(cond), 0 | 1) => Op::If {
+                    address,
+                    width,
+                    cond,
+                    value: op2 & width.mask(),
+                    skip: base as usize + 1,
+                },
+                (Some(_), _) => {
+                    return Err(error(
+                        line,
+                        "conditionals over blocks of codes are not supported",
+                    ));
+                }
+                (None, 0) => Op::Write {
+                    address,
+                    width,
+                    value: op2 & width.mask(),
+                    count: match width {
+                        Width::U8 => (op2 >> 8) + 1,
+                        Width::U16 => (op2 >> 16) + 1,
+                        Width::U32 => 1,
+                    },
+                },
+                (None, 1) => Op::WriteIndirect {
+                    pointer: address,
+                    offset: match width {
+                        Width::U8 => op2 >> 8,
+                        Width::U16 => (op2 >> 16) * 2,
+                        Width::U32 => 0,
+                    },
+                    width,
+                    value: op2 & width.mask(),
+                },
+                (None, 2) => Op::Add {
+                    address,
+                    width,
+                    value: op2 & width.mask(),
+                },
+                (None, _) => {
+                    return Err(error(line, format!("unsupported code type {:02X}", opcode)));
+                }
+            },
+        ));
+    }
+    Ok(ops)
+}
+
+fn write_bytes(width: Width, value: u32, count: u32) -> Vec<u8> {
+    let value = value.to_le_bytes();
+    value[..width.size()].repeat(count as usize)
+}
+
+/// Parses a cheat code, splitting off writes to ROM so they can be patched into the game's data.
+pub fn parse(format: Format, s: &str) -> Result<Code, Error> {
+    let ops = match format {
+        Format::Codebreaker => parse_codebreaker(s)?,
+        Format::GamesharkV3 | Format::ActionReplayV3 => parse_par_v3(s)?,
+    };
+
+    let mut code = Code::default();
+    // The number of following operations that are guarded by a condition.
+    let mut guarded: usize = 0;
+    for (line, op) in ops {
+        let is_guarded = guarded > 0;
+        guarded = match &op {
+            Op::If { skip, .. } => guarded.saturating_sub(1).max(*skip),
+            _ => guarded.saturating_sub(1),
+        };
+
+        match op {
+            Op::Write {
+                address,
+                width,
+                value,
+                count,
+            } if (ROM_START..ROM_END).contains(&address) => {
+                if is_guarded {
+                    return Err(error(line, "conditional ROM patches are not supported"));
+                }
+                if address as u64 + count as u64 * width.size() as u64 > ROM_END as u64 {
+                    return Err(error(line, "ROM patch goes past the end of ROM"));
+                }
+                code.rom_patches
+                    .push((address, write_bytes(width, value, count)));
+            }
+            Op::Write { width, count, .. } if count as u64 * width.size() as u64 > 0x40000 => {
+                return Err(error(line, "fill is larger than any RAM region"));
+            }
+            op => code.ops.push(op),
+        }
+    }
+    Ok(code)
+}
+
+fn read(memory: &gba::Memory, address: u32, width: Width) -> Result<u32, gba::Error> {
+    Ok(match width {
+        Width::U8 => memory.read_u8(address)? as u32,
+        Width::U16 => memory.read_u16(address)? as u32,
+        Width::U32 => memory.read_u32(address)?,
+    })
+}
+
+fn write(memory: &gba::Memory, address: u32, width: Width, value: u32) -> Result<(), gba::Error> {
+    memory.write(address, &write_bytes(width, value, 1))
+}
+
+pub fn apply(ops: &[Op], memory: &gba::Memory) -> Result<(), gba::Error> {
+    let mut i = 0;
+    while i < ops.len() {
+        match ops[i] {
+            Op::Write {
+                address,
+                width,
+                value,
+                count,
+            } => {
+                memory.write(address, &write_bytes(width, value, count))?;
+            }
+            Op::WriteIndirect {
+                pointer,
+                offset,
+                width,
+                value,
+            } => {
+                let address = memory.read_u32(pointer)?.wrapping_add(offset);
+                write(memory, address, width, value)?;
+            }
+            Op::Or {
+                address,
+                width,
+                value,
+            } => {
+                write(
+                    memory,
+                    address,
+                    width,
+                    read(memory, address, width)? | value,
+                )?;
+            }
+            Op::And {
+                address,
+                width,
+                value,
+            } => {
+                write(
+                    memory,
+                    address,
+                    width,
+                    read(memory, address, width)? & value,
+                )?;
+            }
+            Op::Add {
+                address,
+                width,
+                value,
+            } => {
+                write(
+                    memory,
+                    address,
+                    width,
+                    read(memory, address, width)?.wrapping_add(value),
+                )?;
+            }
+            Op::If {
+                address,
+                width,
+                cond,
+                value,
+                skip,
+            } => {
+                if !cond.test(width, read(memory, address, width)?, value) {
+                    i += skip;
+                }
+            }
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+/// The mpak in a .dat that holds the game's ROM, as passed to `chaudloader.util.edit_mpak`.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RomTarget {
+    pub dat: String,
+    pub name: String,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct Definition {
+    pub name: String,
+
+    #[serde(default)]
+    pub game: Option<chaudloader_sdk::Game>,
+
+    pub format: Format,
+
+    pub code: String,
+
+    /// Required for codes that write to ROM.
+    #[serde(default)]
+    pub rom: Option<RomTarget>,
+}
+
+#[derive(serde::Deserialize, Default)]
+struct File {
+    #[serde(default)]
+    cheats: Vec<Definition>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    /// The cheat's name, prefixed by `<mod name>/` for cheats from mods.
+    pub id: String,
+    pub definition: Definition,
+}
+
+const PROFILE_CHEATS_FILE_NAME: &str = "chaudloader_cheats.toml";
+
+const MOD_CHEATS_FILE_NAME: &str = "cheats.toml";
+
+fn read_file(path: &std::path::Path) -> Result<Vec<Definition>, anyhow::Error> {
+    match std::fs::read(path) {
+        Ok(b) => Ok(toml::from_slice::<File>(&b)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+            .cheats),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads the cheats in `chaudloader_cheats.toml` and in the `cheats.toml` of each of the given mods. Files that can't be read are logged and skipped.
+pub fn scan<'a>(mod_names: impl IntoIterator<Item = &'a str>) -> Vec<Cheat> {
+    let mut cheats = vec![];
+    let sources = std::iter::once((None, std::path::PathBuf::from(PROFILE_CHEATS_FILE_NAME)))
+        .chain(mod_names.into_iter().map(|mod_name| {
+            (
+                Some(mod_name),
+                std::path::Path::new("mods")
+                    .join(mod_name)
+                    .join(MOD_CHEATS_FILE_NAME),
+            )
+        }));
+    for (mod_name, path) in sources {
+        match read_file(&path) {
+            Ok(definitions) => {
+                cheats.extend(definitions.into_iter().map(|definition| Cheat {
+                    id: match mod_name {
+                        Some(mod_name) => format!("{}/{}", mod_name, definition.name),
+                        None => definition.name.clone(),
+                    },
+                    definition,
+                }));
+            }
+            Err(e) => log::error!("cannot read cheats: {}", e),
+        }
+    }
+    cheats
+}
+
+struct Active {
+    id: String,
+    game: Option<chaudloader_sdk::Game>,
+    ops: Vec<Op>,
+}
+
+static ACTIVE: std::sync::Mutex<Vec<Active>> = std::sync::Mutex::new(Vec::new());
+
+/// Patches the ROM writes of the given cheats into the game's data and prepares their RAM writes for `apply_loaded`. Returns whether any cheat needs the game load hook.
+pub fn init(
+    cheats: &[Cheat],
+    overlays: &std::collections::HashMap<
+        String,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
+    >,
+) -> bool {
+    let mut rom_patches =
+        std::collections::BTreeMap::<(String, String), Vec<(u32, Vec<u8>)>>::new();
+    let mut active = ACTIVE.lock().unwrap();
+
+    for cheat in cheats {
+        if let Err(e) = (|| -> Result<(), anyhow::Error> {
+            let code = parse(cheat.definition.format, &cheat.definition.code)?;
+            if !code.rom_patches.is_empty() {
+                let rom = cheat.definition.rom.as_ref().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "code writes to ROM, but the cheat has no rom = {{ dat, name }}"
+                    )
+                })?;
+                rom_patches
+                    .entry((rom.dat.clone(), rom.name.clone()))
+                    .or_default()
+                    .extend(code.rom_patches);
+            }
+            if !code.ops.is_empty() {
+                active.push(Active {
+                    id: cheat.id.clone(),
+                    game: cheat.definition.game,
+                    ops: code.ops,
+                });
+            }
+            log::info!("[cheat: {}] enabled", cheat.id);
+            Ok(())
+        })() {
+            log::error!("[cheat: {}] failed to load: {}", cheat.id, e);
+        }
+    }
+
+    for ((dat, name), patches) in rom_patches {
+        if let Err(e) = (|| -> Result<(), anyhow::Error> {
+            let mut overlay = overlays
+                .get(&dat)
+                .ok_or_else(|| anyhow::anyhow!("no such .dat"))?
+                .lock()
+                .unwrap();
+            let map_path = format!("{}.map", name);
+            let mpak_path = format!("{}.mpak", name);
+            let mut mpak = overlay.read_mpak(&map_path, &mpak_path)?;
+            for (address, contents) in patches {
+                mpak.write(address, &contents);
+            }
+            overlay.write_mpak(&map_path, &mpak_path, &mpak)?;
+            Ok(())
+        })() {
+            log::error!("cannot patch cheats into {}/{}: {}", dat, name, e);
+        }
+    }
+
+    !active.is_empty()
+}
+
+fn apply_active(game: u32, memory: &gba::Memory) {
+    ACTIVE.lock().unwrap().retain(|cheat| {
+        if cheat.game.is_some_and(|g| g as u32 != game) {
+            return true;
+        }
+        match apply(&cheat.ops, memory) {
+            Ok(()) => true,
+            Err(e) => {
+                log::error!("[cheat: {}] disabled: {}", cheat.id, e);
+                false
+            }
+        }
+    });
+}
+
+/// Applies RAM cheats to the loaded game, if there is one.
+///
+/// This is called from the game load hook, and then every frame from the game's message loop so the game can't undo the
+/// writes for long. The message loop may not run on the emulator thread, so like with a cheat device, writes can land in
+/// the middle of the game's frame.
+pub fn apply_loaded() {
+    if ACTIVE.lock().unwrap().is_empty() {
+        return;
+    }
+
+    if let Some((game, memory)) = gba::loaded() {
+        apply_active(game, &memory);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypt(op1: &mut u32, op2: &mut u32, seeds: &[u32; 4]) {
+        let mut sum = 0u32;
+        for _ in 0..32 {
+            sum = sum.wrapping_add(TEA_DELTA);
+            *op1 = op1.wrapping_add(
+                (*op2 << 4).wrapping_add(seeds[0])
+                    ^ op2.wrapping_add(sum)
+                    ^ (*op2 >> 5).wrapping_add(seeds[1]),
+            );
+            *op2 = op2.wrapping_add(
+                (*op1 << 4).wrapping_add(seeds[2])
+                    ^ op1.wrapping_add(sum)
+                    ^ (*op1 >> 5).wrapping_add(seeds[3]),
+            );
+        }
+    }
+
+    fn encrypt_code(lines: &[(u32, u32)]) -> String {
+        lines
+            .iter()
+            .map(|&(mut op1, mut op2)| {
+                encrypt(&mut op1, &mut op2, &PAR_V3_SEEDS);
+                format!("{:08X} {:08X}\n", op1, op2)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_decrypt_roundtrip() {
+        let (mut op1, mut op2) = (0x12345678, 0x9abcdef0);
+        encrypt(&mut op1, &mut op2, &PAR_V3_SEEDS);
+        assert_ne!((op1, op2), (0x12345678, 0x9abcdef0));
+        decrypt(&mut op1, &mut op2, &PAR_V3_SEEDS);
+        assert_eq!((op1, op2), (0x12345678, 0x9abcdef0));
+    }
+
+    #[test]
+    fn test_parse_par_v3() {
+        let code = parse(
+            Format::ActionReplayV3,
+            &encrypt_code(&[
+                // Fill 3 bytes at 0x02001234 with 0x63.
+                (0x00201234, 0x00000263),
+                // If the halfword at 0x03000010 is 5, write a word to 0x02000000.
+                (0x0a300010, 0x00000005),
+                (0x04200000, 0xdeadbeef),
+                // Patch the ROM at 0x08000100.
+                (0x00000000, 0x18000080),
+                (0x0000abcd, 0x00000000),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            code.ops,
+            [
+                Op::Write {
+                    address: 0x02001234,
+                    width: Width::U8,
+                    value: 0x63,
+                    count: 3,
+                },
+                Op::If {
+                    address: 0x03000010,
+                    width: Width::U16,
+                    cond: Cond::Eq,
+                    value: 5,
+                    skip: 1,
+                },
+                Op::Write {
+                    address: 0x02000000,
+                    width: Width::U32,
+                    value: 0xdeadbeef,
+                    count: 1,
+                },
+            ]
+        );
+        assert_eq!(code.rom_patches, [(0x08000100, vec![0xcd, 0xab])]);
+
+        assert_eq!(
+            parse(Format::GamesharkV3, "0123456789ABCDEF"),
+            Err(error(
+                1,
+                "expected 8 and 8 hex digits, separated by a space"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_codebreaker() {
+        let code = parse(
+            Format::Codebreaker,
+            "0000ABCD 0007\n\n\
+             82000000 1234\n\
+             32000002 00FF\n\
+             72000000 1234\n\
+             E2000004 0010\n\
+             A2000000 1234\n\
+             82000006 FFFF\n\
+             88000000 0001\n",
+        )
+        .unwrap();
+        assert_eq!(code.ops.len(), 6);
+        assert_eq!(code.rom_patches, [(0x08000000, vec![0x01, 0x00])]);
+
+        let mut ewram = vec![0u8; 0x40000];
+        let mut gba_state = [0u8; 80];
+        gba_state[72..]
+            .copy_from_slice(&(ewram.as_mut_ptr().wrapping_sub(0x02000000) as usize).to_le_bytes());
+        let memory = unsafe { gba::Memory::from_state(gba_state.as_mut_ptr()) };
+        apply(&code.ops, &memory).unwrap();
+        assert_eq!(ewram[..8], [0x34, 0x12, 0xff, 0x00, 0x10, 0x00, 0x00, 0x00]);
+
+        assert_eq!(
+            parse(Format::Codebreaker, "72000000 1234\n88000000 0001"),
+            Err(error(2, "conditional ROM patches are not supported"))
+        );
+        assert_eq!(
+            parse(Format::Codebreaker, "D2000000 0001"),
+            Err(error(1, "unsupported CodeBreaker code type D"))
+        );
+    }
+}
@@ -13,11 +13,21 @@ pub struct Config {
     pub disable_autostart: bool,
     #[serde(default = "empty_btreeset::<String>")]
     pub enabled_mods: std::collections::BTreeSet<String>,
+    #[serde(default = "empty_btreeset::<String>")]
+    pub enabled_cheats: std::collections::BTreeSet<String>,
+    /// If set, serves GBA memory to external tools on this localhost port.
+    pub memory_bridge_port: Option<u16>,
+    /// Overrides the fuel limit for WASM mods.
+    pub wasm_fuel_limit: Option<u64>,
+    /// Overrides the memory limit for WASM mods, in MiB.
+    pub wasm_memory_limit_mib: Option<usize>,
 
     // Secret options
     pub developer_mode: Option<bool>,
     pub enable_hook_guards: Option<bool>, // requires developer_mode
     pub stage0_commands: Option<std::collections::BTreeSet<String>>, // requires developer_mode
+    pub repl_port: Option<u16>,           // requires developer_mode
+    pub lua_debugger_port: Option<u16>,   // requires developer_mode
 }
 
 const CONFIG_FILE_NAME: &str = "chaudloader.toml";
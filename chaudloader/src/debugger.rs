@@ -0,0 +1,745 @@
+use serde_json::json;
+
+/// The only thread reported to clients: mods run their Lua on the thread that initialized them.
+const THREAD_ID: i64 = 1;
+
+static DEBUGGER: std::sync::OnceLock<std::sync::Arc<Debugger>> = std::sync::OnceLock::new();
+
+fn read_message(
+    reader: &mut impl std::io::BufRead,
+) -> Result<Option<serde_json::Value>, std::io::Error> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+            );
+        }
+    }
+    let content_length = content_length.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "missing Content-Length")
+    })?;
+    let mut buf = vec![0u8; content_length];
+    reader.read_exact(&mut buf)?;
+    Ok(Some(serde_json::from_slice(&buf)?))
+}
+
+fn write_message(
+    writer: &mut impl std::io::Write,
+    message: &serde_json::Value,
+) -> Result<(), std::io::Error> {
+    let buf = serde_json::to_vec(message)?;
+    write!(writer, "Content-Length: {}\r\n\r\n", buf.len())?;
+    writer.write_all(&buf)?;
+    writer.flush()
+}
+
+/// Normalizes a path for comparison: `/` separators and lowercase, as Windows paths are case-insensitive.
+fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/").to_lowercase()
+}
+
+/// The path of a chunk relative to the game directory, from the chunk name set by `require` (e.g. `=foo/bar.lua`), if it is a mod file.
+fn chunk_path(mod_name: &str, chunk_name: &str) -> Option<String> {
+    let path = chunk_name.strip_prefix('=')?;
+    if path.starts_with('<') || path == "[C]" {
+        return None;
+    }
+    Some(format!("mods/{}/{}", mod_name, path.replace('\\', "/")))
+}
+
+/// The breakpoint key of a path sent by a client: relative to the game directory `base` if it is inside it.
+fn source_key(path: &str, base: &str) -> String {
+    let path = normalize_path(path);
+    let base = normalize_path(base);
+    path.strip_prefix(&base)
+        .and_then(|path| path.strip_prefix('/'))
+        .map(|path| path.to_string())
+        .unwrap_or(path)
+}
+
+fn stack_depth(lua: &mlua::Lua) -> usize {
+    (0..)
+        .take_while(|level| lua.inspect_stack(*level).is_some())
+        .count()
+}
+
+/// Pushes a list of `{name, value}` pairs of the locals of the function at `level` of the stack (0 being the function that was running when the hook fired), like `debug.getlocal` which safe mods don't have.
+unsafe extern "C-unwind" fn get_locals(state: *mut mlua::ffi::lua_State) -> std::ffi::c_int {
+    unsafe {
+        let level = mlua::ffi::luaL_checkinteger(state, 1) as std::ffi::c_int;
+        mlua::ffi::lua_createtable(state, 0, 0);
+
+        // Level 0 is this function.
+        let mut ar = std::mem::MaybeUninit::<mlua::ffi::lua_Debug>::zeroed().assume_init();
+        if mlua::ffi::lua_getstack(state, level + 1, &mut ar) == 0 {
+            return 1;
+        }
+
+        let mut n = 1;
+        loop {
+            let name = mlua::ffi::lua_getlocal(state, &ar, n);
+            if name.is_null() {
+                break;
+            }
+            // Stack: list, value
+            mlua::ffi::lua_createtable(state, 2, 0);
+            mlua::ffi::lua_pushstring(state, name);
+            mlua::ffi::lua_rawseti(state, -2, 1);
+            // Stack: list, value, entry
+            mlua::ffi::lua_rotate(state, -2, 1);
+            mlua::ffi::lua_rawseti(state, -2, 2);
+            mlua::ffi::lua_rawseti(state, -2, n as mlua::ffi::lua_Integer);
+            n += 1;
+        }
+        1
+    }
+}
+
+enum Mode {
+    Run,
+    StepIn,
+    StepOver(usize),
+    StepOut(usize),
+}
+
+/// Values that the client can expand, referred to by `variablesReference` (index + 1).
+enum Container<'lua> {
+    Locals(usize),
+    Globals,
+    Table(mlua::Table<'lua>),
+}
+
+/// The state of a stopped Lua thread, which lives until it's resumed.
+struct Stopped<'lua> {
+    lua: &'lua mlua::Lua,
+    mod_name: &'lua str,
+    base: &'lua std::path::Path,
+    get_locals: mlua::Function<'lua>,
+    containers: Vec<Container<'lua>>,
+}
+
+impl<'lua> Stopped<'lua> {
+    fn add(&mut self, container: Container<'lua>) -> usize {
+        self.containers.push(container);
+        self.containers.len()
+    }
+
+    fn locals(&self, level: usize) -> Result<Vec<(String, mlua::Value<'lua>)>, mlua::Error> {
+        self.get_locals
+            .call::<_, mlua::Table>(level)?
+            .sequence_values::<mlua::Table>()
+            .map(|entry| {
+                let entry = entry?;
+                Ok((entry.get::<_, String>(1)?, entry.get::<_, mlua::Value>(2)?))
+            })
+            .filter(|entry| !matches!(entry, Ok((name, _)) if name.starts_with('(')))
+            .collect()
+    }
+
+    fn describe(&mut self, value: mlua::Value<'lua>) -> serde_json::Value {
+        let r#type = value.type_name();
+        let display = match &value {
+            mlua::Value::String(s) => format!("{:?}", s.to_string_lossy()),
+            value => value.to_string().unwrap_or_else(|_| r#type.to_string()),
+        };
+        let reference = match value {
+            mlua::Value::Table(table) => self.add(Container::Table(table)),
+            _ => 0,
+        };
+        json!({"value": display, "type": r#type, "variablesReference": reference})
+    }
+
+    fn handle(&mut self, request: &serde_json::Value) -> Result<serde_json::Value, anyhow::Error> {
+        let args = &request["arguments"];
+        let frame_id = || {
+            args["frameId"]
+                .as_u64()
+                .map(|level| level as usize)
+                .ok_or_else(|| anyhow::anyhow!("missing frameId"))
+        };
+
+        Ok(match request["command"].as_str().unwrap_or("") {
+            "stackTrace" => {
+                let mut frames = vec![];
+                while let Some(debug) = self.lua.inspect_stack(frames.len()) {
+                    let source = debug.source();
+                    let name = debug.names().name.map(|name| name.to_string());
+                    let mut frame = json!({
+                        "id": frames.len(),
+                        "name": name.as_deref().unwrap_or(if source.what == "main" { "main chunk" } else { "?" }),
+                        "line": debug.curr_line().max(0),
+                        "column": 0,
+                    });
+                    if let Some(path) = source
+                        .source
+                        .as_deref()
+                        .and_then(|chunk_name| chunk_path(self.mod_name, chunk_name))
+                    {
+                        let path = path
+                            .split('/')
+                            .fold(self.base.to_path_buf(), |path, component| {
+                                path.join(component)
+                            });
+                        frame["source"] = json!({
+                            "name": path.file_name().map(|name| name.to_string_lossy()),
+                            "path": path.to_string_lossy(),
+                        });
+                    }
+                    frames.push(frame);
+                }
+                json!({"stackFrames": frames, "totalFrames": frames.len()})
+            }
+            "scopes" => {
+                let level = frame_id()?;
+                json!({"scopes": [
+                    {"name": "Locals", "variablesReference": self.add(Container::Locals(level)), "expensive": false},
+                    {"name": "Globals", "variablesReference": self.add(Container::Globals), "expensive": true},
+                ]})
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let entries = match reference
+                    .checked_sub(1)
+                    .and_then(|index| self.containers.get(index))
+                    .ok_or_else(|| anyhow::anyhow!("invalid variablesReference: {}", reference))?
+                {
+                    Container::Locals(level) => self.locals(*level)?,
+                    Container::Globals => table_entries(self.lua.globals())?,
+                    Container::Table(table) => table_entries(table.clone())?,
+                };
+                let variables = entries
+                    .into_iter()
+                    .map(|(name, value)| {
+                        let mut variable = self.describe(value);
+                        variable["name"] = name.into();
+                        variable
+                    })
+                    .collect::<Vec<_>>();
+                json!({"variables": variables})
+            }
+            "evaluate" => {
+                let expression = args["expression"]
+                    .as_str()
+                    .ok_or_else(|| anyhow::anyhow!("missing expression"))?;
+
+                // Locals are copied into the environment, so assigning to them does not change the running function.
+                let env = self.lua.create_table()?;
+                if args["frameId"].is_u64() {
+                    for (name, value) in self.locals(frame_id()?)? {
+                        env.raw_set(name, value)?;
+                    }
+                }
+                let globals = self.lua.globals();
+                env.set_metatable(Some(self.lua.create_table_from([
+                    ("__index", globals.clone()),
+                    ("__newindex", globals),
+                ])?));
+
+                let values = match self
+                    .lua
+                    .load(format!("return {}", expression))
+                    .set_name("=eval")
+                    .set_mode(mlua::ChunkMode::Text)
+                    .set_environment(env.clone())
+                    .into_function()
+                {
+                    Ok(f) => f.call::<_, mlua::MultiValue>(())?,
+                    Err(_) => self
+                        .lua
+                        .load(expression)
+                        .set_name("=eval")
+                        .set_mode(mlua::ChunkMode::Text)
+                        .set_environment(env)
+                        .call::<_, mlua::MultiValue>(())?,
+                };
+                let mut values = values.into_vec();
+                if values.len() == 1 {
+                    let mut result = self.describe(values.remove(0));
+                    result["result"] = result["value"].take();
+                    result
+                } else {
+                    let results = values
+                        .into_iter()
+                        .map(|value| self.describe(value)["value"].take())
+                        .collect::<Vec<_>>();
+                    json!({
+                        "result": results.iter().filter_map(|v| v.as_str()).collect::<Vec<_>>().join(", "),
+                        "variablesReference": 0,
+                    })
+                }
+            }
+            command => return Err(anyhow::anyhow!("unsupported request: {}", command)),
+        })
+    }
+}
+
+fn table_entries(table: mlua::Table<'_>) -> Result<Vec<(String, mlua::Value<'_>)>, mlua::Error> {
+    let mut entries = table
+        .pairs::<mlua::Value, mlua::Value>()
+        .map(|pair| {
+            let (key, value) = pair?;
+            let name = match &key {
+                mlua::Value::String(s) => s.to_string_lossy().to_string(),
+                key => format!("[{}]", key.to_string()?),
+            };
+            Ok((name, value))
+        })
+        .collect::<Result<Vec<_>, mlua::Error>>()?;
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(entries)
+}
+
+pub struct Debugger {
+    base: std::path::PathBuf,
+    writer: std::sync::Mutex<Option<std::net::TcpStream>>,
+    seq: std::sync::atomic::AtomicI64,
+    breakpoints:
+        std::sync::Mutex<std::collections::HashMap<String, std::collections::BTreeSet<usize>>>,
+    mode: std::sync::Mutex<Mode>,
+    pause_requested: std::sync::atomic::AtomicBool,
+
+    /// Forwards requests to the stopped Lua thread, if any.
+    stopped: std::sync::Mutex<Option<std::sync::mpsc::Sender<serde_json::Value>>>,
+    configured: (std::sync::Mutex<bool>, std::sync::Condvar),
+}
+
+impl Debugger {
+    fn send(&self, mut message: serde_json::Value) {
+        let mut writer = self.writer.lock().unwrap();
+        let Some(writer) = writer.as_mut() else {
+            return;
+        };
+        message["seq"] = (self.seq.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1).into();
+        if let Err(e) = write_message(writer, &message) {
+            log::warn!("lua debugger: {}", e);
+        }
+    }
+
+    fn respond(&self, request: &serde_json::Value, result: Result<serde_json::Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.send(response);
+    }
+
+    fn event(&self, event: &str, body: serde_json::Value) {
+        self.send(json!({"type": "event", "event": event, "body": body}));
+    }
+
+    fn set_configured(&self, configured: bool) {
+        let (lock, condvar) = &self.configured;
+        *lock.lock().unwrap() = configured;
+        condvar.notify_all();
+    }
+
+    /// Blocks until a client has sent `configurationDone`, so breakpoints are set before any mod runs.
+    pub fn wait_for_client(&self) {
+        let (lock, condvar) = &self.configured;
+        let _configured = condvar
+            .wait_while(lock.lock().unwrap(), |configured| !*configured)
+            .unwrap();
+    }
+
+    /// Handles a request on the server thread. Returns false if the client disconnected.
+    fn handle_request(&self, request: serde_json::Value) -> bool {
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let args = &request["arguments"];
+        match command.as_str() {
+            "initialize" => {
+                self.respond(
+                    &request,
+                    Ok(json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                    })),
+                );
+                self.event("initialized", json!({}));
+            }
+            "launch" | "attach" | "setExceptionBreakpoints" => {
+                self.respond(&request, Ok(json!({})));
+            }
+            "setBreakpoints" => {
+                let Some(path) = args["source"]["path"].as_str() else {
+                    self.respond(&request, Err("missing source path".to_string()));
+                    return true;
+                };
+                let lines = args["breakpoints"]
+                    .as_array()
+                    .map(|breakpoints| {
+                        breakpoints
+                            .iter()
+                            .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                            .map(|line| line as usize)
+                            .collect::<std::collections::BTreeSet<_>>()
+                    })
+                    .unwrap_or_default();
+                let breakpoints = lines
+                    .iter()
+                    .map(|line| json!({"verified": true, "line": line}))
+                    .collect::<Vec<_>>();
+                self.breakpoints
+                    .lock()
+                    .unwrap()
+                    .insert(source_key(path, &self.base.to_string_lossy()), lines);
+                self.respond(&request, Ok(json!({"breakpoints": breakpoints})));
+            }
+            "configurationDone" => {
+                self.respond(&request, Ok(json!({})));
+                self.set_configured(true);
+            }
+            "threads" => {
+                self.respond(
+                    &request,
+                    Ok(json!({"threads": [{"id": THREAD_ID, "name": "Lua"}]})),
+                );
+            }
+            "pause" => {
+                self.pause_requested
+                    .store(true, std::sync::atomic::Ordering::SeqCst);
+                self.respond(&request, Ok(json!({})));
+            }
+            "disconnect" => {
+                self.respond(&request, Ok(json!({})));
+                return false;
+            }
+            _ => {
+                let stopped = self.stopped.lock().unwrap();
+                match stopped.as_ref() {
+                    Some(sender) => {
+                        // If the thread resumed in the meantime, it answers the request itself.
+                        let _ = sender.send(request);
+                    }
+                    None if command == "continue" => {
+                        self.respond(&request, Ok(json!({"allThreadsContinued": true})));
+                    }
+                    None => self.respond(&request, Err("not stopped".to_string())),
+                }
+            }
+        }
+        true
+    }
+
+    fn handle_connection(&self, stream: std::net::TcpStream) -> Result<(), std::io::Error> {
+        *self.writer.lock().unwrap() = Some(stream.try_clone()?);
+        let mut reader = std::io::BufReader::new(stream);
+        while let Some(request) = read_message(&mut reader)? {
+            if !self.handle_request(request) {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets the client's breakpoints and resumes the Lua thread, so the game runs on without it.
+    fn detach(&self) {
+        *self.writer.lock().unwrap() = None;
+        self.breakpoints.lock().unwrap().clear();
+        *self.mode.lock().unwrap() = Mode::Run;
+        *self.stopped.lock().unwrap() = None;
+        self.set_configured(true);
+    }
+
+    fn should_stop(
+        &self,
+        path: Option<&str>,
+        line: usize,
+        depth: impl Fn() -> usize,
+    ) -> Option<&'static str> {
+        if self
+            .pause_requested
+            .swap(false, std::sync::atomic::Ordering::SeqCst)
+        {
+            return Some("pause");
+        }
+        let stepped = match *self.mode.lock().unwrap() {
+            Mode::Run => false,
+            Mode::StepIn => true,
+            Mode::StepOver(d) => depth() <= d,
+            Mode::StepOut(d) => depth() < d,
+        };
+        if stepped {
+            return Some("step");
+        }
+        if let Some(path) = path
+            && self
+                .breakpoints
+                .lock()
+                .unwrap()
+                .get(&normalize_path(path))
+                .is_some_and(|lines| lines.contains(&line))
+        {
+            return Some("breakpoint");
+        }
+        None
+    }
+
+    /// Stops the Lua thread and handles requests on it until the client resumes it.
+    fn stop(&self, lua: &mlua::Lua, mod_name: &str, reason: &str) -> Result<(), mlua::Error> {
+        let (sender, receiver) = std::sync::mpsc::channel::<serde_json::Value>();
+        *self.mode.lock().unwrap() = Mode::Run;
+        *self.stopped.lock().unwrap() = Some(sender);
+        self.event(
+            "stopped",
+            json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}),
+        );
+
+        let mut stopped = Stopped {
+            lua,
+            mod_name,
+            base: &self.base,
+            get_locals: unsafe { lua.create_c_function(get_locals)? },
+            containers: vec![],
+        };
+        for request in receiver.iter() {
+            let mode = match request["command"].as_str().unwrap_or("") {
+                "continue" => Some(Mode::Run),
+                "next" => Some(Mode::StepOver(stack_depth(lua))),
+                "stepIn" => Some(Mode::StepIn),
+                "stepOut" => Some(Mode::StepOut(stack_depth(lua))),
+                _ => None,
+            };
+            if let Some(mode) = mode {
+                *self.mode.lock().unwrap() = mode;
+                *self.stopped.lock().unwrap() = None;
+                self.respond(&request, Ok(json!({"allThreadsContinued": true})));
+                break;
+            }
+            let result = stopped.handle(&request).map_err(|e| e.to_string());
+            self.respond(&request, result);
+        }
+
+        *self.stopped.lock().unwrap() = None;
+        for request in receiver.try_iter() {
+            match request["command"].as_str().unwrap_or("") {
+                "continue" | "next" | "stepIn" | "stepOut" => {
+                    self.respond(&request, Ok(json!({"allThreadsContinued": true})))
+                }
+                _ => self.respond(&request, Err("not stopped".to_string())),
+            }
+        }
+        Ok(())
+    }
+
+    fn attach_to(self: std::sync::Arc<Self>, lua: &mlua::Lua, mod_name: &str) {
+        let mod_name = mod_name.to_string();
+        lua.set_hook(mlua::HookTriggers::EVERY_LINE, move |lua, debug| {
+            let path = debug
+                .source()
+                .source
+                .as_deref()
+                .and_then(|chunk_name| chunk_path(&mod_name, chunk_name));
+            if let Some(reason) =
+                self.should_stop(path.as_deref(), debug.curr_line().max(0) as usize, || {
+                    stack_depth(lua)
+                })
+            {
+                self.stop(lua, &mod_name, reason)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Serves debugger clients on `listener` in the background, one at a time.
+pub fn serve(listener: std::net::TcpListener) -> std::sync::Arc<Debugger> {
+    let debugger = std::sync::Arc::new(Debugger {
+        base: std::env::current_dir().unwrap_or_default(),
+        writer: std::sync::Mutex::new(None),
+        seq: std::sync::atomic::AtomicI64::new(0),
+        breakpoints: std::sync::Mutex::new(std::collections::HashMap::new()),
+        mode: std::sync::Mutex::new(Mode::Run),
+        pause_requested: std::sync::atomic::AtomicBool::new(false),
+        stopped: std::sync::Mutex::new(None),
+        configured: (std::sync::Mutex::new(false), std::sync::Condvar::new()),
+    });
+    std::thread::spawn({
+        let debugger = std::sync::Arc::clone(&debugger);
+        move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::error!("lua debugger: {}", e);
+                        continue;
+                    }
+                };
+                log::info!("lua debugger: client connected");
+                if let Err(e) = debugger.handle_connection(stream) {
+                    log::warn!("lua debugger: connection closed: {}", e);
+                }
+                debugger.detach();
+                log::info!("lua debugger: client disconnected");
+            }
+        }
+    });
+    debugger
+}
+
+/// Starts the debug adapter on `127.0.0.1:<port>` and waits for a client to attach.
+pub fn start(port: u16) -> Result<(), std::io::Error> {
+    let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?;
+    log::info!(
+        "lua debugger: waiting for a client on {}",
+        listener.local_addr()?
+    );
+    let debugger = DEBUGGER.get_or_init(|| serve(listener));
+    debugger.wait_for_client();
+    Ok(())
+}
+
+/// Lets the debugger stop in a mod's Lua state, if the debugger was started.
+pub fn attach(lua: &mlua::Lua, mod_name: &str) {
+    if let Some(debugger) = DEBUGGER.get() {
+        std::sync::Arc::clone(debugger).attach_to(lua, mod_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_key() {
+        assert_eq!(
+            source_key(r"C:\Games\exe\Mods\Foo\init.lua", r"C:\Games\exe"),
+            "mods/foo/init.lua"
+        );
+        assert_eq!(
+            source_key("/tmp/init.lua", r"C:\Games\exe"),
+            "/tmp/init.lua"
+        );
+        assert_eq!(
+            chunk_path("foo", "=lib/bar.lua").as_deref(),
+            Some("mods/foo/lib/bar.lua")
+        );
+        assert_eq!(chunk_path("foo", "=<builtin>\\chaudloader\\util.lua"), None);
+        assert_eq!(chunk_path("foo", "local x = 1"), None);
+    }
+
+    struct Client {
+        reader: std::io::BufReader<std::net::TcpStream>,
+        writer: std::net::TcpStream,
+        seq: i64,
+    }
+
+    impl Client {
+        fn request(&mut self, command: &str, arguments: serde_json::Value) -> serde_json::Value {
+            self.seq += 1;
+            write_message(
+                &mut self.writer,
+                &json!({"seq": self.seq, "type": "request", "command": command, "arguments": arguments}),
+            )
+            .unwrap();
+            loop {
+                let message = read_message(&mut self.reader).unwrap().unwrap();
+                if message["type"] == "response" && message["request_seq"] == self.seq {
+                    assert_eq!(message["success"], true, "{}", message);
+                    return message["body"].clone();
+                }
+            }
+        }
+
+        fn wait_for_event(&mut self, event: &str) -> serde_json::Value {
+            loop {
+                let message = read_message(&mut self.reader).unwrap().unwrap();
+                if message["type"] == "event" && message["event"] == event {
+                    return message["body"].clone();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let debugger = serve(listener);
+
+        let client = std::thread::spawn(move || {
+            let stream = std::net::TcpStream::connect(addr).unwrap();
+            let mut client = Client {
+                reader: std::io::BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+                seq: 0,
+            };
+            client.request("initialize", json!({}));
+            let path = std::env::current_dir()
+                .unwrap()
+                .join("mods")
+                .join("test")
+                .join("init.lua");
+            client.request(
+                "setBreakpoints",
+                json!({"source": {"path": path}, "breakpoints": [{"line": 3}]}),
+            );
+            client.request("configurationDone", json!({}));
+
+            assert_eq!(client.wait_for_event("stopped")["reason"], "breakpoint");
+            let frames = client.request("stackTrace", json!({"threadId": THREAD_ID}));
+            assert_eq!(frames["stackFrames"][0]["line"], 3);
+            assert_eq!(
+                frames["stackFrames"][0]["source"]["path"],
+                path.to_string_lossy().as_ref()
+            );
+
+            let scopes = client.request("scopes", json!({"frameId": 0}));
+            let locals = client.request(
+                "variables",
+                json!({"variablesReference": scopes["scopes"][0]["variablesReference"]}),
+            )["variables"]
+                .clone();
+            assert_eq!(locals[0]["name"], "x");
+            assert_eq!(locals[0]["value"], "1");
+            assert_eq!(locals[1]["name"], "t");
+            let fields = client.request(
+                "variables",
+                json!({"variablesReference": locals[1]["variablesReference"]}),
+            );
+            assert_eq!(fields["variables"][0]["name"], "a");
+            assert_eq!(fields["variables"][0]["value"], "\"b\"");
+
+            let result =
+                client.request("evaluate", json!({"expression": "x + #t.a", "frameId": 0}));
+            assert_eq!(result["result"], "2");
+
+            client.request("next", json!({"threadId": THREAD_ID}));
+            assert_eq!(client.wait_for_event("stopped")["reason"], "step");
+            let frames = client.request("stackTrace", json!({"threadId": THREAD_ID}));
+            assert_eq!(frames["stackFrames"][0]["line"], 4);
+
+            client.request("continue", json!({"threadId": THREAD_ID}));
+        });
+
+        debugger.wait_for_client();
+        let lua = mlua::Lua::new();
+        std::sync::Arc::clone(&debugger).attach_to(&lua, "test");
+        let x = lua
+            .load("local x = 1\nlocal t = {a = 'b'}\nx = x + 1\nreturn x\n")
+            .set_name("=init.lua")
+            .eval::<i64>()
+            .unwrap();
+        assert_eq!(x, 2);
+        client.join().unwrap();
+    }
+}
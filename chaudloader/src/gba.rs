@@ -0,0 +1,378 @@
+/// A region of the GBA bus.
+///
+/// The emulator keeps GBA memory in a single buffer (`GBAState.memory`), indexed directly by bus address.
+#[derive(Debug, PartialEq)]
+pub struct Region {
+    pub name: &'static str,
+    pub start: u32,
+    pub size: u32,
+    pub writable: bool,
+}
+
+impl Region {
+    fn contains(&self, address: u32, len: usize) -> bool {
+        address >= self.start && (address - self.start) as u64 + len as u64 <= self.size as u64
+    }
+}
+
+pub const REGIONS: &[Region] = &[
+    Region {
+        name: "BIOS",
+        start: 0x00000000,
+        size: 0x4000,
+        writable: false,
+    },
+    Region {
+        name: "EWRAM",
+        start: 0x02000000,
+        size: 0x40000,
+        writable: true,
+    },
+    Region {
+        name: "IWRAM",
+        start: 0x03000000,
+        size: 0x8000,
+        writable: true,
+    },
+    Region {
+        name: "IO",
+        start: 0x04000000,
+        size: 0x400,
+        writable: true,
+    },
+    Region {
+        name: "PALETTE",
+        start: 0x05000000,
+        size: 0x400,
+        writable: true,
+    },
+    Region {
+        name: "VRAM",
+        start: 0x06000000,
+        size: 0x18000,
+        writable: true,
+    },
+    Region {
+        name: "OAM",
+        start: 0x07000000,
+        size: 0x400,
+        writable: true,
+    },
+    Region {
+        name: "ROM",
+        start: 0x08000000,
+        size: 0x2000000,
+        writable: true,
+    },
+];
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum Error {
+    #[error("{len} byte(s) at {address:#010x} are not in a mapped region")]
+    Unmapped { address: u32, len: usize },
+
+    #[error("{len} byte(s) at {address:#010x} are past the end of the {rom_len:#x} byte ROM")]
+    PastRomEnd {
+        address: u32,
+        len: usize,
+        rom_len: u32,
+    },
+
+    #[error("{len} byte(s) at {address:#010x} are in the read-only {region} region")]
+    ReadOnly {
+        address: u32,
+        len: usize,
+        region: &'static str,
+    },
+}
+
+/// Finds the region that holds all of `len` bytes starting at bus address `address`.
+///
+/// This only checks the memory map: the ROM region is as large as a cartridge can be, not the ROM of the running game.
+pub fn region(address: u32, len: usize) -> Result<&'static Region, Error> {
+    REGIONS
+        .iter()
+        .find(|region| region.contains(address, len))
+        .ok_or(Error::Unmapped { address, len })
+}
+
+/// Offset of the `memory` pointer in the emulator's GBA state, after r0-r15, cpuFlags and flagsImplicitUpdate.
+const MEMORY_OFFSET: usize = 18 * std::mem::size_of::<u32>();
+
+/// The ROM region in [`REGIONS`].
+const ROM: &Region = &REGIONS[REGIONS.len() - 1];
+
+/// Returns how many bytes from the start of the ROM region the emulator's memory buffer at `base` actually holds, by walking
+/// the writable pages that follow it.
+unsafe fn rom_len(base: *mut u8) -> u32 {
+    let start = base.wrapping_add(ROM.start as usize) as usize;
+    let mut len = 0;
+    while len < ROM.size as usize {
+        let mut info =
+            std::mem::MaybeUninit::<winapi::um::winnt::MEMORY_BASIC_INFORMATION>::zeroed();
+        if unsafe {
+            winapi::um::memoryapi::VirtualQuery(
+                std::ptr::with_exposed_provenance(start + len),
+                info.as_mut_ptr(),
+                std::mem::size_of::<winapi::um::winnt::MEMORY_BASIC_INFORMATION>(),
+            )
+        } == 0
+        {
+            break;
+        }
+        let info = unsafe { info.assume_init() };
+        if info.State != winapi::um::winnt::MEM_COMMIT
+            || info.Protect & winapi::um::winnt::PAGE_GUARD != 0
+            || info.Protect
+                & (winapi::um::winnt::PAGE_READWRITE | winapi::um::winnt::PAGE_EXECUTE_READWRITE)
+                == 0
+        {
+            break;
+        }
+        len = info.BaseAddress as usize + info.RegionSize - start;
+    }
+    len.min(ROM.size as usize) as u32
+}
+
+/// Bounds-checked access to GBA memory by bus address.
+#[derive(Clone, Copy)]
+pub struct Memory {
+    base: *mut u8,
+    rom_len: u32,
+}
+
+impl Memory {
+    /// Creates an accessor over the memory of the emulator's GBA state, as passed to `on_game_load`.
+    pub unsafe fn from_state(gba_state: *mut u8) -> Self {
+        let base =
+            unsafe { std::ptr::read_unaligned(gba_state.add(MEMORY_OFFSET) as *const *mut u8) };
+        Self {
+            base,
+            rom_len: unsafe { rom_len(base) },
+        }
+    }
+
+    /// Creates an accessor over the memory of the emulator's GBA state, whose ROM is `rom_len` bytes long.
+    unsafe fn from_state_with_rom_len(gba_state: *mut u8, rom_len: u32) -> Self {
+        Self {
+            base: unsafe {
+                std::ptr::read_unaligned(gba_state.add(MEMORY_OFFSET) as *const *mut u8)
+            },
+            rom_len,
+        }
+    }
+
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// The length of the running game's ROM, which may be shorter than the ROM region.
+    pub fn rom_len(&self) -> u32 {
+        self.rom_len
+    }
+
+    /// Finds the region that holds all of `len` bytes starting at bus address `address`, checking ROM accesses against the
+    /// length of the ROM.
+    fn region(&self, address: u32, len: usize) -> Result<&'static Region, Error> {
+        let region = region(address, len)?;
+        if std::ptr::eq(region, ROM)
+            && (address - ROM.start) as u64 + len as u64 > self.rom_len as u64
+        {
+            return Err(Error::PastRomEnd {
+                address,
+                len,
+                rom_len: self.rom_len,
+            });
+        }
+        Ok(region)
+    }
+
+    pub fn read(&self, address: u32, buf: &mut [u8]) -> Result<(), Error> {
+        self.region(address, buf.len())?;
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                self.base.wrapping_add(address as usize),
+                buf.as_mut_ptr(),
+                buf.len(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, address: u32, buf: &[u8]) -> Result<(), Error> {
+        let region = self.region(address, buf.len())?;
+        if !region.writable {
+            return Err(Error::ReadOnly {
+                address,
+                len: buf.len(),
+                region: region.name,
+            });
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                buf.as_ptr(),
+                self.base.wrapping_add(address as usize),
+                buf.len(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn read_u8(&self, address: u32) -> Result<u8, Error> {
+        let mut buf = [0u8; 1];
+        self.read(address, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    pub fn read_u16(&self, address: u32) -> Result<u16, Error> {
+        let mut buf = [0u8; 2];
+        self.read(address, &mut buf)?;
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub fn read_u32(&self, address: u32) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        self.read(address, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    pub fn write_u8(&self, address: u32, value: u8) -> Result<(), Error> {
+        self.write(address, &[value])
+    }
+
+    pub fn write_u16(&self, address: u32, value: u16) -> Result<(), Error> {
+        self.write(address, &value.to_le_bytes())
+    }
+
+    pub fn write_u32(&self, address: u32, value: u32) -> Result<(), Error> {
+        self.write(address, &value.to_le_bytes())
+    }
+}
+
+/// The running game, the address of its GBA state and the length of its ROM, as last passed to `on_game_load`, until it is
+/// unloaded.
+static LOADED: std::sync::Mutex<Option<(u32, usize, u32)>> = std::sync::Mutex::new(None);
+
+pub unsafe fn set_loaded(game: u32, gba_state: *mut u8) {
+    let rom_len = unsafe { Memory::from_state(gba_state) }.rom_len();
+    *LOADED.lock().unwrap() = Some((game, gba_state.expose_provenance(), rom_len));
+}
+
+pub fn set_unloaded() {
+    *LOADED.lock().unwrap() = None;
+}
+
+/// Returns the running game and its GBA state, if a game has been loaded.
+pub fn loaded_state() -> Option<(u32, *mut u8)> {
+    LOADED
+        .lock()
+        .unwrap()
+        .map(|(game, gba_state, _)| (game, std::ptr::with_exposed_provenance_mut(gba_state)))
+}
+
+/// Returns the running game and its memory, if a game has been loaded.
+pub fn loaded() -> Option<(u32, Memory)> {
+    LOADED.lock().unwrap().map(|(game, gba_state, rom_len)| {
+        (game, unsafe {
+            Memory::from_state_with_rom_len(
+                std::ptr::with_exposed_provenance_mut(gba_state),
+                rom_len,
+            )
+        })
+    })
+}
+
+/// Reads `len` bytes at GBA bus address `address` into `buf`. Returns false if the range is not mapped.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chaudloader_gba_read(
+    gba_state: *mut u8,
+    address: u32,
+    buf: *mut u8,
+    len: usize,
+) -> bool {
+    unsafe {
+        Memory::from_state(gba_state)
+            .read(address, std::slice::from_raw_parts_mut(buf, len))
+            .is_ok()
+    }
+}
+
+/// Writes `len` bytes from `buf` to GBA bus address `address`. Returns false if the range is not mapped or is read-only.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chaudloader_gba_write(
+    gba_state: *mut u8,
+    address: u32,
+    buf: *const u8,
+    len: usize,
+) -> bool {
+    unsafe {
+        Memory::from_state(gba_state)
+            .write(address, std::slice::from_raw_parts(buf, len))
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_region() {
+        assert_eq!(region(0x02000000, 4).unwrap().name, "EWRAM");
+        assert_eq!(region(0x0203fffc, 4).unwrap().name, "EWRAM");
+        assert_eq!(
+            region(0x0203fffe, 4),
+            Err(Error::Unmapped {
+                address: 0x0203fffe,
+                len: 4
+            })
+        );
+        assert_eq!(region(0x09ffffff, 1).unwrap().name, "ROM");
+        assert!(region(0x0a000000, 1).is_err());
+        assert!(region(0xffffffff, 2).is_err());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut iwram = vec![0u8; 0x8000];
+        let mut gba_state = [0u8; 80];
+        let base = iwram.as_mut_ptr().wrapping_sub(0x03000000);
+        gba_state[MEMORY_OFFSET..].copy_from_slice(&(base as usize).to_le_bytes());
+        let memory = unsafe { Memory::from_state(gba_state.as_mut_ptr()) };
+
+        memory.write_u32(0x03000010, 0x12345678).unwrap();
+        assert_eq!(iwram[0x10..0x14], [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(memory.read_u16(0x03000012).unwrap(), 0x1234);
+        assert_eq!(memory.read_u8(0x03000010).unwrap(), 0x78);
+        assert!(memory.write_u16(0x03007fff, 0).is_err());
+        assert_eq!(
+            memory.write_u8(0x00000000, 0),
+            Err(Error::ReadOnly {
+                address: 0,
+                len: 1,
+                region: "BIOS"
+            })
+        );
+    }
+
+    #[test]
+    fn test_rom_len() {
+        let mut rom = vec![0u8; 0x100];
+        let mut gba_state = [0u8; 80];
+        let base = rom.as_mut_ptr().wrapping_sub(0x08000000);
+        gba_state[MEMORY_OFFSET..].copy_from_slice(&(base as usize).to_le_bytes());
+        let memory = unsafe { Memory::from_state_with_rom_len(gba_state.as_mut_ptr(), 0x100) };
+
+        memory.write_u32(0x080000fc, 0x12345678).unwrap();
+        assert_eq!(memory.read_u32(0x080000fc).unwrap(), 0x12345678);
+        assert_eq!(
+            memory.read_u32(0x080000fe),
+            Err(Error::PastRomEnd {
+                address: 0x080000fe,
+                len: 4,
+                rom_len: 0x100
+            })
+        );
+        assert!(memory.write_u8(0x09ffffff, 0).is_err());
+    }
+}
@@ -0,0 +1,1352 @@
+#[derive(thiserror::Error, Debug)]
+#[error("line {line}: {message}")]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Default, Debug)]
+pub struct Options {
+    /// ROM address to start assembling at, if the source does not start with `.org`.
+    pub org: Option<u32>,
+
+    /// Symbols defined outside of the source, e.g. addresses of existing routines in the ROM.
+    pub symbols: std::collections::HashMap<String, u32>,
+}
+
+/// A contiguous run of assembled bytes, starting at a ROM address.
+pub struct Chunk {
+    pub rom_addr: u32,
+    pub contents: Vec<u8>,
+}
+
+pub struct Assembly {
+    /// One chunk per `.org`, sorted by ROM address. Chunks never overlap.
+    pub chunks: Vec<Chunk>,
+
+    /// The addresses of all labels defined in the source.
+    pub labels: std::collections::BTreeMap<String, u32>,
+}
+
+enum ItemKind {
+    Org,
+    Instruction {
+        mnemonic: String,
+        operands: Vec<String>,
+        /// Pool and slot index, for `ldr rd, =expr`.
+        literal: Option<(usize, usize)>,
+    },
+    Data {
+        size: usize,
+        exprs: Vec<String>,
+    },
+    Bytes(Vec<u8>),
+    Pool(usize),
+}
+
+struct Item {
+    line: usize,
+    addr: u32,
+    kind: ItemKind,
+}
+
+#[derive(Default)]
+struct Pool {
+    addr: u32,
+    exprs: Vec<(usize, String)>,
+}
+
+struct Assembler<'a> {
+    options: &'a Options,
+    symbols: std::collections::HashMap<String, i64>,
+    labels: std::collections::BTreeMap<String, u32>,
+    items: Vec<Item>,
+    pools: Vec<Pool>,
+    pending_pool: Option<usize>,
+    addr: Option<u32>,
+    line: usize,
+}
+
+/// Assembles Thumb source code, in the pre-UAL syntax used by GNU as with `.thumb`.
+///
+/// All 19 ARM7TDMI Thumb instruction formats are supported, along with labels, `ldr rd, =expr` literal pools and the following directives: `.org`, `.thumb`, `.align`, `.byte`, `.hword`/`.short`, `.word`/`.long`, `.ascii`, `.asciz`, `.space`, `.equ`/`.set` and `.pool`/`.ltorg`.
+pub fn assemble(source: &str, options: &Options) -> Result<Assembly, Error> {
+    let mut assembler = Assembler {
+        options,
+        symbols: std::collections::HashMap::new(),
+        labels: std::collections::BTreeMap::new(),
+        items: vec![],
+        pools: vec![],
+        pending_pool: None,
+        addr: options.org,
+        line: 0,
+    };
+
+    if options.org.is_some() {
+        assembler.push(0, ItemKind::Org).unwrap();
+    }
+
+    for (i, line) in source.lines().enumerate() {
+        assembler.line = i + 1;
+        assembler
+            .parse_statement(strip_comment(line).trim())
+            .map_err(|message| Error {
+                line: assembler.line,
+                message,
+            })?;
+    }
+    assembler.flush_pool().map_err(|message| Error {
+        line: assembler.line,
+        message,
+    })?;
+
+    assembler.emit()
+}
+
+/// Strips comments, which start with `@`, `;` or `//`, outside of string literals.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '@' | ';' => return &line[..i],
+            '/' if line[i..].starts_with("//") => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits operands on top-level commas, i.e. those not inside brackets, braces, parentheses or strings.
+pub(crate) fn split_operands(s: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(s[start..i].trim().to_string());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    let last = s[start..].trim();
+    if !last.is_empty() || !operands.is_empty() {
+        operands.push(last.to_string());
+    }
+    operands
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '.'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+pub(crate) fn parse_string(s: &str) -> Result<Vec<u8>, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or_else(|| format!("expected string literal, got {}", s))?;
+    let mut buf = vec![];
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            buf.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        buf.push(match chars.next() {
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('0') => 0,
+            Some('\\') => b'\\',
+            Some('"') => b'"',
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                u8::from_str_radix(&hex, 16)
+                    .map_err(|_| format!("invalid hex escape \\x{}", hex))?
+            }
+            c => return Err(format!("invalid escape \\{}", c.unwrap_or(' '))),
+        });
+    }
+    Ok(buf)
+}
+
+struct ExprParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+    pc: i64,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl ExprParser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        self.skip_whitespace();
+        if self.s[self.pos..].starts_with(op.as_bytes()) {
+            self.pos += op.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&str],
+        next: fn(&mut Self) -> Result<i64, String>,
+        apply: fn(&str, i64, i64) -> Result<i64, String>,
+    ) -> Result<i64, String> {
+        let mut lhs = next(self)?;
+        'outer: loop {
+            for op in ops {
+                if self.eat(op) {
+                    let rhs = next(self)?;
+                    lhs = apply(op, lhs, rhs)?;
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn or(&mut self) -> Result<i64, String> {
+        self.binary(&["|"], Self::xor, |_, a, b| Ok(a | b))
+    }
+
+    fn xor(&mut self) -> Result<i64, String> {
+        self.binary(&["^"], Self::and, |_, a, b| Ok(a ^ b))
+    }
+
+    fn and(&mut self) -> Result<i64, String> {
+        self.binary(&["&"], Self::shift, |_, a, b| Ok(a & b))
+    }
+
+    fn shift(&mut self) -> Result<i64, String> {
+        self.binary(&["<<", ">>"], Self::add, |op, a, b| {
+            let b = u32::try_from(b)
+                .ok()
+                .filter(|b| *b < 64)
+                .ok_or_else(|| format!("invalid shift amount {}", b))?;
+            Ok(if op == "<<" { a << b } else { a >> b })
+        })
+    }
+
+    fn add(&mut self) -> Result<i64, String> {
+        self.binary(&["+", "-"], Self::mul, |op, a, b| {
+            Ok(if op == "+" {
+                a.wrapping_add(b)
+            } else {
+                a.wrapping_sub(b)
+            })
+        })
+    }
+
+    fn mul(&mut self) -> Result<i64, String> {
+        self.binary(&["*", "/", "%"], Self::unary, |op, a, b| match op {
+            "*" => Ok(a.wrapping_mul(b)),
+            _ if b == 0 => Err("division by zero".to_string()),
+            "/" => Ok(a.wrapping_div(b)),
+            _ => Ok(a.wrapping_rem(b)),
+        })
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        if self.eat("-") {
+            Ok(self.unary()?.wrapping_neg())
+        } else if self.eat("~") {
+            Ok(!self.unary()?)
+        } else if self.eat("+") {
+            self.unary()
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<i64, String> {
+        self.skip_whitespace();
+        if self.eat("(") {
+            let v = self.or()?;
+            if !self.eat(")") {
+                return Err("expected )".to_string());
+            }
+            return Ok(v);
+        }
+
+        let start = self.pos;
+        let rest = std::str::from_utf8(&self.s[start..]).unwrap();
+        let Some(c) = rest.chars().next() else {
+            return Err("unexpected end of expression".to_string());
+        };
+
+        if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let token = &rest[..len];
+            self.pos += len;
+            let lower = token.to_ascii_lowercase();
+            let (digits, radix) = if let Some(digits) = lower.strip_prefix("0x") {
+                (digits, 16)
+            } else if let Some(digits) = lower.strip_prefix("0b") {
+                (digits, 2)
+            } else {
+                (&lower[..], 10)
+            };
+            return i64::from_str_radix(digits, radix)
+                .map_err(|_| format!("invalid number {}", token));
+        }
+
+        if is_ident_start(c) {
+            let len = rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len());
+            let name = &rest[..len];
+            self.pos += len;
+            if name == "." {
+                return Ok(self.pc);
+            }
+            return (self.lookup)(name).ok_or_else(|| format!("undefined symbol {}", name));
+        }
+
+        Err(format!("unexpected {} in expression", c))
+    }
+}
+
+/// Evaluates an expression made of numbers, symbols and C-like operators, where `.` is the current address.
+pub(crate) fn eval_expr(
+    expr: &str,
+    pc: i64,
+    lookup: &dyn Fn(&str) -> Option<i64>,
+) -> Result<i64, String> {
+    let mut parser = ExprParser {
+        s: expr.as_bytes(),
+        pos: 0,
+        pc,
+        lookup,
+    };
+    let v = parser.or()?;
+    parser.skip_whitespace();
+    if parser.pos != expr.len() {
+        return Err(format!("unexpected {} in expression", &expr[parser.pos..]));
+    }
+    Ok(v)
+}
+
+fn parse_reg(s: &str) -> Option<u8> {
+    let s = s.trim().to_ascii_lowercase();
+    match s.as_str() {
+        "sp" => Some(13),
+        "lr" => Some(14),
+        "pc" => Some(15),
+        _ => s
+            .strip_prefix('r')
+            .and_then(|n| n.parse::<u8>().ok())
+            .filter(|n| *n < 16),
+    }
+}
+
+fn reg(s: &str) -> Result<u8, String> {
+    parse_reg(s).ok_or_else(|| format!("expected register, got {}", s))
+}
+
+fn low_reg(s: &str) -> Result<u16, String> {
+    let r = reg(s)?;
+    if r >= 8 {
+        return Err(format!("expected r0-r7, got {}", s));
+    }
+    Ok(r as u16)
+}
+
+fn is_imm(s: &str) -> bool {
+    s.starts_with('#')
+}
+
+/// Checks that a value is in range and a multiple of `align`, and returns it divided by `align`.
+fn check_range(what: &str, value: i64, min: i64, max: i64, align: i64) -> Result<u16, String> {
+    if value < min || value > max {
+        return Err(format!(
+            "{} {:#x} out of range ({:#x} to {:#x})",
+            what, value, min, max
+        ));
+    }
+    if value % align != 0 {
+        return Err(format!(
+            "{} {:#x} is not a multiple of {}",
+            what, value, align
+        ));
+    }
+    Ok(((value / align) as u32 & 0xffff) as u16)
+}
+
+enum MemOffset {
+    Imm(i64),
+    Reg(u16),
+}
+
+const CONDITIONS: &[(&str, u16)] = &[
+    ("eq", 0),
+    ("ne", 1),
+    ("cs", 2),
+    ("hs", 2),
+    ("cc", 3),
+    ("lo", 3),
+    ("mi", 4),
+    ("pl", 5),
+    ("vs", 6),
+    ("vc", 7),
+    ("hi", 8),
+    ("ls", 9),
+    ("ge", 10),
+    ("lt", 11),
+    ("gt", 12),
+    ("le", 13),
+];
+
+const ALU_OPS: &[(&str, u16)] = &[
+    ("and", 0),
+    ("eor", 1),
+    ("adc", 5),
+    ("sbc", 6),
+    ("ror", 7),
+    ("tst", 8),
+    ("neg", 9),
+    ("cmn", 11),
+    ("orr", 12),
+    ("mul", 13),
+    ("bic", 14),
+    ("mvn", 15),
+];
+
+/// Mnemonics which may carry a redundant `s` suffix, as Thumb data processing instructions always set flags.
+const FLAG_SETTING: &[&str] = &[
+    "lsl", "lsr", "asr", "add", "sub", "mov", "and", "eor", "adc", "sbc", "ror", "neg", "orr",
+    "mul", "bic", "mvn",
+];
+
+fn normalize_mnemonic(mnemonic: &str) -> String {
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    match mnemonic.strip_suffix('s') {
+        Some(base) if FLAG_SETTING.contains(&base) => base.to_string(),
+        _ => mnemonic,
+    }
+}
+
+fn expect_operands(mnemonic: &str, operands: &[String], n: usize) -> Result<(), String> {
+    if operands.len() != n {
+        return Err(format!(
+            "{} expects {} operands, got {}",
+            mnemonic,
+            n,
+            operands.len()
+        ));
+    }
+    Ok(())
+}
+
+fn parse_rlist(s: &str) -> Result<u16, String> {
+    let inner = s
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(|| format!("expected register list, got {}", s))?;
+    let mut rlist = 0u16;
+    for part in inner.split(',') {
+        let part = part.trim();
+        if let Some((first, last)) = part.split_once('-') {
+            let (first, last) = (reg(first)?, reg(last)?);
+            if first > last {
+                return Err(format!("invalid register range {}", part));
+            }
+            for r in first..=last {
+                rlist |= 1 << r;
+            }
+        } else {
+            rlist |= 1 << reg(part)?;
+        }
+    }
+    Ok(rlist)
+}
+
+impl Assembler<'_> {
+    fn lookup(&self, name: &str) -> Option<i64> {
+        self.symbols
+            .get(name)
+            .copied()
+            .or_else(|| self.options.symbols.get(name).map(|v| *v as i64))
+    }
+
+    fn eval(&self, expr: &str, pc: u32) -> Result<i64, String> {
+        eval_expr(expr, pc as i64, &|name| self.lookup(name))
+    }
+
+    fn imm(&self, s: &str, pc: u32) -> Result<i64, String> {
+        self.eval(
+            s.strip_prefix('#')
+                .ok_or_else(|| format!("expected immediate, got {}", s))?,
+            pc,
+        )
+    }
+
+    fn current_addr(&self) -> Result<u32, String> {
+        self.addr
+            .ok_or_else(|| "no address to assemble at: use .org first".to_string())
+    }
+
+    fn push(&mut self, size: usize, kind: ItemKind) -> Result<(), String> {
+        let addr = self.current_addr()?;
+        self.items.push(Item {
+            line: self.line,
+            addr,
+            kind,
+        });
+        self.addr = Some(
+            u32::try_from(addr as u64 + size as u64)
+                .map_err(|_| "address overflowed".to_string())?,
+        );
+        Ok(())
+    }
+
+    fn pad_to(&mut self, align: u32, fill: u8) -> Result<(), String> {
+        let addr = self.current_addr()?;
+        let padding = (addr
+            .checked_next_multiple_of(align)
+            .ok_or_else(|| format!("cannot align {:#010x} to {} bytes", addr, align))?
+            - addr) as usize;
+        if padding > 0 {
+            self.push(padding, ItemKind::Bytes(vec![fill; padding]))?;
+        }
+        Ok(())
+    }
+
+    /// Places the pending literal pool, if any, at the current address.
+    fn flush_pool(&mut self) -> Result<(), String> {
+        let Some(index) = self.pending_pool.take() else {
+            return Ok(());
+        };
+        self.pad_to(4, 0)?;
+        self.pools[index].addr = self.current_addr()?;
+        self.push(self.pools[index].exprs.len() * 4, ItemKind::Pool(index))
+    }
+
+    fn define(&mut self, name: &str, value: i64) -> Result<(), String> {
+        if !name.starts_with(is_ident_start) || !name.chars().all(is_ident_char) || name == "." {
+            return Err(format!("invalid symbol name {}", name));
+        }
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err(format!("{} is already defined", name));
+        }
+        Ok(())
+    }
+
+    fn parse_statement(&mut self, mut s: &str) -> Result<(), String> {
+        while let Some((name, rest)) = s.split_once(':') {
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(is_ident_char) {
+                break;
+            }
+            let addr = self.current_addr()?;
+            self.define(name, addr as i64)?;
+            self.labels.insert(name.to_string(), addr);
+            s = rest.trim();
+        }
+        if s.is_empty() {
+            return Ok(());
+        }
+
+        let (mnemonic, rest) = s
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((s, ""));
+        let operands = split_operands(rest.trim());
+        let mnemonic = normalize_mnemonic(mnemonic);
+
+        if mnemonic.starts_with('.') {
+            return self.parse_directive(&mnemonic, &operands);
+        }
+
+        let mut literal = None;
+        if mnemonic == "ldr" && operands.len() == 2 && operands[1].starts_with('=') {
+            let expr = operands[1][1..].trim().to_string();
+            let pool_index = *self.pending_pool.get_or_insert_with(|| {
+                self.pools.push(Pool::default());
+                self.pools.len() - 1
+            });
+            let pool = &mut self.pools[pool_index];
+            let slot = match pool.exprs.iter().position(|(_, e)| *e == expr) {
+                Some(slot) => slot,
+                None => {
+                    pool.exprs.push((self.line, expr));
+                    pool.exprs.len() - 1
+                }
+            };
+            literal = Some((pool_index, slot));
+        }
+
+        let size = if mnemonic == "bl" { 4 } else { 2 };
+        self.push(
+            size,
+            ItemKind::Instruction {
+                mnemonic,
+                operands,
+                literal,
+            },
+        )
+    }
+
+    fn parse_directive(&mut self, directive: &str, operands: &[String]) -> Result<(), String> {
+        let pc = self.addr.unwrap_or(0);
+        match directive {
+            ".org" => {
+                expect_operands(directive, operands, 1)?;
+                self.flush_pool()?;
+                let addr = self.eval(&operands[0], pc)?;
+                self.addr =
+                    Some(u32::try_from(addr).map_err(|_| format!("invalid address {:#x}", addr))?);
+                self.push(0, ItemKind::Org)
+            }
+            ".thumb" | ".thumb_func" | ".text" => Ok(()),
+            ".code" if operands.len() == 1 && operands[0] == "16" => Ok(()),
+            ".arm" | ".code" => Err("only Thumb code is supported".to_string()),
+            ".align" | ".balign" => {
+                let n = match operands.first() {
+                    Some(n) => self.eval(n, pc)?,
+                    None => 2,
+                };
+                let align = if directive == ".align" {
+                    if !(0..=16).contains(&n) {
+                        return Err(format!("invalid alignment 2^{}", n));
+                    }
+                    1 << n
+                } else {
+                    u32::try_from(n)
+                        .ok()
+                        .filter(|n| n.is_power_of_two())
+                        .ok_or_else(|| format!("alignment {} is not a power of two", n))?
+                };
+                self.pad_to(align, 0)
+            }
+            ".byte" | ".hword" | ".short" | ".half" | ".word" | ".long" => {
+                let size = match directive {
+                    ".byte" => 1,
+                    ".word" | ".long" => 4,
+                    _ => 2,
+                };
+                self.push(
+                    size * operands.len(),
+                    ItemKind::Data {
+                        size,
+                        exprs: operands.to_vec(),
+                    },
+                )
+            }
+            ".ascii" | ".asciz" => {
+                let mut buf = vec![];
+                for operand in operands {
+                    buf.extend(parse_string(operand)?);
+                    if directive == ".asciz" {
+                        buf.push(0);
+                    }
+                }
+                self.push(buf.len(), ItemKind::Bytes(buf))
+            }
+            ".space" | ".skip" => {
+                if operands.is_empty() || operands.len() > 2 {
+                    return Err(format!("{} expects 1 or 2 operands", directive));
+                }
+                let size = self.eval(&operands[0], pc)?;
+                let size = usize::try_from(size)
+                    .ok()
+                    .filter(|size| *size <= 0x02000000)
+                    .ok_or_else(|| format!("invalid size {}", size))?;
+                let fill = match operands.get(1) {
+                    Some(fill) => check_range("fill", self.eval(fill, pc)?, -0x80, 0xff, 1)? as u8,
+                    None => 0,
+                };
+                self.push(size, ItemKind::Bytes(vec![fill; size]))
+            }
+            ".equ" | ".set" => {
+                expect_operands(directive, operands, 2)?;
+                let value = self.eval(&operands[1], pc)?;
+                self.define(&operands[0], value)
+            }
+            ".pool" | ".ltorg" => self.flush_pool(),
+            _ => Err(format!("unknown directive {}", directive)),
+        }
+    }
+
+    fn emit(&self) -> Result<Assembly, Error> {
+        let mut chunks: Vec<(usize, Chunk)> = vec![];
+        for item in self.items.iter() {
+            let error = |message| Error {
+                line: item.line,
+                message,
+            };
+            let contents = match &item.kind {
+                ItemKind::Org => {
+                    chunks.push((
+                        item.line,
+                        Chunk {
+                            rom_addr: item.addr,
+                            contents: vec![],
+                        },
+                    ));
+                    continue;
+                }
+                ItemKind::Instruction {
+                    mnemonic,
+                    operands,
+                    literal,
+                } => {
+                    if item.addr % 2 != 0 {
+                        return Err(error(format!(
+                            "instruction at odd address {:#010x}",
+                            item.addr
+                        )));
+                    }
+                    let literal_addr =
+                        literal.map(|(pool, slot)| self.pools[pool].addr + slot as u32 * 4);
+                    self.encode(mnemonic, operands, literal_addr, item.addr)
+                        .map_err(error)?
+                        .into_iter()
+                        .flat_map(|v| v.to_le_bytes())
+                        .collect::<Vec<_>>()
+                }
+                ItemKind::Data { size, exprs } => {
+                    let mut buf = vec![];
+                    for (i, expr) in exprs.iter().enumerate() {
+                        let v = self
+                            .eval(expr, item.addr + (i * size) as u32)
+                            .map_err(error)?;
+                        let (min, max) = match size {
+                            1 => (-0x80, 0xff),
+                            2 => (-0x8000, 0xffff),
+                            _ => (-0x80000000, 0xffffffff),
+                        };
+                        check_range("value", v, min, max, 1).map_err(error)?;
+                        buf.extend_from_slice(&(v as u32).to_le_bytes()[..*size]);
+                    }
+                    buf
+                }
+                ItemKind::Bytes(buf) => buf.clone(),
+                ItemKind::Pool(index) => {
+                    let pool = &self.pools[*index];
+                    let mut buf = vec![];
+                    for (i, (line, expr)) in pool.exprs.iter().enumerate() {
+                        let v = self
+                            .eval(expr, pool.addr + i as u32 * 4)
+                            .and_then(|v| {
+                                check_range("literal", v, -0x80000000, 0xffffffff, 1).map(|_| v)
+                            })
+                            .map_err(|message| Error {
+                                line: *line,
+                                message,
+                            })?;
+                        buf.extend_from_slice(&(v as u32).to_le_bytes());
+                    }
+                    buf
+                }
+            };
+            chunks
+                .last_mut()
+                .expect("items before any .org are rejected while parsing")
+                .1
+                .contents
+                .extend(contents);
+        }
+
+        chunks.retain(|(_, chunk)| !chunk.contents.is_empty());
+        chunks.sort_by_key(|(_, chunk)| chunk.rom_addr);
+        for pair in chunks.windows(2) {
+            let (_, prev) = &pair[0];
+            let (line, next) = &pair[1];
+            if prev.rom_addr as u64 + prev.contents.len() as u64 > next.rom_addr as u64 {
+                return Err(Error {
+                    line: *line,
+                    message: format!(
+                        "code at {:#010x} overlaps code at {:#010x}",
+                        next.rom_addr, prev.rom_addr
+                    ),
+                });
+            }
+        }
+
+        Ok(Assembly {
+            chunks: chunks.into_iter().map(|(_, chunk)| chunk).collect(),
+            labels: self.labels.clone(),
+        })
+    }
+
+    fn parse_mem(&self, s: &str, pc: u32) -> Result<(u8, MemOffset), String> {
+        let inner = s
+            .strip_prefix('[')
+            .and_then(|s| s.strip_suffix(']'))
+            .ok_or_else(|| format!("expected memory operand, got {}", s))?;
+        let parts = split_operands(inner);
+        match parts.as_slice() {
+            [base] => Ok((reg(base)?, MemOffset::Imm(0))),
+            [base, offset] if is_imm(offset) => {
+                Ok((reg(base)?, MemOffset::Imm(self.imm(offset, pc)?)))
+            }
+            [base, offset] => Ok((reg(base)?, MemOffset::Reg(low_reg(offset)?))),
+            _ => Err(format!("invalid memory operand {}", s)),
+        }
+    }
+
+    /// Computes the offset of a PC-relative word load or address from `pc` to `target`.
+    fn pc_relative_word(&self, target: i64, pc: u32) -> Result<u16, String> {
+        check_range(
+            "pc-relative offset",
+            target - ((pc as i64 + 4) & !3),
+            0,
+            1020,
+            4,
+        )
+    }
+
+    /// Computes the halfword offset of a branch from `pc` to `target`, which must fit in a signed `bits + 1` bit byte offset.
+    fn branch_offset(&self, target: &str, pc: u32, bits: u32) -> Result<u32, String> {
+        let target = self.eval(target.strip_prefix('#').unwrap_or(target), pc)?;
+        let offset = target - (pc as i64 + 4);
+        let max = 1i64 << bits;
+        check_range("branch offset", offset, -max, max - 2, 2)?;
+        Ok((offset >> 1) as u32)
+    }
+
+    fn encode(
+        &self,
+        mnemonic: &str,
+        ops: &[String],
+        literal_addr: Option<u32>,
+        pc: u32,
+    ) -> Result<Vec<u16>, String> {
+        let is_sp = |s: &str| parse_reg(s) == Some(13);
+        let is_pc = |s: &str| parse_reg(s) == Some(15);
+        let is_low = |s: &str| parse_reg(s).is_some_and(|r| r < 8);
+
+        let insn = match mnemonic {
+            "nop" => {
+                expect_operands(mnemonic, ops, 0)?;
+                0x46c0
+            }
+
+            "lsl" | "lsr" | "asr" => {
+                let op = match mnemonic {
+                    "lsl" => 0,
+                    "lsr" => 1,
+                    _ => 2,
+                };
+                match ops {
+                    [rd, rs, imm] | [rd @ rs, imm] if is_imm(imm) => {
+                        let imm = self.imm(imm, pc)?;
+                        let imm = if op == 0 {
+                            check_range("shift", imm, 0, 31, 1)?
+                        } else {
+                            check_range("shift", imm, 1, 32, 1)? & 0x1f
+                        };
+                        op << 11 | imm << 6 | low_reg(rs)? << 3 | low_reg(rd)?
+                    }
+                    [rd, rs] => 0x4000 | (op + 2) << 6 | low_reg(rs)? << 3 | low_reg(rd)?,
+                    _ => return Err(format!("invalid operands for {}", mnemonic)),
+                }
+            }
+
+            "add" | "sub" => {
+                let sub = mnemonic == "sub";
+                match ops {
+                    [sp, imm] | [sp, _, imm] if is_sp(sp) && is_imm(imm) => {
+                        if ops.len() == 3 && !is_sp(&ops[1]) {
+                            return Err(format!("invalid operands for {}", mnemonic));
+                        }
+                        let imm = self.imm(imm, pc)?;
+                        let imm = if sub { -imm } else { imm };
+                        if imm < 0 {
+                            0xb080 | check_range("offset", -imm, 0, 508, 4)?
+                        } else {
+                            0xb000 | check_range("offset", imm, 0, 508, 4)?
+                        }
+                    }
+                    [rd, base, imm] if !sub && (is_sp(base) || is_pc(base)) && is_imm(imm) => {
+                        let imm = check_range("offset", self.imm(imm, pc)?, 0, 1020, 4)?;
+                        (if is_sp(base) { 0xa800 } else { 0xa000 }) | low_reg(rd)? << 8 | imm
+                    }
+                    [rd, rs, imm] if is_imm(imm) => {
+                        let imm = check_range("immediate", self.imm(imm, pc)?, 0, 7, 1)?;
+                        0x1c00 | (sub as u16) << 9 | imm << 6 | low_reg(rs)? << 3 | low_reg(rd)?
+                    }
+                    [rd, rs, rn] => {
+                        0x1800
+                            | (sub as u16) << 9
+                            | low_reg(rn)? << 6
+                            | low_reg(rs)? << 3
+                            | low_reg(rd)?
+                    }
+                    [rd, imm] if is_imm(imm) => {
+                        let imm = check_range("immediate", self.imm(imm, pc)?, 0, 255, 1)?;
+                        (if sub { 0x3800 } else { 0x3000 }) | low_reg(rd)? << 8 | imm
+                    }
+                    [rd, rs] if is_low(rd) && is_low(rs) => {
+                        0x1800
+                            | (sub as u16) << 9
+                            | low_reg(rs)? << 6
+                            | low_reg(rd)? << 3
+                            | low_reg(rd)?
+                    }
+                    [rd, rs] if !sub => hi_reg_op(0, rd, rs)?,
+                    _ => return Err(format!("invalid operands for {}", mnemonic)),
+                }
+            }
+
+            "mov" | "cmp" => {
+                let cmp = mnemonic == "cmp";
+                match ops {
+                    [rd, imm] if is_imm(imm) => {
+                        let imm = check_range("immediate", self.imm(imm, pc)?, 0, 255, 1)?;
+                        (if cmp { 0x2800 } else { 0x2000 }) | low_reg(rd)? << 8 | imm
+                    }
+                    // mov between low registers is `add rd, rs, #0`, as Thumb has no flag-setting low register mov.
+                    [rd, rs] if is_low(rd) && is_low(rs) => {
+                        if cmp {
+                            0x4280 | low_reg(rs)? << 3 | low_reg(rd)?
+                        } else {
+                            0x1c00 | low_reg(rs)? << 3 | low_reg(rd)?
+                        }
+                    }
+                    [rd, rs] => hi_reg_op(if cmp { 1 } else { 2 }, rd, rs)?,
+                    _ => return Err(format!("invalid operands for {}", mnemonic)),
+                }
+            }
+
+            "bx" => {
+                expect_operands(mnemonic, ops, 1)?;
+                0x4700 | (reg(&ops[0])? as u16) << 3
+            }
+
+            _ if ALU_OPS.iter().any(|(name, _)| *name == mnemonic) => {
+                let op = ALU_OPS
+                    .iter()
+                    .find(|(name, _)| *name == mnemonic)
+                    .unwrap()
+                    .1;
+                let (rd, rs) = match ops {
+                    [rd, rs] => (rd, rs),
+                    // Allow the three operand form, as long as the destination is also one of the sources.
+                    [rd, rs, rm] if mnemonic == "mul" && reg(rd)? == reg(rm)? => (rd, rs),
+                    [rd, rn, rs] if mnemonic != "mul" && reg(rd)? == reg(rn)? => (rd, rs),
+                    _ => return Err(format!("invalid operands for {}", mnemonic)),
+                };
+                0x4000 | op << 6 | low_reg(rs)? << 3 | low_reg(rd)?
+            }
+
+            "adr" => {
+                expect_operands(mnemonic, ops, 2)?;
+                let target = self.eval(&ops[1], pc)?;
+                0xa000 | low_reg(&ops[0])? << 8 | self.pc_relative_word(target, pc)?
+            }
+
+            "ldr" | "str" | "ldrb" | "strb" | "ldrh" | "strh" | "ldsb" | "ldrsb" | "ldsh"
+            | "ldrsh" => {
+                expect_operands(mnemonic, ops, 2)?;
+                let rd = low_reg(&ops[0])?;
+                let load = mnemonic.starts_with("ld");
+
+                if let Some(literal_addr) = literal_addr {
+                    return Ok(vec![
+                        0x4800
+                            | rd << 8
+                            | self
+                                .pc_relative_word(literal_addr as i64, pc)
+                                .map_err(|e| {
+                                    format!("{} (use .pool to place the literal pool closer)", e)
+                                })?,
+                    ]);
+                }
+
+                if !ops[1].starts_with('[') {
+                    if mnemonic != "ldr" {
+                        return Err(format!("invalid operands for {}", mnemonic));
+                    }
+                    let target = self.eval(&ops[1], pc)?;
+                    return Ok(vec![0x4800 | rd << 8 | self.pc_relative_word(target, pc)?]);
+                }
+
+                let (base, offset) = self.parse_mem(&ops[1], pc)?;
+                match (mnemonic, base, offset) {
+                    ("ldr", 15, MemOffset::Imm(imm)) => {
+                        0x4800 | rd << 8 | check_range("offset", imm, 0, 1020, 4)?
+                    }
+                    ("ldr" | "str", 13, MemOffset::Imm(imm)) => {
+                        (if load { 0x9800 } else { 0x9000 })
+                            | rd << 8
+                            | check_range("offset", imm, 0, 1020, 4)?
+                    }
+                    (_, base, MemOffset::Reg(ro)) => {
+                        let base = low_reg(&format!("r{}", base))?;
+                        let op = match mnemonic {
+                            "str" => 0x5000,
+                            "strh" => 0x5200,
+                            "strb" => 0x5400,
+                            "ldsb" | "ldrsb" => 0x5600,
+                            "ldr" => 0x5800,
+                            "ldrh" => 0x5a00,
+                            "ldrb" => 0x5c00,
+                            _ => 0x5e00,
+                        };
+                        op | ro << 6 | base << 3 | rd
+                    }
+                    ("ldsb" | "ldrsb" | "ldsh" | "ldrsh", _, MemOffset::Imm(_)) => {
+                        return Err(format!("{} only supports register offsets", mnemonic));
+                    }
+                    (_, base, MemOffset::Imm(imm)) => {
+                        let base = low_reg(&format!("r{}", base))?;
+                        let (op, imm) = match mnemonic {
+                            "str" | "ldr" => (
+                                if load { 0x6800 } else { 0x6000 },
+                                check_range("offset", imm, 0, 124, 4)?,
+                            ),
+                            "strb" | "ldrb" => (
+                                if load { 0x7800 } else { 0x7000 },
+                                check_range("offset", imm, 0, 31, 1)?,
+                            ),
+                            _ => (
+                                if load { 0x8800 } else { 0x8000 },
+                                check_range("offset", imm, 0, 62, 2)?,
+                            ),
+                        };
+                        op | imm << 6 | base << 3 | rd
+                    }
+                }
+            }
+
+            "push" | "pop" => {
+                expect_operands(mnemonic, ops, 1)?;
+                let rlist = parse_rlist(&ops[0])?;
+                let extra = if mnemonic == "push" { 1 << 14 } else { 1 << 15 };
+                if rlist & !(0xff | extra) != 0 {
+                    return Err(format!(
+                        "{} can only use r0-r7 and {}",
+                        mnemonic,
+                        if mnemonic == "push" { "lr" } else { "pc" }
+                    ));
+                }
+                (if mnemonic == "push" { 0xb400 } else { 0xbc00 })
+                    | if rlist & extra != 0 { 0x100 } else { 0 }
+                    | (rlist & 0xff)
+            }
+
+            "stmia" | "ldmia" | "stm" | "ldm" => {
+                expect_operands(mnemonic, ops, 2)?;
+                let rb = ops[0]
+                    .strip_suffix('!')
+                    .ok_or_else(|| format!("{} requires writeback (rb!)", mnemonic))?;
+                let rlist = parse_rlist(&ops[1])?;
+                if rlist & !0xff != 0 {
+                    return Err(format!("{} can only use r0-r7", mnemonic));
+                }
+                (if mnemonic.starts_with("ld") {
+                    0xc800
+                } else {
+                    0xc000
+                }) | low_reg(rb)? << 8
+                    | rlist
+            }
+
+            "swi" | "svc" => {
+                expect_operands(mnemonic, ops, 1)?;
+                let imm = self.eval(ops[0].strip_prefix('#').unwrap_or(&ops[0]), pc)?;
+                0xdf00 | check_range("comment", imm, 0, 255, 1)?
+            }
+
+            "bl" => {
+                expect_operands(mnemonic, ops, 1)?;
+                let offset = self.branch_offset(&ops[0], pc, 22)?;
+                return Ok(vec![
+                    0xf000 | ((offset >> 11) & 0x7ff) as u16,
+                    0xf800 | (offset & 0x7ff) as u16,
+                ]);
+            }
+
+            "b" | "bal" => {
+                expect_operands(mnemonic, ops, 1)?;
+                0xe000 | (self.branch_offset(&ops[0], pc, 11)? & 0x7ff) as u16
+            }
+
+            _ => {
+                let cond = mnemonic
+                    .strip_prefix('b')
+                    .and_then(|cond| CONDITIONS.iter().find(|(name, _)| *name == cond))
+                    .ok_or_else(|| format!("unknown instruction {}", mnemonic))?
+                    .1;
+                expect_operands(mnemonic, ops, 1)?;
+                0xd000 | cond << 8 | (self.branch_offset(&ops[0], pc, 8)? & 0xff) as u16
+            }
+        };
+        Ok(vec![insn])
+    }
+}
+
+/// Encodes an add, cmp or mov where at least one register may be r8-r15.
+fn hi_reg_op(op: u16, rd: &str, rs: &str) -> Result<u16, String> {
+    let (rd, rs) = (reg(rd)? as u16, reg(rs)? as u16);
+    Ok(0x4400 | op << 8 | (rd >> 3) << 7 | rs << 3 | (rd & 7))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble_at(source: &str, org: u32) -> Result<Assembly, Error> {
+        assemble(
+            source,
+            &Options {
+                org: Some(org),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn halfwords(contents: &[u8]) -> Vec<u16> {
+        contents
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn test_encodings() {
+        let assembly = assemble_at(
+            "
+            lsl r0, r1, #2
+            lsrs r0, r1, #32
+            add r0, r1, r2
+            sub r0, r1, #3
+            mov r0, #1
+            cmp r0, #0xff
+            mov r0, r1
+            cmp r0, r1
+            mul r0, r1
+            mvn r2, r3
+            mov r8, r0
+            add r0, r10
+            bx lr
+            nop
+            ldr r0, [r1, #4]
+            ldrh r0, [r1, #2]
+            strb r0, [r1, r2]
+            ldrsh r0, [r1, r2]
+            ldr r0, [sp, #8]
+            add r0, sp, #8
+            add sp, #-8
+            sub sp, #8
+            push {r4-r7, lr}
+            pop {r4-r7, pc}
+            stmia r0!, {r1, r2}
+            swi 6
+            ",
+            0x08000000,
+        )
+        .unwrap();
+        assert_eq!(assembly.chunks.len(), 1);
+        assert_eq!(assembly.chunks[0].rom_addr, 0x08000000);
+        assert_eq!(
+            halfwords(&assembly.chunks[0].contents),
+            vec![
+                0x0088, 0x0808, 0x1888, 0x1ec8, 0x2001, 0x28ff, 0x1c08, 0x4288, 0x4348, 0x43da,
+                0x4680, 0x4450, 0x4770, 0x46c0, 0x6848, 0x8848, 0x5488, 0x5e88, 0x9802, 0xa802,
+                0xb082, 0xb082, 0xb5f0, 0xbdf0, 0xc006, 0xdf06,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branches_and_labels() {
+        let assembly = assemble(
+            "
+            .org 0x08000000
+            start: beq start
+                b start
+                bl far
+            .org 0x08000100
+            far:
+                bx lr
+            ",
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(assembly.labels["start"], 0x08000000);
+        assert_eq!(assembly.labels["far"], 0x08000100);
+        assert_eq!(
+            halfwords(&assembly.chunks[0].contents),
+            vec![0xd0fe, 0xe7fd, 0xf000, 0xf87c]
+        );
+        assert_eq!(assembly.chunks[1].rom_addr, 0x08000100);
+    }
+
+    #[test]
+    fn test_branch_errors() {
+        let err = assemble(
+            "
+            .org 0x08000000
+            start:
+                beq start
+                b start
+                bl far
+            ",
+            &Options::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.line, 6);
+        assert_eq!(err.message, "undefined symbol far");
+
+        let err = assemble(
+            "
+            .org 0x08000000
+            start:
+                beq start
+                bne.w_is_not_a_thing
+            ",
+            &Options::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.line, 5);
+        assert_eq!(err.message, "unknown instruction bne.w_is_not_a_thing");
+    }
+
+    #[test]
+    fn test_align_overflow() {
+        let err = assemble(".org 0xfffffffe\n.align 4", &Options::default())
+            .err()
+            .unwrap();
+        assert_eq!(err.line, 2);
+        assert!(err.message.starts_with("cannot align"));
+    }
+
+    #[test]
+    fn test_bl_range() {
+        assert!(assemble_at("bl 0x08400002", 0x08000000).is_ok());
+        let err = assemble_at("nop\nbl 0x08400006", 0x08000000).err().unwrap();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("out of range"));
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let assembly = assemble_at(
+            "
+            .equ target, 0x08000100
+            ldr r0, =0x12345678
+            ldr r1, =target
+            ldr r2, =0x12345678
+            bx lr
+            .pool
+            .byte 1, 2
+            ldr r3, =target
+            ",
+            0x08000000,
+        )
+        .unwrap();
+        let contents = &assembly.chunks[0].contents;
+        assert_eq!(
+            halfwords(&contents[..8]),
+            vec![0x4801, 0x4902, 0x4a00, 0x4770]
+        );
+        assert_eq!(&contents[8..16], b"\x78\x56\x34\x12\x00\x01\x00\x08");
+        assert_eq!(&contents[16..18], b"\x01\x02");
+        // The second pool is placed at the end, aligned to 4 bytes.
+        assert_eq!(halfwords(&contents[18..20]), vec![0x4b00]);
+        assert_eq!(&contents[20..24], b"\x00\x01\x00\x08");
+    }
+
+    #[test]
+    fn test_external_symbols_and_data() {
+        let assembly = assemble(
+            "
+            .equ count, 3
+            .org base + 2
+                .hword count * 2, -1
+                .align 2
+                .word base
+                .asciz \"hi\"
+            ",
+            &Options {
+                symbols: [("base".to_string(), 0x08001000)].into(),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(assembly.chunks[0].rom_addr, 0x08001002);
+        assert_eq!(
+            assembly.chunks[0].contents,
+            b"\x06\x00\xff\xff\x00\x00\x00\x10\x00\x08hi\x00"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(
+            assemble("mov r0, #1", &Options::default())
+                .err()
+                .unwrap()
+                .message
+                .contains(".org")
+        );
+        assert_eq!(
+            assemble_at("\nmov r0, #256", 0x08000000)
+                .err()
+                .unwrap()
+                .line,
+            2
+        );
+        assert!(
+            assemble_at("ldr r0, =missing", 0x08000000)
+                .err()
+                .unwrap()
+                .message
+                .contains("undefined symbol missing")
+        );
+        assert!(
+            assemble_at(
+                ".org 0x08000000\n.word 0\n.org 0x08000002\n.word 0",
+                0x08000000
+            )
+            .err()
+            .unwrap()
+            .message
+            .contains("overlaps")
+        );
+        assert!(assemble_at("mov r8, #1", 0x08000000).is_err());
+        assert!(assemble_at(".arm", 0x08000000).is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use fltk::prelude::*;
 
-use crate::{config, console, mods, path};
+use crate::{cheats, config, console, mods, path, repl};
 
 struct ConsoleWriter<'a> {
     terminal: &'a mut fltk::terminal::Terminal,
@@ -94,6 +94,7 @@ impl Drop for FltkAppLockGuard {
 
 pub struct StartRequest {
     pub enabled_mods: Vec<(String, std::sync::Arc<mods::Mod>)>,
+    pub enabled_cheats: std::collections::BTreeSet<String>,
     pub disable_autostart: bool,
 }
 
@@ -116,12 +117,17 @@ fn make_main_tile(
 
     let mut refresh_button = fltk::button::Button::default()
         .with_label("Refresh") // TODO: Localize.
-        .with_size(toolbar_group.width() / 2, toolbar_group.height());
+        .with_size(toolbar_group.width() / 3, toolbar_group.height());
 
     let mut open_folder_button = fltk::button::Button::default()
         .with_label("Open Folder") // TODO: Localize.
-        .with_size(toolbar_group.width() / 2, toolbar_group.height())
-        .with_pos(toolbar_group.width() / 2, 0);
+        .with_size(toolbar_group.width() / 3, toolbar_group.height())
+        .with_pos(toolbar_group.width() / 3, 0);
+
+    let mut cheats_button = fltk::button::Button::default()
+        .with_label("Cheats") // TODO: Localize.
+        .with_size(toolbar_group.width() / 3, toolbar_group.height())
+        .with_pos(toolbar_group.width() * 2 / 3, 0);
 
     toolbar_group.end();
 
@@ -135,6 +141,8 @@ fn make_main_tile(
         ModBinding,
     >::new()));
 
+    let enabled_cheats = std::sync::Arc::new(std::sync::Mutex::new(config.enabled_cheats.clone()));
+
     let mut play_button = fltk::button::Button::default()
         .with_label("►Play")
         .with_size(left_group.width(), 30)
@@ -190,7 +198,7 @@ fn make_main_tile(
                 return;
             };
 
-            let compatibility = mods::check_compatibility(&game_env, &binding.r#mod.info);
+            let compatibility = mods::check_compatibility(&game_env, &binding.r#mod);
             if compatibility.is_compatible() {
                 enabled_checkbox.show();
                 enabled_checkbox.set(binding.enabled);
@@ -297,6 +305,20 @@ fn make_main_tile(
                                             } else {
                                                 maud::html! { }
                                             })
+
+                                            (if !compatibility.missing_symbols.is_empty() {
+                                                maud::html! {
+                                                    li {
+                                                        font color="red" {
+                                                            "Symbols required by the mod could not be found for this game version ("
+                                                            (compatibility.missing_symbols.join(", "))
+                                                            ")"
+                                                        }
+                                                    }
+                                                }
+                                            } else {
+                                                maud::html! { }
+                                            })
                                         }
                                     }
                                 }
@@ -352,7 +374,7 @@ fn make_main_tile(
             for (i, (name, binding)) in mod_bindings.iter().enumerate() {
                 browser.add(&format!(
                     "{}@.{} {} v{}",
-                    if !mods::check_compatibility(&game_env, &binding.r#mod.info).is_compatible() {
+                    if !mods::check_compatibility(&game_env, &binding.r#mod).is_compatible() {
                         "@B88"
                     } else {
                         ""
@@ -393,7 +415,7 @@ fn make_main_tile(
                             name.clone(),
                             ModBinding {
                                 r#mod: std::sync::Arc::clone(&r#mod),
-                                enabled: mods::check_compatibility(&game_env, &r#mod.info)
+                                enabled: mods::check_compatibility(&game_env, &r#mod)
                                     .is_compatible()
                                     && currently_enabled.contains(&name),
                             },
@@ -471,8 +493,7 @@ fn make_main_tile(
                         name.clone(),
                         ModBinding {
                             r#mod: std::sync::Arc::clone(&r#mod),
-                            enabled: mods::check_compatibility(game_env, &r#mod.info)
-                                .is_compatible()
+                            enabled: mods::check_compatibility(game_env, &r#mod).is_compatible()
                                 && config.enabled_mods.contains(&name),
                         },
                     )
@@ -483,6 +504,23 @@ fn make_main_tile(
         update_browser_items(&mod_bindings);
     }
 
+    cheats_button.set_callback({
+        let mod_bindings = std::sync::Arc::clone(&mod_bindings);
+        let enabled_cheats = std::sync::Arc::clone(&enabled_cheats);
+        move |_| {
+            // Cheats from mods only apply while the mod is enabled, but are listed regardless so they can be set up ahead of time.
+            let cheats = cheats::scan(
+                mod_bindings
+                    .lock()
+                    .unwrap()
+                    .keys()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>(),
+            );
+            show_cheats_window(cheats, std::sync::Arc::clone(&enabled_cheats));
+        }
+    });
+
     refresh_button.set_callback({
         let mut refresh_browser = refresh_browser.clone();
         move |_| {
@@ -516,6 +554,7 @@ fn make_main_tile(
     let play_fn = {
         let mut tile = tile.clone();
         let mod_bindings = std::sync::Arc::clone(&mod_bindings);
+        let enabled_cheats = std::sync::Arc::clone(&enabled_cheats);
         let autostart_checkbox = autostart_checkbox.clone();
         move || {
             let mod_bindings = mod_bindings.lock().unwrap();
@@ -529,6 +568,7 @@ fn make_main_tile(
                             (name.to_string(), std::sync::Arc::clone(&binding.r#mod))
                         })
                         .collect(),
+                    enabled_cheats: enabled_cheats.lock().unwrap().clone(),
                     disable_autostart: !autostart_checkbox.value(),
                 },
             );
@@ -595,6 +635,47 @@ fn make_main_tile(
     tile
 }
 
+fn show_cheats_window(
+    cheats: Vec<cheats::Cheat>,
+    enabled_cheats: std::sync::Arc<std::sync::Mutex<std::collections::BTreeSet<String>>>,
+) {
+    let mut wind = fltk::window::Window::default()
+        .with_size(400, 300)
+        .with_label("Cheats"); // TODO: Localize.
+    wind.make_resizable(true);
+
+    let mut browser = fltk::browser::CheckBrowser::default_fill();
+    wind.resizable(&browser);
+    {
+        let enabled_cheats = enabled_cheats.lock().unwrap();
+        for cheat in cheats.iter() {
+            browser.add(
+                &match cheat.definition.game {
+                    Some(game) => {
+                        format!("{} ({})", cheat.id, serde_plain::to_string(&game).unwrap())
+                    }
+                    None => cheat.id.clone(),
+                },
+                enabled_cheats.contains(&cheat.id),
+            );
+        }
+    }
+    browser.set_callback(move |browser| {
+        let mut enabled_cheats = enabled_cheats.lock().unwrap();
+        for (i, cheat) in cheats.iter().enumerate() {
+            if browser.checked((i + 1) as i32) {
+                enabled_cheats.insert(cheat.id.clone());
+            } else {
+                enabled_cheats.remove(&cheat.id);
+            }
+        }
+    });
+
+    wind.end();
+    wind.make_modal(true);
+    wind.show();
+}
+
 fn make_window(
     game_env: &mods::GameEnv,
     start_sender: oneshot::Sender<StartRequest>,
@@ -611,10 +692,33 @@ fn make_window(
 
     wind.make_resizable(true);
 
-    let mut console = fltk::terminal::Terminal::default_fill();
-    wind.resizable(&console);
+    let mut console_group = fltk::group::Flex::default_fill().column();
+    let mut console = fltk::terminal::Terminal::default();
     console.set_ansi(true);
-    console.hide();
+    if config.developer_mode == Some(true) {
+        // Lines typed here are evaluated by the REPL, see repl.rs.
+        let mut repl_input = fltk::input::Input::default();
+        console_group.fixed(&repl_input, 24);
+        repl_input.set_trigger(fltk::enums::CallbackTrigger::EnterKeyAlways);
+        repl_input.set_callback({
+            let console = console.clone();
+            move |repl_input| {
+                let line = repl_input.value();
+                repl_input.set_value("");
+                let mut console = console.clone();
+                console.append(&format!("> {}\n", line));
+                std::thread::spawn(move || {
+                    let output = repl::eval(&line);
+                    if !output.is_empty() {
+                        console.append(&format!("{}\n", output));
+                    }
+                });
+            }
+        });
+    }
+    console_group.end();
+    wind.resizable(&console_group);
+    console_group.hide();
     let start_sender = std::sync::Arc::new(std::sync::Mutex::new(Some(start_sender)));
 
     let main_tile = make_main_tile(game_env, config, {
@@ -639,7 +743,7 @@ fn make_window(
                 }
             });
 
-            console.show();
+            console_group.show();
 
             start_sender.send(start_request).unwrap();
             if config.developer_mode != Some(true) {
@@ -1,3 +1,5 @@
+pub mod native;
+pub mod signatures;
 pub mod stage0;
 pub mod stage1;
 
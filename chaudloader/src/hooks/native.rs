@@ -0,0 +1,204 @@
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("retour: {0}")]
+    Retour(#[from] retour::Error),
+
+    #[error(
+        "hook at {target:#x} cannot be changed: [mod: {mod_name}] hooked the same function afterwards"
+    )]
+    NotTopmost { target: usize, mod_name: String },
+
+    #[error("function at {0:#x} is hooked by chaudloader itself and cannot be hooked by mods")]
+    LoaderOwned(usize),
+}
+
+struct Registration {
+    mod_name: String,
+    detour: retour::RawDetour,
+}
+
+// RawDetour only holds addresses of code, which can be patched from any thread.
+unsafe impl Send for Registration {}
+
+/// Native hooks installed by mods, by target address, in the order they were installed.
+///
+/// Hooks on the same function are chained: each new detour patches over the previous one, so its trampoline calls the previous detour, which calls the one before it, and so on until the original function. Only the most recent hook on a function may be toggled, since toggling one further down would patch over the hooks after it.
+///
+/// Hooks are never removed, since mods may keep calling their trampolines for as long as the game runs.
+static REGISTRY: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<usize, Vec<Registration>>>,
+> = std::sync::LazyLock::new(|| std::sync::Mutex::new(std::collections::HashMap::new()));
+
+/// Functions that the loader hooks itself. The loader toggles some of these hooks at any time (see `HooksDisableGuard`), which would tear out any hook chained onto them, so mods may not hook them.
+static LOADER_OWNED: std::sync::Mutex<std::collections::BTreeSet<usize>> =
+    std::sync::Mutex::new(std::collections::BTreeSet::new());
+
+/// Reserves `target` for the loader's own hooks. This must be called before any mod can install hooks.
+pub fn reserve(target: usize) {
+    LOADER_OWNED.lock().unwrap().insert(target);
+}
+
+#[derive(Clone, Copy)]
+pub struct HookId {
+    target: usize,
+    index: usize,
+}
+
+impl HookId {
+    pub fn target(&self) -> usize {
+        self.target
+    }
+}
+
+/// Installs and enables a hook that redirects calls of `target` to `detour`. Returns its ID and the address of the trampoline that calls the next hook in the chain, or the original function.
+pub unsafe fn install(
+    mod_name: &str,
+    target: usize,
+    detour: usize,
+) -> Result<(HookId, usize), Error> {
+    if LOADER_OWNED.lock().unwrap().contains(&target) {
+        return Err(Error::LoaderOwned(target));
+    }
+
+    let mut registry = REGISTRY.lock().unwrap();
+    let registrations = registry.entry(target).or_default();
+
+    if let Some(previous) = registrations.last() {
+        log::info!(
+            "[mod: {}] chaining hook at {:#x} after [mod: {}]",
+            mod_name,
+            target,
+            previous.mod_name
+        );
+        if !previous.detour.is_enabled() {
+            // Chaining onto a disabled hook would bake the original bytes into the new trampoline, so the disabled hook couldn't be enabled again.
+            return Err(Error::NotTopmost {
+                target,
+                mod_name: previous.mod_name.clone(),
+            });
+        }
+    }
+
+    let raw_detour = unsafe {
+        retour::RawDetour::new(
+            std::ptr::with_exposed_provenance(target),
+            std::ptr::with_exposed_provenance(detour),
+        )?
+    };
+    unsafe { raw_detour.enable()? };
+    let trampoline = (raw_detour.trampoline() as *const ()).expose_provenance();
+
+    registrations.push(Registration {
+        mod_name: mod_name.to_string(),
+        detour: raw_detour,
+    });
+    Ok((
+        HookId {
+            target,
+            index: registrations.len() - 1,
+        },
+        trampoline,
+    ))
+}
+
+pub unsafe fn set_enabled(id: HookId, enabled: bool) -> Result<(), Error> {
+    let registry = REGISTRY.lock().unwrap();
+    let registrations = &registry[&id.target];
+    if let Some(next) = registrations.get(id.index + 1) {
+        return Err(Error::NotTopmost {
+            target: id.target,
+            mod_name: next.mod_name.clone(),
+        });
+    }
+    let detour = &registrations[id.index].detour;
+    unsafe {
+        if enabled {
+            detour.enable()?;
+        } else {
+            detour.disable()?;
+        }
+    }
+    Ok(())
+}
+
+pub fn is_enabled(id: HookId) -> bool {
+    REGISTRY.lock().unwrap()[&id.target][id.index]
+        .detour
+        .is_enabled()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type AddFn = extern "C" fn(i32, i32) -> i32;
+
+    #[inline(never)]
+    extern "C" fn add(a: i32, b: i32) -> i32 {
+        std::hint::black_box(a) + std::hint::black_box(b)
+    }
+
+    static FIRST_TRAMPOLINE: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+    static SECOND_TRAMPOLINE: std::sync::atomic::AtomicUsize =
+        std::sync::atomic::AtomicUsize::new(0);
+
+    extern "C" fn double(a: i32, b: i32) -> i32 {
+        let original: AddFn = unsafe {
+            std::mem::transmute(FIRST_TRAMPOLINE.load(std::sync::atomic::Ordering::SeqCst))
+        };
+        original(a, b) * 2
+    }
+
+    extern "C" fn plus_one(a: i32, b: i32) -> i32 {
+        let original: AddFn = unsafe {
+            std::mem::transmute(SECOND_TRAMPOLINE.load(std::sync::atomic::Ordering::SeqCst))
+        };
+        original(a, b) + 1
+    }
+
+    #[test]
+    fn test_chain() {
+        let add = std::hint::black_box(add as AddFn);
+        unsafe {
+            let (first, trampoline) =
+                install("first", add as usize, (double as AddFn) as usize).unwrap();
+            FIRST_TRAMPOLINE.store(trampoline, std::sync::atomic::Ordering::SeqCst);
+            assert_eq!(add(1, 2), 6);
+
+            let (second, trampoline) =
+                install("second", add as usize, (plus_one as AddFn) as usize).unwrap();
+            SECOND_TRAMPOLINE.store(trampoline, std::sync::atomic::Ordering::SeqCst);
+            assert_eq!(add(1, 2), 7);
+
+            assert!(matches!(
+                set_enabled(first, false),
+                Err(Error::NotTopmost { mod_name, .. }) if mod_name == "second"
+            ));
+            assert!(is_enabled(first));
+
+            set_enabled(second, false).unwrap();
+            assert!(!is_enabled(second));
+            assert_eq!(add(1, 2), 6);
+
+            set_enabled(second, true).unwrap();
+            assert_eq!(add(1, 2), 7);
+        }
+    }
+
+    #[inline(never)]
+    extern "C" fn sub(a: i32, b: i32) -> i32 {
+        std::hint::black_box(a) - std::hint::black_box(b)
+    }
+
+    #[test]
+    fn test_loader_owned() {
+        let sub = std::hint::black_box(sub as AddFn);
+        reserve(sub as usize);
+        assert!(matches!(
+            unsafe { install("mod", sub as usize, (double as AddFn) as usize) },
+            Err(Error::LoaderOwned(target)) if target == sub as usize
+        ));
+        assert_eq!(sub(3, 1), 2);
+    }
+}
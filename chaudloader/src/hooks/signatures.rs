@@ -0,0 +1,287 @@
+use crate::pattern;
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct KnownAddress {
+    pub exe_crc32: u32,
+
+    /// Relative to the image base, which can change with ASLR.
+    pub rva: usize,
+}
+
+#[derive(serde::Deserialize, Clone, Default, Debug)]
+pub struct Signature {
+    #[serde(default)]
+    pub known: Vec<KnownAddress>,
+
+    #[serde(default)]
+    pub pattern: Option<String>,
+
+    #[serde(default)]
+    pub pattern_offset: isize,
+
+    #[serde(default)]
+    pub offsets: std::collections::HashMap<String, usize>,
+}
+
+/// Signatures for each hook, in the order they should be tried.
+pub type Signatures = std::collections::BTreeMap<String, Vec<Signature>>;
+
+const BUILTIN_SIGNATURES: &str = include_str!("signatures.toml");
+
+const SIGNATURES_FILE_NAME: &str = "chaudloader_signatures.toml";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum SignatureError {
+    #[error("missing offsets: {}", .0.join(", "))]
+    MissingOffsets(Vec<String>),
+
+    #[error("no known address for this exe and no pattern")]
+    NoAddressOrPattern,
+
+    #[error("invalid pattern: {0}")]
+    InvalidPattern(#[from] pattern::ParseError),
+
+    #[error("pattern not found")]
+    PatternNotFound,
+
+    #[error("address {0:#x} is outside of the .text section")]
+    OutsideText(usize),
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ResolveError {
+    #[error("no signatures")]
+    NoSignatures,
+
+    #[error("no signature resolved ({})", .0.iter().enumerate().map(|(i, e)| format!("#{}: {}", i + 1, e)).collect::<Vec<_>>().join("; "))]
+    Unresolved(Vec<SignatureError>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Method {
+    Known,
+    Pattern { matches: usize },
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Method::Known => write!(f, "known address"),
+            Method::Pattern { matches: 1 } => write!(f, "pattern"),
+            Method::Pattern { matches } => write!(f, "pattern (first of {} matches)", matches),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Resolved {
+    pub address: usize,
+    pub offsets: std::collections::HashMap<String, usize>,
+
+    /// Which signature resolved, counting from 0.
+    pub index: usize,
+    pub method: Method,
+}
+
+pub fn parse(s: &str) -> Result<Signatures, toml::de::Error> {
+    toml::from_str(s)
+}
+
+/// Loads the built-in signatures, with any from `chaudloader_signatures.toml` taking priority.
+pub fn load() -> Result<Signatures, anyhow::Error> {
+    let mut signatures = parse(BUILTIN_SIGNATURES)?;
+    match std::fs::read_to_string(SIGNATURES_FILE_NAME) {
+        Ok(s) => {
+            for (name, mut entries) in
+                parse(&s).map_err(|e| anyhow::anyhow!("{}: {}", SIGNATURES_FILE_NAME, e))?
+            {
+                entries.extend(signatures.remove(&name).unwrap_or_default());
+                signatures.insert(name, entries);
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e.into());
+        }
+    }
+    Ok(signatures)
+}
+
+fn resolve_one(
+    signature: &Signature,
+    text: &[u8],
+    image_base: usize,
+    exe_crc32: u32,
+    required_offsets: &[&str],
+) -> Result<(usize, Method), SignatureError> {
+    let missing_offsets = required_offsets
+        .iter()
+        .filter(|name| !signature.offsets.contains_key(**name))
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    if !missing_offsets.is_empty() {
+        return Err(SignatureError::MissingOffsets(missing_offsets));
+    }
+
+    let text_range = text.as_ptr_range();
+    let text_range = text_range.start as usize..text_range.end as usize;
+
+    if let Some(known) = signature.known.iter().find(|k| k.exe_crc32 == exe_crc32) {
+        let address = image_base.wrapping_add(known.rva);
+        if !text_range.contains(&address) {
+            return Err(SignatureError::OutsideText(address));
+        }
+        return Ok((address, Method::Known));
+    }
+
+    let pattern = signature
+        .pattern
+        .as_ref()
+        .ok_or(SignatureError::NoAddressOrPattern)?
+        .parse::<pattern::Pattern>()?;
+    let mut offsets = pattern.find_iter(text);
+    let offset = offsets.next().ok_or(SignatureError::PatternNotFound)?;
+    let address = (text_range.start + offset).wrapping_add_signed(signature.pattern_offset);
+    if !text_range.contains(&address) {
+        return Err(SignatureError::OutsideText(address));
+    }
+    Ok((
+        address,
+        Method::Pattern {
+            matches: 1 + offsets.count(),
+        },
+    ))
+}
+
+/// Resolves the address of a hooked function in the .text section by trying each signature in order.
+///
+/// Known addresses are relative to `image_base`. Signatures without all of `required_offsets` are skipped.
+pub fn resolve(
+    signatures: &[Signature],
+    text: &[u8],
+    image_base: usize,
+    exe_crc32: u32,
+    required_offsets: &[&str],
+) -> Result<Resolved, ResolveError> {
+    if signatures.is_empty() {
+        return Err(ResolveError::NoSignatures);
+    }
+
+    let mut errors = vec![];
+    for (index, signature) in signatures.iter().enumerate() {
+        match resolve_one(signature, text, image_base, exe_crc32, required_offsets) {
+            Ok((address, method)) => {
+                return Ok(Resolved {
+                    address,
+                    offsets: signature.offsets.clone(),
+                    index,
+                    method,
+                });
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    Err(ResolveError::Unresolved(errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin() {
+        let signatures = parse(BUILTIN_SIGNATURES).unwrap();
+        assert_eq!(
+            signatures.keys().collect::<Vec<_>>(),
+            ["bnk_load", "on_game_load", "pck_load"]
+        );
+        assert_eq!(signatures["on_game_load"][0].offsets["gba_state"], 0x3f8);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let text = [
+            0x90, 0x90, 0x48, 0x89, 0x5c, 0x24, 0x90, 0x48, 0x89, 0x5c, 0x24,
+        ];
+        let base = text.as_ptr() as usize;
+        let signatures = parse(&format!(
+            r#"
+            [[hook]]
+            pattern = "48 89 ?? 24"
+            pattern_offset = -1
+            offsets = {{ foo = 1 }}
+
+            [[hook.known]]
+            exe_crc32 = 0x12345678
+            rva = 0x1006
+            "#
+        ))
+        .unwrap();
+
+        let resolved = resolve(&signatures["hook"], &text, base - 0x1000, 0, &["foo"]).unwrap();
+        assert_eq!(resolved.address, base + 1);
+        assert_eq!(resolved.method, Method::Pattern { matches: 2 });
+        assert_eq!(resolved.offsets["foo"], 1);
+
+        let resolved = resolve(&signatures["hook"], &text, base - 0x1000, 0x12345678, &[]).unwrap();
+        assert_eq!(resolved.address, base + 6);
+        assert_eq!(resolved.method, Method::Known);
+
+        assert_eq!(
+            resolve(&signatures["hook"], &text, base, 0x12345678, &[]).err(),
+            Some(ResolveError::Unresolved(vec![SignatureError::OutsideText(
+                base + 0x1006
+            )]))
+        );
+
+        assert_eq!(
+            resolve(
+                &signatures["hook"],
+                &text,
+                base - 0x1000,
+                0,
+                &["foo", "bar"]
+            )
+            .err(),
+            Some(ResolveError::Unresolved(vec![
+                SignatureError::MissingOffsets(vec!["bar".to_string()])
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_resolve_fallback() {
+        let text = [0x90, 0x48, 0x89, 0x5c, 0x24];
+        let signatures = parse(
+            r#"
+            [[hook]]
+            pattern = "48 8b"
+
+            [[hook]]
+            pattern = "48 zz"
+
+            [[hook]]
+
+            [[hook]]
+            pattern = "48 89"
+            "#,
+        )
+        .unwrap();
+
+        let resolved = resolve(&signatures["hook"], &text, 0, 0, &[]).unwrap();
+        assert_eq!(resolved.address, text.as_ptr() as usize + 1);
+        assert_eq!(resolved.index, 3);
+
+        let err = resolve(&signatures["hook"][..3], &text, 0, 0, &[])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "no signature resolved (#1: pattern not found; #2: invalid pattern: invalid byte \"zz\" (expected two hex digits or ??); #3: no known address for this exe and no pattern)"
+        );
+        assert_eq!(
+            resolve(&[], &text, 0, 0, &[]).err(),
+            Some(ResolveError::NoSignatures)
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
-    assets, config, gui,
+    api, assets, bridge, cheats, config, debugger, gui,
     mods::{self, MODAUDIOFILES, MODFUNCTIONS, ModAudioFiles, ModFunctions},
+    pe, repl,
 };
 use byteorder::WriteBytesExt;
 use retour::static_detour;
@@ -83,12 +84,14 @@ fn init(
         hasher.finalize()
     };
 
+    let (image, sections) = process_game_image()
+        .inspect_err(|e| log::warn!("error while processing game sections: {e}"))
+        .unwrap_or_default();
     let game_env = mods::GameEnv {
         volume: game_volume,
         exe_crc32: exe_crc32,
-        sections: process_game_sections()
-            .inspect_err(|e| log::warn!("error while processing game sections: {e}"))
-            .unwrap_or_default(),
+        sections,
+        image,
     };
 
     std::thread::spawn({
@@ -122,7 +125,7 @@ fn init(
 
     let overlays = overlays
         .into_iter()
-        .map(|(k, v)| (k, std::rc::Rc::new(std::cell::RefCell::new(v))))
+        .map(|(k, v)| (k, std::sync::Arc::new(std::sync::Mutex::new(v))))
         .collect::<std::collections::HashMap<_, _>>();
 
     let start_request = gui_client.wait_for_start();
@@ -134,13 +137,16 @@ fn init(
     log::info!("enabled mods: {:?}", enabled_mods);
     config.enabled_mods = enabled_mods;
     config.disable_autostart = start_request.disable_autostart;
+    config.enabled_cheats = start_request.enabled_cheats.clone();
     config::save(&config)?;
 
-    let mut loaded_mods = std::collections::HashMap::<String, mods::State>::new();
+    let mut loaded_mods =
+        std::collections::HashMap::<String, std::rc::Rc<std::cell::RefCell<mods::State>>>::new();
     assert!(
         assets::REPLACER
             .set(std::sync::Mutex::new(assets::Replacer::new(
-                &serde_plain::to_string(&game_volume).unwrap()
+                &serde_plain::to_string(&game_volume).unwrap(),
+                &std::env::current_dir()?,
             )?))
             .is_ok()
     );
@@ -150,9 +156,18 @@ fn init(
             .is_ok()
     );
 
+    super::stage1::reserve_hook_targets();
+
+    if config.developer_mode == Some(true)
+        && let Some(port) = config.lua_debugger_port
+        && let Err(e) = debugger::start(port)
+    {
+        log::error!("cannot start lua debugger on port {port}: {e}");
+    }
+
     for (mod_name, r#mod) in start_request.enabled_mods {
         if let Err(e) = (|| -> Result<(), anyhow::Error> {
-            let compatibility = mods::check_compatibility(&game_env, &r#mod.info);
+            let compatibility = mods::check_compatibility(&game_env, &r#mod);
             if !compatibility.is_compatible() {
                 return Err(anyhow::format_err!(
                     "compatibility not met: {:?}",
@@ -174,28 +189,41 @@ fn init(
 
             let mod_state = std::rc::Rc::new(std::cell::RefCell::new(mods::State::new()));
 
-            {
-                let lua = mods::lua::new(
-                    &mod_name,
-                    &game_env,
-                    &r#mod.info,
-                    std::rc::Rc::clone(&mod_state),
-                    overlays.clone(),
-                )?;
-                lua.load(&r#mod.init_lua)
-                    .set_name("=init.lua")
-                    .set_mode(mlua::ChunkMode::Text)
-                    .exec()?;
+            match &r#mod.init {
+                mods::Init::Lua(init_lua) => {
+                    let lua = mods::lua::new(
+                        &mod_name,
+                        &game_env,
+                        &r#mod.info,
+                        std::rc::Rc::clone(&mod_state),
+                        std::sync::Arc::clone(&r#mod.symbols),
+                        overlays.clone(),
+                    )?;
+                    debugger::attach(&lua, &mod_name);
+                    lua.load(init_lua)
+                        .set_name("=init.lua")
+                        .set_mode(mlua::ChunkMode::Text)
+                        .exec()?;
+                    mods::lua::finish(&lua, &mod_name)?;
+                    if r#mod.info.persistent {
+                        mods::lua::keep_alive(&mod_name, lua);
+                    }
+                    log::info!("[mod: {}] Lua script complete", mod_name);
+                }
+                mods::Init::Wasm(init_wasm) => {
+                    let mut limits = mods::wasm::Limits::default();
+                    if let Some(fuel) = config.wasm_fuel_limit {
+                        limits.fuel = fuel;
+                    }
+                    if let Some(mib) = config.wasm_memory_limit_mib {
+                        limits.memory = mib * 1024 * 1024;
+                    }
+                    mods::wasm::run(&mod_name, init_wasm, overlays.clone(), &limits)?;
+                    log::info!("[mod: {}] WASM module complete", mod_name);
+                }
             }
-            log::info!("[mod: {}] Lua script complete", mod_name);
-
-            loaded_mods.insert(
-                mod_name.to_string(),
-                std::rc::Rc::try_unwrap(mod_state)
-                    .map_err(|_| anyhow::anyhow!("mod_state: Rc was not unique"))
-                    .unwrap()
-                    .into_inner(),
-            );
+
+            loaded_mods.insert(mod_name.to_string(), mod_state);
 
             Ok(())
         })() {
@@ -203,7 +231,24 @@ fn init(
         }
     }
 
-    let on_game_load_hook_needed = init_mod_functions(&loaded_mods)?;
+    let cheats = cheats::scan(loaded_mods.keys().map(|name| name.as_str()))
+        .into_iter()
+        .filter(|cheat| start_request.enabled_cheats.contains(&cheat.id))
+        .collect::<Vec<_>>();
+    let cheats_hook_needed = cheats::init(&cheats, &overlays);
+
+    let on_game_load_hook_needed = init_mod_functions(&game_env, &loaded_mods)?;
+    let repl_enabled = config.developer_mode == Some(true);
+    // The other game hooks need to know which game is running.
+    let on_game_load_hook_needed = on_game_load_hook_needed
+        || cheats_hook_needed
+        || config.memory_bridge_port.is_some()
+        || repl_enabled;
+
+    // RAM cheats are applied again every frame from the game's message loop, which also runs Lua code queued for the main
+    // thread: REPL evaluations, and on_game_load events if the game is loaded on another thread.
+    let peek_message_hook_needed =
+        cheats_hook_needed || repl_enabled || mods::lua::has_on_game_load();
 
     let (pck_hook_needed, bnk_hook_needed) = init_mod_audio()?;
 
@@ -211,48 +256,101 @@ fn init(
     std::thread_local! {
         static LOADED_MODS: std::cell::RefCell<
             Option<
-                std::collections::HashMap<String, mods::State>,
+                std::collections::HashMap<String, std::rc::Rc<std::cell::RefCell<mods::State>>>,
             >,
         > = const { std::cell::RefCell::new(None) };
     }
+    if repl_enabled
+        && let Err(e) = repl::start(
+            game_env.clone(),
+            loaded_mods.keys().cloned().collect(),
+            config.repl_port,
+        )
+    {
+        log::error!("cannot start repl: {e}");
+    }
     LOADED_MODS.set(Some(loaded_mods));
 
     // We are done with mod initialization! We can now go repack everything from our overlays.
+    //
+    // All modified archives are packed in parallel in the background, so the game only has to wait for the specific archive it opens.
     {
         let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
 
         let mut overlays = overlays;
         for (dat_filename, overlay) in overlays.drain() {
-            let overlay = std::rc::Rc::try_unwrap(overlay)
-                .map_err(|_| anyhow::anyhow!("overlay: Rc was not unique"))
+            let mut overlay = std::sync::Arc::try_unwrap(overlay)
+                .map_err(|_| anyhow::anyhow!("overlay: Arc was not unique"))
                 .unwrap()
-                .into_inner();
+                .into_inner()
+                .unwrap();
 
             if !overlay.has_overlaid_files() {
                 continue;
             }
 
-            let overlay = std::cell::RefCell::new(overlay);
-
-            // TODO: This path is a little wobbly, since it relies on BNLC specifying this weird relative path.
-            // We should canonicalize this path instead.
-            let dat_path = std::path::Path::new("..\\exe\\data").join(&dat_filename);
-            assets_replacer.add(&dat_path, move |writer| {
-                let mut overlay = overlay.borrow_mut();
+            let dat_path = std::path::Path::new("data").join(&dat_filename);
+            assets_replacer.add_eager("chaudloader", &dat_path, move |writer| {
                 overlay.pack_into(writer)
-            });
+            })?;
         }
     }
+    let signatures = super::signatures::load()
+        .inspect_err(|e| log::error!("cannot load hook signatures: {e}"))
+        .unwrap_or_default();
+    let resolve_hook = |name: &str, required_offsets: &[&str]| {
+        let text = game_env
+            .sections
+            .text
+            .ok_or_else(|| anyhow::anyhow!("no .text section"))?;
+        let resolved = super::signatures::resolve(
+            signatures.get(name).map(|v| &v[..]).unwrap_or(&[]),
+            text,
+            game_env.image.base,
+            game_env.exe_crc32,
+            required_offsets,
+        )?;
+        log::info!(
+            "hook {}: resolved at {:#x} by {} (signature #{})",
+            name,
+            resolved.address,
+            resolved.method,
+            resolved.index + 1
+        );
+        Ok::<_, anyhow::Error>(resolved)
+    };
+
     unsafe {
         super::stage1::install()?;
+
+        // Hooks that can't be resolved only disable the features that need them.
         if on_game_load_hook_needed {
-            super::stage1::install_on_game_load(&game_env)?;
+            match resolve_hook("on_game_load", &["gba_state_mov", "gba_state"]) {
+                Ok(resolved) => super::stage1::install_on_game_load(&resolved, &game_env)?,
+                Err(e) => {
+                    log::error!("hook on_game_load: {e}, on_game_load will not be called for mods")
+                }
+            }
+        }
+        if peek_message_hook_needed {
+            super::stage1::install_peek_message()?;
+        }
+        if let Some(port) = config.memory_bridge_port
+            && let Err(e) = bridge::start(port, game_env.volume)
+        {
+            log::error!("cannot start memory bridge on port {port}: {e}");
         }
         if pck_hook_needed {
-            super::stage1::install_pck_load(&game_env)?;
+            match resolve_hook("pck_load", &[]) {
+                Ok(resolved) => super::stage1::install_pck_load(&resolved)?,
+                Err(e) => log::error!("hook pck_load: {e}, mod .pck files will not be loaded"),
+            }
         }
         if bnk_hook_needed {
-            super::stage1::install_bnk_load(&game_env)?;
+            match resolve_hook("bnk_load", &[]) {
+                Ok(resolved) => super::stage1::install_bnk_load(&resolved)?,
+                Err(e) => log::error!("hook bnk_load: {e}, mod .bnk files will not be loaded"),
+            }
         }
     }
     Ok(())
@@ -553,26 +651,29 @@ pub unsafe fn install() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-fn process_game_sections() -> Result<mods::Sections, anyhow::Error> {
+fn process_game_image() -> Result<(pe::Image, mods::Sections), anyhow::Error> {
     // Get sections of game executable
     let module = unsafe {
         windows_libloader::ModuleHandle::get(&std::env::current_exe()?.to_string_lossy())?
             .get_base_address() as *const u8
     };
-    let sections = object::read::pe::PeFile64::parse(unsafe {
+    let size_of_image = pe::size_of_image(unsafe {
         std::slice::from_raw_parts(
             module, 0x1000, // probably enough
         )
-    })?
-    .section_table();
+    })?;
+    let image = pe::Image::parse(
+        unsafe { std::slice::from_raw_parts(module, size_of_image) },
+        module as usize,
+    )?;
 
     // Make all sections read/write
     unsafe {
-        for section in sections.iter() {
-            let address = module.add(section.virtual_address.get(object::LittleEndian) as usize);
+        for section in image.sections.iter() {
+            let address = module.add(section.rva as usize);
             if let Err(e) = region::protect(
                 address,
-                section.virtual_size.get(object::LittleEndian) as usize,
+                section.size as usize,
                 region::Protection::READ_WRITE_EXECUTE,
             ) {
                 log::warn!("Cannot unprotect section @ {:#?}: {e}", address);
@@ -581,21 +682,26 @@ fn process_game_sections() -> Result<mods::Sections, anyhow::Error> {
     }
 
     // Return all recognized sections
-    Ok(mods::Sections {
-        // For text section, get the first section and check that it has the correct flags
-        text: sections
-            .section(1)
-            .map_err(Into::into)
+    let sections = mods::Sections {
+        // For text section, look it up by name, falling back to the first code section, and check that it has the correct flags
+        text: image
+            .section(".text")
+            .or_else(|| {
+                image
+                    .sections
+                    .iter()
+                    .find(|s| s.characteristics & object::pe::IMAGE_SCN_CNT_CODE != 0)
+            })
+            .ok_or_else(|| anyhow::anyhow!("no code section"))
             .and_then(|s| {
-                if (s.characteristics.get(object::LittleEndian)
+                if (s.characteristics
                     & (object::pe::IMAGE_SCN_CNT_CODE
                         | object::pe::IMAGE_SCN_MEM_EXECUTE
                         | object::pe::IMAGE_SCN_MEM_READ))
                     != 0
                 {
-                    let (start, size) = s.pe_address_range();
                     Ok(unsafe {
-                        std::slice::from_raw_parts(module.add(start as usize), size as usize)
+                        std::slice::from_raw_parts(module.add(s.rva as usize), s.size as usize)
                     })
                 } else {
                     Err(anyhow::anyhow!("segment does not have correct flags"))
@@ -603,43 +709,67 @@ fn process_game_sections() -> Result<mods::Sections, anyhow::Error> {
             })
             .inspect_err(|e| log::error!("cannot find .text segment: {e}"))
             .ok(),
-    })
+    };
+    Ok((image, sections))
 }
 
 fn init_mod_functions(
-    loaded_mods: &std::collections::HashMap<String, mods::State>,
+    game_env: &mods::GameEnv,
+    loaded_mods: &std::collections::HashMap<String, std::rc::Rc<std::cell::RefCell<mods::State>>>,
 ) -> Result<bool, anyhow::Error> {
     assert!(
         MODFUNCTIONS
             .set(std::sync::Mutex::new(ModFunctions::new()))
             .is_ok()
     );
-    let mut on_game_load_hook_needed = false;
+
+    // chaudloader_mod_init may register callbacks, so MODFUNCTIONS must not be locked while calling it.
+    api::set_initializing(true);
+    for (mod_name, mod_state) in loaded_mods.iter() {
+        let mut mod_api = None;
+        for dll in mod_state.borrow().dlls.values() {
+            unsafe {
+                if let Ok(mod_init_symbol_address) = dll.get_symbol_address("chaudloader_mod_init")
+                {
+                    let mod_init = std::mem::transmute::<
+                        winapi::shared::minwindef::FARPROC,
+                        chaudloader_sdk::ffi::ModInitFunction,
+                    >(mod_init_symbol_address);
+                    mod_init(*mod_api.get_or_insert_with(|| api::new(mod_name, game_env)));
+                }
+            }
+        }
+    }
+    api::set_initializing(false);
+
     let mut mod_funcs = MODFUNCTIONS.get().unwrap().lock().unwrap();
     for mod_state in loaded_mods.values() {
-        for dll in mod_state.dlls.values() {
+        for dll in mod_state.borrow().dlls.values() {
             unsafe {
                 if let Ok(on_game_load_symbol_address) = dll.get_symbol_address("on_game_load") {
                     mod_funcs.on_game_load_functions.push(std::mem::transmute::<
                         winapi::shared::minwindef::FARPROC,
-                        fn(u32, *const u8),
+                        chaudloader_sdk::ffi::OnGameLoadFunction,
                     >(
                         on_game_load_symbol_address
                     ));
-                    on_game_load_hook_needed = true;
                 }
             }
         }
     }
-    Ok(on_game_load_hook_needed)
+    Ok(mods::lua::has_on_game_load()
+        || !mod_funcs.on_game_load_functions.is_empty()
+        || !mod_funcs.on_game_load_callbacks.is_empty())
 }
 
 fn init_mod_audio() -> Result<(bool, bool), anyhow::Error> {
     let mut mod_audio = MODAUDIOFILES.get().unwrap().lock().unwrap();
     let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
     if !mod_audio.wems.is_empty() {
-        let audio_path = std::path::Path::new("..\\exe\\audio\\chaudloader.pck");
-        assets_replacer.add(audio_path, move |writer| generate_chaudloader_pck(writer));
+        let audio_path = std::path::Path::new("audio\\chaudloader.pck");
+        assets_replacer.add("chaudloader", audio_path, move |writer| {
+            generate_chaudloader_pck(writer)
+        })?;
         mod_audio
             .pcks
             .push(std::ffi::OsString::from("chaudloader.pck"));
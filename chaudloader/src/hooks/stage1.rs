@@ -1,4 +1,4 @@
-use crate::{assets, hooks, mods};
+use crate::{assets, cheats, gba, hooks, mods, pattern};
 use retour::static_detour;
 
 use std::os::windows::ffi::OsStrExt;
@@ -46,6 +46,24 @@ static_detour! {
     ) -> winapi::shared::ntdef::HANDLE;
 }
 
+static_detour! {
+    static PeekMessageWHook: unsafe extern "system" fn(
+        /* lp_msg: */ winapi::um::winuser::LPMSG,
+        /* h_wnd: */ winapi::shared::windef::HWND,
+        /* w_msg_filter_min: */ winapi::shared::minwindef::UINT,
+        /* w_msg_filter_max: */ winapi::shared::minwindef::UINT,
+        /* w_remove_msg: */ winapi::shared::minwindef::UINT
+    ) -> winapi::shared::minwindef::BOOL;
+
+    static PeekMessageAHook: unsafe extern "system" fn(
+        /* lp_msg: */ winapi::um::winuser::LPMSG,
+        /* h_wnd: */ winapi::shared::windef::HWND,
+        /* w_msg_filter_min: */ winapi::shared::minwindef::UINT,
+        /* w_msg_filter_max: */ winapi::shared::minwindef::UINT,
+        /* w_remove_msg: */ winapi::shared::minwindef::UINT
+    ) -> winapi::shared::minwindef::BOOL;
+}
+
 static_detour! {
     static mmbnlc_OnGameLoad: unsafe extern "system" fn(
         u32
@@ -84,31 +102,53 @@ unsafe fn on_create_file(
     dw_flags_and_attributes: winapi::shared::minwindef::DWORD,
     handle: winapi::shared::ntdef::HANDLE,
 ) -> winapi::shared::ntdef::HANDLE {
+    // If the replacement is still being generated in the background, wait for it before disabling hooks so other threads can keep opening files in the meantime.
+    let pending = assets::REPLACER
+        .get()
+        .unwrap()
+        .lock()
+        .unwrap()
+        .pending(path);
+    if let Some(pending) = pending {
+        log::info!("waiting for {} to be ready", path.display());
+        pending.wait();
+    }
+
     let _hooks_disable_guard: HooksDisableGuard = unsafe { HooksDisableGuard::new().unwrap() };
 
-    // FIXME: This path is relative to the exe folder, but is sometimes something like ..\exe\data\exe1.dat. We should canonicalize it in all cases to intercept all reads.
+    // The replacer normalizes the path itself, since the game spells paths in all sorts of ways (e.g. ..\exe\data\exe1.dat).
     let path = clean_path::clean(path);
 
     let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
-    let new_path = if let Some(new_path) = assets_replacer.get(&path).unwrap() {
-        new_path
-    } else {
-        let path_wstr = path
-            .as_os_str()
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect::<Vec<_>>();
-        return unsafe {
-            CreateFileWHook.call(
-                path_wstr[..].as_ptr(),
-                dw_desired_access,
-                dw_share_mode,
-                lp_security_attributes,
-                dw_creation_disposition,
-                dw_flags_and_attributes,
-                handle,
-            )
-        };
+    let new_path = match assets_replacer.get(&path) {
+        Ok(Some(new_path)) => new_path,
+        Ok(None) => {
+            let path_wstr = path
+                .as_os_str()
+                .encode_wide()
+                .chain(std::iter::once(0))
+                .collect::<Vec<_>>();
+            return unsafe {
+                CreateFileWHook.call(
+                    path_wstr[..].as_ptr(),
+                    dw_desired_access,
+                    dw_share_mode,
+                    lp_security_attributes,
+                    dw_creation_disposition,
+                    dw_flags_and_attributes,
+                    handle,
+                )
+            };
+        }
+        Err(e) => {
+            log::error!("cannot replace {}: {}", path.display(), e);
+            unsafe {
+                winapi::um::errhandlingapi::SetLastError(
+                    winapi::shared::winerror::ERROR_OPEN_FAILED,
+                )
+            };
+            return winapi::um::handleapi::INVALID_HANDLE_VALUE;
+        }
     };
 
     log::info!(
@@ -135,12 +175,34 @@ unsafe fn on_create_file(
     }
 }
 
+/// Called whenever a thread checks its window message queue, which the game's main loop does every frame.
+fn on_peek_message() {
+    mods::lua::run_pending();
+    cheats::apply_loaded();
+}
+
 unsafe fn on_game_load(game_version: u32, gba_state: *mut u8) {
+    // There is no hook for leaving a game, so the previous game is only unloaded when the next one loads.
+    gba::set_unloaded();
     unsafe { mmbnlc_OnGameLoad.call(game_version) };
+    unsafe { gba::set_loaded(game_version, gba_state) };
     let mod_funcs = mods::MODFUNCTIONS.get().unwrap().lock().unwrap();
-    for on_game_load_functions in &mod_funcs.on_game_load_functions {
-        on_game_load_functions(game_version, gba_state);
+    for on_game_load_function in &mod_funcs.on_game_load_functions {
+        unsafe { on_game_load_function(game_version, gba_state.cast()) };
     }
+    for (callback, userdata) in &mod_funcs.on_game_load_callbacks {
+        unsafe {
+            callback(
+                std::ptr::with_exposed_provenance_mut(*userdata),
+                game_version,
+                gba_state.cast(),
+            )
+        };
+    }
+    drop(mod_funcs);
+    mods::lua::on_game_load(game_version);
+    mods::lua::run_pending();
+    cheats::apply_loaded();
 }
 
 unsafe fn on_pck_load(
@@ -239,12 +301,27 @@ unsafe fn on_bnk_load(
 }
 
 /// Install hooks into the process.
-pub unsafe fn install() -> Result<(), anyhow::Error> {
-    static KERNELBASE: std::sync::LazyLock<windows_libloader::ModuleHandle> =
-        std::sync::LazyLock::new(|| unsafe {
-            windows_libloader::ModuleHandle::get("kernelbase.dll").unwrap()
-        });
+static KERNELBASE: std::sync::LazyLock<windows_libloader::ModuleHandle> =
+    std::sync::LazyLock::new(|| unsafe {
+        windows_libloader::ModuleHandle::get("kernelbase.dll").unwrap()
+    });
+
+static USER32: std::sync::LazyLock<windows_libloader::ModuleHandle> =
+    std::sync::LazyLock::new(|| unsafe {
+        windows_libloader::ModuleHandle::get("user32.dll").unwrap()
+    });
+
+/// Keeps mods from hooking the functions that `install`, `install_peek_message` and `HooksDisableGuard` hook.
+pub fn reserve_hook_targets() {
+    for name in ["CreateFileW", "CreateFileA"] {
+        hooks::native::reserve(unsafe { KERNELBASE.get_symbol_address(name).unwrap() } as usize);
+    }
+    for name in ["PeekMessageW", "PeekMessageA"] {
+        hooks::native::reserve(unsafe { USER32.get_symbol_address(name).unwrap() } as usize);
+    }
+}
 
+pub unsafe fn install() -> Result<(), anyhow::Error> {
     // BNLC actually uses both CreateFileA and CreateFileW... It seems like the third-party code uses CreateFileW but the BNLC code itself uses CreateFileA...
     //
     // Since we don't really care about the distincton, let's just normalize it here and hook it all via on_create_file.
@@ -312,96 +389,109 @@ pub unsafe fn install() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
-/// Install optional on_game_laod hook into the process.
-pub unsafe fn install_on_game_load(game_env: &mods::GameEnv) -> Result<(), anyhow::Error> {
+/// Install optional message queue hook into the process, which runs on the game's main thread every frame.
+///
+/// Unlike the game function hooks, this doesn't need a signature: the game's main loop peeks at its window messages every frame.
+pub unsafe fn install_peek_message() -> Result<(), anyhow::Error> {
     unsafe {
-        if let Some(data) = game_env.sections.text {
-            // This pattern is enough to find the function in all releases of both collections (at 0x141dde120 Vol1 / 0x143147c10 Vol2 for latest releases)
-            let on_game_load_pattern: [u8; 12] = [
-                0x48, 0x89, 0x5c, 0x24, 0x10, 0x56, 0x48, 0x83, 0xec, 0x20, 0x8b, 0xd9,
-            ];
-            if let Some(offset) = data
-                .windows(on_game_load_pattern.len())
-                .position(|window| window == on_game_load_pattern)
-            {
-                let on_game_load_ptr = data.as_ptr().add(offset);
-                // Get the offset to the GBAStruct from a structure referenced in the function
-                let mov_instr_offset = 0x18;
-                let struct_rel_offset = std::ptr::read_unaligned(
-                    on_game_load_ptr.add(mov_instr_offset + 3) as *const u32,
-                ) as usize;
-                let struct_offset =
-                    on_game_load_ptr.add(mov_instr_offset + 7 + struct_rel_offset) as u64;
-
-                mmbnlc_OnGameLoad
-                    .initialize(std::mem::transmute(on_game_load_ptr), {
-                        move |game_version| {
-                            // let gba_state = std::mem::transmute::<u64, * mut u8>(0x80200040);
-                            // Get the gba state offset every time in case this struct moves
-                            let struct_with_gba_state =
-                                std::ptr::read_unaligned(struct_offset as *const *const u8);
-                            let gba_state = std::ptr::read_unaligned(
-                                struct_with_gba_state.add(0x3F8) as *const *mut u8,
-                            );
-                            on_game_load(game_version, gba_state)
-                        }
-                    })?
-                    .enable()?;
-            }
-        }
+        PeekMessageWHook
+            .initialize(
+                std::mem::transmute(USER32.get_symbol_address("PeekMessageW").unwrap()),
+                move |lp_msg, h_wnd, w_msg_filter_min, w_msg_filter_max, w_remove_msg| {
+                    on_peek_message();
+                    PeekMessageWHook.call(
+                        lp_msg,
+                        h_wnd,
+                        w_msg_filter_min,
+                        w_msg_filter_max,
+                        w_remove_msg,
+                    )
+                },
+            )?
+            .enable()?;
+
+        PeekMessageAHook
+            .initialize(
+                std::mem::transmute(USER32.get_symbol_address("PeekMessageA").unwrap()),
+                move |lp_msg, h_wnd, w_msg_filter_min, w_msg_filter_max, w_remove_msg| {
+                    on_peek_message();
+                    PeekMessageAHook.call(
+                        lp_msg,
+                        h_wnd,
+                        w_msg_filter_min,
+                        w_msg_filter_max,
+                        w_remove_msg,
+                    )
+                },
+            )?
+            .enable()?;
+    }
+    Ok(())
+}
+
+/// Install optional on_game_load hook into the process.
+pub unsafe fn install_on_game_load(
+    resolved: &hooks::signatures::Resolved,
+    game_env: &mods::GameEnv,
+) -> Result<(), anyhow::Error> {
+    unsafe {
+        let on_game_load_ptr = resolved.address as *const u8;
+        // Get the offset to the GBAStruct from a structure referenced in the function. The offset comes from the signature, so it may point anywhere.
+        let Some(struct_offset) = game_env.sections.text.and_then(|text| {
+            let mov_instr_offset = (resolved.address - text.as_ptr() as usize)
+                .checked_add(resolved.offsets["gba_state_mov"])?;
+            pattern::resolve_relative(text, text.as_ptr() as u64, mov_instr_offset)
+        }) else {
+            log::error!(
+                "hook on_game_load: cannot resolve GBA state struct, on_game_load will not be called for mods"
+            );
+            return Ok(());
+        };
+        let gba_state_offset = resolved.offsets["gba_state"];
+
+        mmbnlc_OnGameLoad
+            .initialize(std::mem::transmute(on_game_load_ptr), {
+                move |game_version| {
+                    // Get the gba state offset every time in case this struct moves
+                    let struct_with_gba_state =
+                        std::ptr::read_unaligned(struct_offset as *const *const u8);
+                    let gba_state = std::ptr::read_unaligned(
+                        struct_with_gba_state.add(gba_state_offset) as *const *mut u8,
+                    );
+                    on_game_load(game_version, gba_state)
+                }
+            })?
+            .enable()?;
     }
     Ok(())
 }
 
 /// Install optional PCK File load hook into the process.
-pub unsafe fn install_pck_load(game_env: &mods::GameEnv) -> Result<(), anyhow::Error> {
+pub unsafe fn install_pck_load(
+    resolved: &hooks::signatures::Resolved,
+) -> Result<(), anyhow::Error> {
     unsafe {
-        if let Some(data) = game_env.sections.text {
-            // This pattern is enough to find the function in all releases of both collections (at 0x14000A5C0 Vol1 / 0x14000BD20 Vol2 for the October 2023 releases)
-            let pck_load_pattern: [u8; 24] = [
-                0x40, 0x53, 0x55, 0x56, 0x57, 0x41, 0x56, 0x48, 0x81, 0xEC, 0x80, 0x00, 0x00, 0x00,
-                0x48, 0xC7, 0x44, 0x24, 0x38, 0xFE, 0xFF, 0xFF, 0xFF, 0x48,
-            ];
-            if let Some(offset) = data
-                .windows(pck_load_pattern.len())
-                .position(|window| window == pck_load_pattern)
-            {
-                let pck_load_ptr = data.as_ptr().add(offset);
-                LoadFilePackage
-                    .initialize(std::mem::transmute(pck_load_ptr), {
-                        move |sound_engine_class, pck_file_name, out_pck_id| {
-                            on_pck_load(sound_engine_class, pck_file_name, out_pck_id)
-                        }
-                    })?
-                    .enable()?;
-            }
-        }
+        LoadFilePackage
+            .initialize(std::mem::transmute(resolved.address as *const u8), {
+                move |sound_engine_class, pck_file_name, out_pck_id| {
+                    on_pck_load(sound_engine_class, pck_file_name, out_pck_id)
+                }
+            })?
+            .enable()?;
     }
     Ok(())
 }
 
 /// Install optional BNK File load hook into the process.
-pub unsafe fn install_bnk_load(game_env: &mods::GameEnv) -> Result<(), anyhow::Error> {
+pub unsafe fn install_bnk_load(
+    resolved: &hooks::signatures::Resolved,
+) -> Result<(), anyhow::Error> {
     unsafe {
-        if let Some(data) = game_env.sections.text {
-            // This pattern is enough to find the function in all releases of both collections (at 0x141CC27E0 Vol1 / 0x14302C310 Vol2 for the October 2023 releases)
-            let bnk_load_pattern: [u8; 32] = [
-                0x48, 0x89, 0x5C, 0x24, 0x08, 0x48, 0x89, 0x74, 0x24, 0x10, 0x48, 0x89, 0x7C, 0x24,
-                0x18, 0x55, 0x48, 0x8D, 0x6C, 0x24, 0xA9, 0x48, 0x81, 0xEC, 0xE0, 0x00, 0x00, 0x00,
-                0x48, 0x8B, 0xFA, 0x4C,
-            ];
-            if let Some(offset) = data
-                .windows(bnk_load_pattern.len())
-                .position(|window| window == bnk_load_pattern)
-            {
-                let bnk_load_ptr = data.as_ptr().add(offset);
-                LoadBank
-                    .initialize(std::mem::transmute(bnk_load_ptr), {
-                        move |bnk_file_name, out_bnk_id| on_bnk_load(bnk_file_name, out_bnk_id)
-                    })?
-                    .enable()?;
-            }
-        }
+        LoadBank
+            .initialize(std::mem::transmute(resolved.address as *const u8), {
+                move |bnk_file_name, out_bnk_id| on_bnk_load(bnk_file_name, out_bnk_id)
+            })?
+            .enable()?;
     }
     Ok(())
 }
@@ -1,12 +1,23 @@
 #![windows_subsystem = "windows"]
 
+mod api;
 mod assets;
+mod bridge;
+mod cheats;
 mod config;
 mod console;
+mod debugger;
+mod gba;
+mod gba_asm;
 mod gui;
 mod hooks;
 mod mods;
 mod path;
+mod pattern;
+mod pe;
+mod repl;
+mod symbols;
+mod x86_asm;
 
 pub static VERSION: std::sync::LazyLock<semver::Version> =
     std::sync::LazyLock::new(|| semver::Version::parse(env!("CARGO_PKG_VERSION")).unwrap());
@@ -1,4 +1,5 @@
 pub mod lua;
+pub mod wasm;
 
 #[derive(serde::Deserialize, Debug)]
 pub struct Info {
@@ -12,6 +13,9 @@ pub struct Info {
     #[serde(default)]
     pub r#unsafe: bool,
 
+    #[serde(default)]
+    pub persistent: bool,
+
     #[serde(default)]
     pub authors: Vec<String>,
 
@@ -23,6 +27,23 @@ pub struct Info {
 
     #[serde(default)]
     pub requires_exe_crc32: Option<std::collections::HashSet<u32>>,
+
+    #[serde(default)]
+    pub requires_symbols: Vec<String>,
+
+    #[serde(default)]
+    pub runtime: Runtime,
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Runtime {
+    /// The mod is initialized by `init.lua`.
+    #[default]
+    Lua,
+
+    /// The mod is initialized by `init.wasm`.
+    Wasm,
 }
 
 #[derive(Clone, Default)]
@@ -35,6 +56,7 @@ pub struct GameEnv {
     pub volume: crate::GameVolume,
     pub exe_crc32: u32,
     pub sections: Sections,
+    pub image: crate::pe::Image,
 }
 
 pub struct State {
@@ -50,13 +72,17 @@ impl State {
 }
 
 pub struct ModFunctions {
-    pub on_game_load_functions: Vec<fn(u32, *const u8)>,
+    pub on_game_load_functions: Vec<chaudloader_sdk::ffi::OnGameLoadFunction>,
+
+    /// Callbacks registered through the host API, with their userdata pointers.
+    pub on_game_load_callbacks: Vec<(crate::api::OnGameLoadCallback, usize)>,
 }
 
 impl ModFunctions {
     pub fn new() -> Self {
         Self {
             on_game_load_functions: Vec::new(),
+            on_game_load_callbacks: Vec::new(),
         }
     }
 }
@@ -82,10 +108,16 @@ impl ModAudioFiles {
     }
 }
 
+pub enum Init {
+    Lua(String),
+    Wasm(Vec<u8>),
+}
+
 pub struct Mod {
     pub info: Info,
     pub readme: String,
-    pub init_lua: String,
+    pub init: Init,
+    pub symbols: std::sync::Arc<crate::symbols::SymbolTable>,
 }
 
 #[derive(Debug)]
@@ -93,15 +125,17 @@ pub struct Compatibility {
     pub loader_version: bool,
     pub game: bool,
     pub exe_crc32: bool,
+    pub missing_symbols: Vec<String>,
 }
 
 impl Compatibility {
     pub fn is_compatible(&self) -> bool {
-        self.loader_version && self.game && self.exe_crc32
+        self.loader_version && self.game && self.exe_crc32 && self.missing_symbols.is_empty()
     }
 }
 
-pub fn check_compatibility(game_env: &GameEnv, info: &Info) -> Compatibility {
+pub fn check_compatibility(game_env: &GameEnv, r#mod: &Mod) -> Compatibility {
+    let info = &r#mod.info;
     Compatibility {
         loader_version: info.requires_loader_version.matches(&crate::VERSION),
         game: info
@@ -114,6 +148,12 @@ pub fn check_compatibility(game_env: &GameEnv, info: &Info) -> Compatibility {
             .as_ref()
             .map(|exe_crc32s| exe_crc32s.contains(&game_env.exe_crc32))
             .unwrap_or(true),
+        missing_symbols: info
+            .requires_symbols
+            .iter()
+            .filter(|name| crate::symbols::resolve(&r#mod.symbols, name, game_env).is_none())
+            .cloned()
+            .collect(),
     }
 }
 
@@ -152,19 +192,33 @@ pub fn scan() -> Result<std::collections::BTreeMap<String, std::sync::Arc<Mod>>,
                     ))
                 }
             })?;
-            let init_lua: String =
-                std::fs::read_to_string(entry.path().join("init.lua")).map_err(|e| {
-                    std::io::Error::new(
-                        e.kind(),
-                        anyhow::format_err!("error reading init.lua: {}", e),
-                    )
-                })?;
+            let init = match info.runtime {
+                Runtime::Lua => Init::Lua(
+                    std::fs::read_to_string(entry.path().join("init.lua")).map_err(|e| {
+                        std::io::Error::new(
+                            e.kind(),
+                            anyhow::format_err!("error reading init.lua: {}", e),
+                        )
+                    })?,
+                ),
+                Runtime::Wasm => {
+                    Init::Wasm(std::fs::read(entry.path().join("init.wasm")).map_err(|e| {
+                        std::io::Error::new(
+                            e.kind(),
+                            anyhow::format_err!("error reading init.wasm: {}", e),
+                        )
+                    })?)
+                }
+            };
+            let symbols = crate::symbols::SymbolTable::load(&entry.path().join("symbols"))
+                .map_err(|e| anyhow::format_err!("error reading symbols: {}", e))?;
             mods.insert(
                 mod_name.to_string(),
                 std::sync::Arc::new(Mod {
                     info,
                     readme,
-                    init_lua,
+                    init,
+                    symbols: std::sync::Arc::new(symbols),
                 }),
             );
             Ok(())
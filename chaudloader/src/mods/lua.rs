@@ -7,9 +7,10 @@ pub fn new(
     game_env: &mods::GameEnv,
     info: &mods::Info,
     state: std::rc::Rc<std::cell::RefCell<mods::State>>,
+    mod_symbols: std::sync::Arc<crate::symbols::SymbolTable>,
     overlays: std::collections::HashMap<
         String,
-        std::rc::Rc<std::cell::RefCell<assets::exedat::Overlay>>,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
     >,
 ) -> Result<mlua::Lua, mlua::Error> {
     let lua = if info.r#unsafe {
@@ -17,6 +18,154 @@ pub fn new(
     } else {
         mlua::Lua::new()
     };
-    lib::set_globals(&lua, game_env, name, info, state, overlays)?;
+    lib::set_globals(&lua, game_env, name, info, state, mod_symbols, overlays)?;
     Ok(lua)
 }
+
+pub fn finish(lua: &mlua::Lua, name: &str) -> Result<(), mlua::Error> {
+    lib::chaudloader::finish(lua, name)
+}
+
+std::thread_local! {
+    /// Lua states of persistent mods, which are not `Send` and so stay on the thread that initialized the mods.
+    static PERSISTENT: std::cell::RefCell<Vec<(String, mlua::Lua)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+static PERSISTENT_THREAD: std::sync::OnceLock<std::thread::ThreadId> = std::sync::OnceLock::new();
+
+/// Keeps a mod's Lua state alive after its `init.lua` has run, so it can handle game events.
+pub fn keep_alive(name: &str, lua: mlua::Lua) {
+    let _ = PERSISTENT_THREAD.set(std::thread::current().id());
+    PERSISTENT.with_borrow_mut(|states| states.push((name.to_string(), lua)));
+}
+
+pub fn has_on_game_load() -> bool {
+    PERSISTENT.with_borrow(|states| {
+        states
+            .iter()
+            .any(|(_, lua)| lib::chaudloader::has_on_game_load(lua).unwrap_or(false))
+    })
+}
+
+/// Whether this is the thread that owns the persistent Lua states, i.e. the game's main thread.
+fn on_persistent_thread() -> bool {
+    PERSISTENT_THREAD
+        .get()
+        .is_none_or(|thread_id| *thread_id == std::thread::current().id())
+}
+
+/// Game loads that happened on another thread, for `run_pending` to dispatch.
+static PENDING_GAME_LOADS: std::sync::Mutex<Vec<u32>> = std::sync::Mutex::new(Vec::new());
+
+/// Dispatches `on_game_load` to persistent mods.
+///
+/// Lua states can't leave the thread that initialized the mods, so if the game is loaded on another thread, the event is
+/// queued for `run_pending` instead.
+pub fn on_game_load(game: u32) {
+    if !on_persistent_thread() {
+        PENDING_GAME_LOADS.lock().unwrap().push(game);
+        return;
+    }
+    dispatch_on_game_load(game);
+}
+
+fn dispatch_on_game_load(game: u32) {
+    PERSISTENT.with_borrow(|states| {
+        for (name, lua) in states {
+            if let Err(e) = lib::chaudloader::on_game_load(lua, name, game) {
+                log::error!("[mod: {}] on_game_load failed: {}", name, e);
+            }
+        }
+    });
+}
+
+/// Creates the Lua state of the developer REPL.
+///
+/// It has the unsafe API, `mods` listing the names of loaded mods, and `gba()` returning the GBA of the running game, if any.
+pub fn new_repl(
+    game_env: &mods::GameEnv,
+    mod_names: Vec<String>,
+    overlays: std::collections::HashMap<
+        String,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
+    >,
+) -> Result<mlua::Lua, mlua::Error> {
+    let info = mods::Info {
+        title: "REPL".to_string(),
+        version: semver::Version::new(0, 0, 0),
+        url: None,
+        r#unsafe: true,
+        persistent: false,
+        authors: vec![],
+        requires_loader_version: semver::VersionReq::default(),
+        requires_game: None,
+        requires_exe_crc32: None,
+        requires_symbols: vec![],
+        runtime: mods::Runtime::Lua,
+    };
+    let lua = new(
+        "repl",
+        game_env,
+        &info,
+        std::rc::Rc::new(std::cell::RefCell::new(mods::State::new())),
+        std::sync::Arc::new(crate::symbols::SymbolTable::default()),
+        overlays,
+    )?;
+
+    let globals = lua.globals();
+    globals.set("mods", mod_names)?;
+    globals.set(
+        "gba",
+        lua.create_function(|lua, ()| {
+            crate::gba::loaded_state()
+                .map(|_| lib::chaudloader::new_gba(lua))
+                .transpose()
+        })?,
+    )?;
+    Ok(lua)
+}
+
+type PendingEval = (String, String, oneshot::Sender<Result<String, String>>);
+
+static PENDING_EVALS: std::sync::Mutex<Vec<PendingEval>> = std::sync::Mutex::new(Vec::new());
+
+/// Queues code to be evaluated in a persistent mod's Lua state by `run_pending`.
+pub fn queue_eval(name: &str, code: &str) -> oneshot::Receiver<Result<String, String>> {
+    let (sender, receiver) = oneshot::channel();
+    PENDING_EVALS
+        .lock()
+        .unwrap()
+        .push((name.to_string(), code.to_string(), sender));
+    receiver
+}
+
+/// Dispatches game loads queued by `on_game_load`, then runs evaluations queued by `queue_eval`. Does nothing unless called
+/// from the thread that initialized the mods, which is the game's main thread.
+pub fn run_pending() {
+    if !on_persistent_thread() {
+        return;
+    }
+
+    let pending_game_loads = std::mem::take(&mut *PENDING_GAME_LOADS.lock().unwrap());
+    for game in pending_game_loads {
+        dispatch_on_game_load(game);
+    }
+
+    let pending = std::mem::take(&mut *PENDING_EVALS.lock().unwrap());
+    PERSISTENT.with_borrow(|states| {
+        for (name, code, sender) in pending {
+            let result = states
+                .iter()
+                .find(|(n, _)| *n == name)
+                .ok_or_else(|| format!("no persistent mod named {}", name))
+                .and_then(|(_, lua)| crate::repl::eval_lua(lua, &code).map_err(|e| e.to_string()));
+            // The REPL stops waiting if the game takes too long to get here, so log the result instead.
+            if let Err(e) = sender.send(result) {
+                match e.into_inner() {
+                    Ok(output) => log::info!("[repl] @{}: {}", name, output),
+                    Err(e) => log::error!("[repl] @{}: {}", name, e),
+                }
+            }
+        }
+    });
+}
@@ -62,9 +62,10 @@ pub fn set_globals(
     name: &str,
     info: &mods::Info,
     state: std::rc::Rc<std::cell::RefCell<mods::State>>,
+    mod_symbols: std::sync::Arc<crate::symbols::SymbolTable>,
     overlays: std::collections::HashMap<
         String,
-        std::rc::Rc<std::cell::RefCell<assets::exedat::Overlay>>,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
     >,
 ) -> Result<(), mlua::Error> {
     let globals = lua.globals();
@@ -234,7 +235,7 @@ pub fn set_globals(
 
     globals.set(
         "chaudloader",
-        chaudloader::new(lua, game_env, name, info, overlays)?,
+        chaudloader::new(lua, game_env, name, info, mod_symbols, overlays)?,
     )?;
 
     lua.load(include_str!("compat.lua"))
@@ -1,13 +1,18 @@
 mod bnk;
 mod buffer;
+mod events;
 mod exedat;
+mod files;
+mod gba;
+mod gba_asm;
 mod modfiles;
 mod mpak;
 mod msg;
 mod pck;
+mod symbols;
 mod r#unsafe;
 
-use crate::{assets, mods};
+use crate::{assets, mods, pe};
 use mlua::ExternalError;
 
 fn new_game_env<'a>(
@@ -29,9 +34,77 @@ fn new_game_env<'a>(
     game_env_table.set("name", serde_plain::to_string(&env.volume).unwrap())?;
     game_env_table.set("exe_crc32", env.exe_crc32)?;
     game_env_table.set("sections", sections_table)?;
+    game_env_table.set("image", new_image(lua, &env.image)?)?;
     Ok(mlua::Value::Table(game_env_table))
 }
 
+fn new_image<'a>(lua: &'a mlua::Lua, image: &pe::Image) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+    table.set("base", image.base)?;
+    table.set("size", image.size)?;
+
+    let sections_table = lua.create_table()?;
+    for section in image.sections.iter() {
+        let section_table = lua.create_table()?;
+        section_table.set("name", section.name.as_str())?;
+        section_table.set("address", image.base + section.rva as usize)?;
+        section_table.set("size", section.size)?;
+        section_table.set("characteristics", section.characteristics)?;
+        sections_table.push(section_table)?;
+    }
+    table.set("sections", sections_table)?;
+
+    let imports_table = lua.create_table()?;
+    for import in image.imports.iter() {
+        let import_table = lua.create_table()?;
+        import_table.set("dll", import.dll.as_str())?;
+        import_table.set("name", import.name.as_deref())?;
+        import_table.set("ordinal", import.ordinal)?;
+        import_table.set("slot_address", image.base + import.slot_rva as usize)?;
+        imports_table.push(import_table)?;
+    }
+    table.set("imports", imports_table)?;
+
+    let exports_table = lua.create_table()?;
+    for export in image.exports.iter() {
+        let export_table = lua.create_table()?;
+        export_table.set("name", export.name.as_deref())?;
+        export_table.set("ordinal", export.ordinal)?;
+        match &export.target {
+            pe::ExportTarget::Rva(rva) => {
+                export_table.set("address", image.base + *rva as usize)?;
+            }
+            pe::ExportTarget::Forward(forward) => {
+                export_table.set("forward", forward.as_str())?;
+            }
+        }
+        exports_table.push(export_table)?;
+    }
+    table.set("exports", exports_table)?;
+
+    // The address helpers only need the image's range.
+    let image = pe::Image {
+        base: image.base,
+        size: image.size,
+        ..Default::default()
+    };
+
+    table.set(
+        "rva_to_va",
+        lua.create_function({
+            let image = image.clone();
+            move |_, (rva,): (u32,)| Ok(image.rva_to_va(rva))
+        })?,
+    )?;
+
+    table.set(
+        "va_to_rva",
+        lua.create_function(move |_, (va,): (usize,)| Ok(image.va_to_rva(va)))?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
+
 fn new_mod_env<'a>(lua: &'a mlua::Lua, name: &'a str) -> Result<mlua::Value<'a>, mlua::Error> {
     let table = lua.create_table()?;
     let mod_path = std::path::Path::new("mods").join(name);
@@ -51,9 +124,10 @@ pub fn new<'a>(
     game_env: &mods::GameEnv,
     name: &'a str,
     info: &mods::Info,
+    mod_symbols: std::sync::Arc<crate::symbols::SymbolTable>,
     overlays: std::collections::HashMap<
         String,
-        std::rc::Rc<std::cell::RefCell<assets::exedat::Overlay>>,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
     >,
 ) -> Result<mlua::Value<'a>, mlua::Error> {
     let table = lua.create_table()?;
@@ -66,12 +140,16 @@ pub fn new<'a>(
     table.set("mpak", mpak::new(lua)?)?;
     table.set("buffer", buffer::new(lua)?)?;
     table.set("msg", msg::new(lua)?)?;
+    table.set("gba_asm", gba_asm::new(lua)?)?;
     table.set("modfiles", modfiles::new(lua, &mod_path)?)?;
-    table.set("pck", pck::new(lua, &mod_path)?)?;
-    table.set("bnk", bnk::new(lua, &mod_path)?)?;
+    table.set("pck", pck::new(lua, name, &mod_path)?)?;
+    table.set("bnk", bnk::new(lua, name, &mod_path)?)?;
+    table.set("files", files::new(lua, name, &mod_path)?)?;
+    table.set("symbols", symbols::new(lua, game_env, mod_symbols)?)?;
+    table.set("events", events::new(lua, info.persistent)?)?;
 
     if info.r#unsafe {
-        table.set("unsafe", r#unsafe::new(lua)?)?;
+        table.set("unsafe", r#unsafe::new(lua, name)?)?;
     }
 
     table.set(
@@ -84,3 +162,22 @@ pub fn new<'a>(
 
     Ok(mlua::Value::Table(table))
 }
+
+/// Finishes up anything the mod deferred until after its `init.lua` completed.
+pub fn finish(lua: &mlua::Lua, name: &str) -> Result<(), mlua::Error> {
+    files::run_generators(lua, name)?;
+    Ok(())
+}
+
+/// Wraps the GBA of the running game for Lua, like the one passed to event handlers.
+pub fn new_gba(lua: &mlua::Lua) -> Result<mlua::AnyUserData<'_>, mlua::Error> {
+    lua.create_userdata(gba::Gba::loaded())
+}
+
+pub fn has_on_game_load(lua: &mlua::Lua) -> Result<bool, mlua::Error> {
+    events::has_on_game_load(lua)
+}
+
+pub fn on_game_load(lua: &mlua::Lua, name: &str, game: u32) -> Result<(), mlua::Error> {
+    events::dispatch_on_game_load(lua, name, game)
+}
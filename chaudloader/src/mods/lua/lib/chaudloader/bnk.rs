@@ -3,6 +3,7 @@ use mlua::ExternalError;
 
 pub fn new<'a>(
     lua: &'a mlua::Lua,
+    name: &str,
     mod_path: &std::path::Path,
 ) -> Result<mlua::Value<'a>, mlua::Error> {
     let table = lua.create_table()?;
@@ -10,6 +11,7 @@ pub fn new<'a>(
     table.set(
         "load_bnk",
         lua.create_function({
+            let name = name.to_string();
             let mod_path = mod_path.to_path_buf();
             move |_, (path,): (String,)| {
                 let path = path::ensure_safe(std::path::Path::new(&path))
@@ -35,9 +37,9 @@ pub fn new<'a>(
                     } else {
                         // The game will only try to load bnk files from the audio folder.
                         // Use the asset replacer to reroute it to the mod's folder.
-                        let dst_bnk_path = std::path::PathBuf::from("..\\exe\\audio").join(base_filename);
+                        let dst_bnk_path = std::path::PathBuf::from("audio").join(base_filename);
                         let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
-                        assets_replacer.add_path(&dst_bnk_path, &bnk_path);
+                        assets_replacer.add_path(&format!("mod: {}", name), &dst_bnk_path, &bnk_path)?;
                         mod_audio.bnks.push(base_filename_osstr);
                         Ok(())
                     }
@@ -0,0 +1,108 @@
+use crate::mods::lua::lib::chaudloader::gba::Gba;
+use mlua::ExternalError;
+
+const ON_GAME_LOAD_REGISTRY_KEY: &str = "chaudloader.events.on_game_load";
+
+pub fn new<'a>(lua: &'a mlua::Lua, persistent: bool) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+
+    lua.set_named_registry_value(ON_GAME_LOAD_REGISTRY_KEY, lua.create_table()?)?;
+
+    table.set(
+        "on_game_load",
+        lua.create_function(move |lua, (cb,): (mlua::Function,)| {
+            if !persistent {
+                return Err(anyhow::anyhow!(
+                    "in order to handle events, you must mark your mod as persistent!"
+                )
+                .into_lua_err());
+            }
+            let callbacks = lua.named_registry_value::<mlua::Table>(ON_GAME_LOAD_REGISTRY_KEY)?;
+            callbacks.raw_push(cb)?;
+            Ok(())
+        })?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
+
+pub fn has_on_game_load(lua: &mlua::Lua) -> Result<bool, mlua::Error> {
+    Ok(lua
+        .named_registry_value::<mlua::Table>(ON_GAME_LOAD_REGISTRY_KEY)?
+        .raw_len()
+        > 0)
+}
+
+/// Calls each `on_game_load` callback of the mod `name` in the order they were registered.
+///
+/// A callback that raises an error is logged and does not stop the callbacks after it.
+pub fn dispatch_on_game_load(lua: &mlua::Lua, name: &str, game: u32) -> Result<(), mlua::Error> {
+    let callbacks = lua.named_registry_value::<mlua::Table>(ON_GAME_LOAD_REGISTRY_KEY)?;
+    for (i, cb) in callbacks.sequence_values::<mlua::Function>().enumerate() {
+        if let Err(e) = cb?.call::<_, ()>((game, Gba::loaded())) {
+            log::error!(
+                "[mod: {}] on_game_load callback {} failed: {}",
+                name,
+                i + 1,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_on_game_load() {
+        let lua = mlua::Lua::new();
+        lua.globals()
+            .set("events", new(&lua, true).unwrap())
+            .unwrap();
+        assert!(!has_on_game_load(&lua).unwrap());
+
+        lua.load(
+            r#"
+            calls = {}
+            events.on_game_load(function(game, gba)
+                table.insert(calls, { game = game, memory_address = gba.memory_address })
+                kept_gba = gba
+            end)
+            events.on_game_load(function() error("oops") end)
+            events.on_game_load(function(game) table.insert(calls, game) end)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert!(has_on_game_load(&lua).unwrap());
+
+        let mut memory = [0u8; 4];
+        let mut gba_state = [0u8; 80];
+        gba_state[72..].copy_from_slice(&(memory.as_mut_ptr() as usize).to_le_bytes());
+        unsafe { crate::gba::set_loaded(5, gba_state.as_mut_ptr()) };
+        dispatch_on_game_load(&lua, "test", 5).unwrap();
+        crate::gba::set_unloaded();
+
+        let calls = lua.globals().get::<_, mlua::Table>("calls").unwrap();
+        let first = calls.get::<_, mlua::Table>(1).unwrap();
+        assert_eq!(first.get::<_, u32>("game").unwrap(), 5);
+        assert_eq!(
+            first.get::<_, usize>("memory_address").unwrap(),
+            memory.as_ptr() as usize
+        );
+        assert_eq!(calls.get::<_, u32>(2).unwrap(), 5);
+        assert!(lua.load("kept_gba:read_u8(0x02000000)").exec().is_err());
+
+        let lua = mlua::Lua::new();
+        lua.globals()
+            .set("events", new(&lua, false).unwrap())
+            .unwrap();
+        assert!(
+            lua.load("events.on_game_load(function() end)")
+                .exec()
+                .is_err()
+        );
+    }
+}
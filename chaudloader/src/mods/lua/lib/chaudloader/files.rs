@@ -0,0 +1,115 @@
+use crate::{assets, mods::lua::lib::chaudloader::buffer::Buffer, path};
+use mlua::ExternalError;
+
+const GENERATORS_REGISTRY_KEY: &str = "chaudloader.files.generators";
+
+/// Checks that a path relative to the game directory does not escape it.
+fn resolve_game_path(path: &str) -> Result<std::path::PathBuf, mlua::Error> {
+    let path = path::ensure_safe(std::path::Path::new(path))
+        .ok_or_else(|| anyhow::anyhow!("cannot replace files outside of game directory"))
+        .map_err(|e| e.into_lua_err())?;
+    if path.as_os_str().is_empty() {
+        return Err(anyhow::anyhow!("cannot replace the game directory itself").into_lua_err());
+    }
+    Ok(path)
+}
+
+pub fn new<'a>(
+    lua: &'a mlua::Lua,
+    name: &str,
+    mod_path: &std::path::Path,
+) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+
+    lua.set_named_registry_value(GENERATORS_REGISTRY_KEY, lua.create_table()?)?;
+
+    table.set(
+        "replace",
+        lua.create_function({
+            let name = name.to_string();
+            let mod_path = mod_path.to_path_buf();
+            move |_, (path, replacement): (String, mlua::Value)| {
+                let game_path = resolve_game_path(&path)?;
+                let owner = format!("mod: {}", name);
+                match replacement {
+                    mlua::Value::String(src_path) => {
+                        let src_path = path::ensure_safe(std::path::Path::new(src_path.to_str()?))
+                            .ok_or_else(|| {
+                                anyhow::anyhow!("cannot read files outside of mod directory")
+                            })
+                            .map_err(|e| e.into_lua_err())?;
+
+                        let src_path = mod_path.join(&src_path);
+                        if !src_path.exists() {
+                            return Err(anyhow::anyhow!("{} does not exist", src_path.display())
+                                .into_lua_err());
+                        }
+
+                        let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
+                        assets_replacer.add_path(&owner, &game_path, &src_path)?;
+                    }
+                    mlua::Value::UserData(ud) => {
+                        let contents = ud.borrow::<Buffer>()?.borrow().to_vec();
+                        let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
+                        assets_replacer.add(&owner, &game_path, move |writer| {
+                            writer.write_all(&contents)
+                        })?;
+                    }
+                    v => {
+                        return Err(anyhow::anyhow!(
+                            "replacement must be a path or a Buffer, not {}",
+                            v.type_name()
+                        )
+                        .into_lua_err());
+                    }
+                }
+                log::info!("[mod: {}] replacing {}", name, path);
+                Ok(())
+            }
+        })?,
+    )?;
+
+    table.set(
+        "generate",
+        lua.create_function(move |lua, (path, cb): (String, mlua::Function)| {
+            // Validate the path eagerly, so the error points at the call site.
+            resolve_game_path(&path)?;
+            let generators = lua.named_registry_value::<mlua::Table>(GENERATORS_REGISTRY_KEY)?;
+            let generator = lua.create_table()?;
+            generator.raw_set(1, path)?;
+            generator.raw_set(2, cb)?;
+            generators.raw_set(generators.raw_len() + 1, generator)?;
+            Ok(())
+        })?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
+
+/// Runs all generators registered with `chaudloader.files.generate` and registers their output as replacements.
+///
+/// This must be called after the mod's `init.lua` has run, but before its Lua state is dropped.
+pub fn run_generators(lua: &mlua::Lua, name: &str) -> Result<(), mlua::Error> {
+    let generators = lua.named_registry_value::<mlua::Table>(GENERATORS_REGISTRY_KEY)?;
+    for generator in generators.sequence_values::<mlua::Table>() {
+        let (path, cb) = {
+            let generator = generator?;
+            (
+                generator.raw_get::<_, String>(1)?,
+                generator.raw_get::<_, mlua::Function>(2)?,
+            )
+        };
+        let contents = cb
+            .call::<_, mlua::UserDataRef<Buffer>>(path.clone())?
+            .borrow()
+            .to_vec();
+        let mut assets_replacer = assets::REPLACER.get().unwrap().lock().unwrap();
+        assets_replacer.add(
+            &format!("mod: {}", name),
+            &resolve_game_path(&path)?,
+            move |writer| writer.write_all(&contents),
+        )?;
+        log::info!("[mod: {}] generated {}", name, path);
+    }
+    Ok(())
+}
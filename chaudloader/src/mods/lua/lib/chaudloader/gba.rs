@@ -0,0 +1,103 @@
+use crate::{gba, mods::lua::lib::chaudloader::buffer::Buffer};
+use mlua::ExternalError;
+
+/// The emulated GBA passed to game lifecycle events.
+///
+/// Unless it was created for a fixed GBA state, the running game's state is looked up on every access, so a `Gba` that is
+/// kept after its game is unloaded raises errors instead of touching freed memory.
+pub struct Gba {
+    state: Option<*mut u8>,
+}
+
+impl Gba {
+    /// The GBA of whichever game is running when it is accessed.
+    pub fn loaded() -> Self {
+        Self { state: None }
+    }
+
+    /// The GBA with the state at `state`, which must stay valid for as long as the `Gba` is used.
+    pub unsafe fn from_state(state: *mut u8) -> Self {
+        Self { state: Some(state) }
+    }
+
+    fn state(&self) -> Result<*mut u8, mlua::Error> {
+        self.state
+            .or_else(|| gba::loaded_state().map(|(_, state)| state))
+            .ok_or_else(|| anyhow::anyhow!("no game is loaded").into_lua_err())
+    }
+
+    fn memory(&self) -> Result<gba::Memory, mlua::Error> {
+        match self.state {
+            Some(state) => Ok(unsafe { gba::Memory::from_state(state) }),
+            None => gba::loaded()
+                .map(|(_, memory)| memory)
+                .ok_or_else(|| anyhow::anyhow!("no game is loaded").into_lua_err()),
+        }
+    }
+}
+
+impl mlua::UserData for Gba {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("state_address", |_, this| {
+            Ok(this.state()?.expose_provenance())
+        });
+        fields.add_field_method_get("memory_address", |_, this| {
+            Ok(this.memory()?.base().expose_provenance())
+        });
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("read", |_, this, (address, len): (u32, usize)| {
+            let mut buf = vec![0u8; len];
+            this.memory()?
+                .read(address, &mut buf)
+                .map_err(|e| e.into_lua_err())?;
+            Ok(Buffer::new(buf))
+        });
+
+        methods.add_method(
+            "write",
+            |_, this, (address, buf): (u32, mlua::UserDataRef<Buffer>)| {
+                this.memory()?
+                    .write(address, &buf.borrow())
+                    .map_err(|e| e.into_lua_err())
+            },
+        );
+
+        methods.add_method("read_u8", |_, this, (address,): (u32,)| {
+            this.memory()?
+                .read_u8(address)
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("read_u16", |_, this, (address,): (u32,)| {
+            this.memory()?
+                .read_u16(address)
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("read_u32", |_, this, (address,): (u32,)| {
+            this.memory()?
+                .read_u32(address)
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("write_u8", |_, this, (address, value): (u32, u8)| {
+            this.memory()?
+                .write_u8(address, value)
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("write_u16", |_, this, (address, value): (u32, u16)| {
+            this.memory()?
+                .write_u16(address, value)
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("write_u32", |_, this, (address, value): (u32, u32)| {
+            this.memory()?
+                .write_u32(address, value)
+                .map_err(|e| e.into_lua_err())
+        });
+    }
+}
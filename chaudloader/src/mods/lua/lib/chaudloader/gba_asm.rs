@@ -0,0 +1,30 @@
+use crate::{gba_asm, mods::lua::lib::chaudloader::buffer::Buffer};
+use mlua::ExternalError;
+
+pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+
+    table.set(
+        "assemble",
+        lua.create_function(|lua, (source, options): (String, Option<mlua::Table>)| {
+            let mut asm_options = gba_asm::Options::default();
+            if let Some(options) = options {
+                asm_options.org = options.get("org")?;
+                if let Some(symbols) = options.get("symbols")? {
+                    asm_options.symbols = symbols;
+                }
+            }
+
+            let assembly =
+                gba_asm::assemble(&source, &asm_options).map_err(|e| e.into_lua_err())?;
+
+            let chunks = lua.create_table()?;
+            for chunk in assembly.chunks {
+                chunks.set(chunk.rom_addr, Buffer::new(chunk.contents))?;
+            }
+            Ok((chunks, assembly.labels))
+        })?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
@@ -1,7 +1,31 @@
-use crate::{assets, mods::lua::lib::chaudloader::buffer::Buffer};
+use crate::{
+    assets,
+    mods::lua::lib::chaudloader::{buffer::Buffer, exedat::ExeDat},
+};
+use mlua::ExternalError;
 
 struct Mpak(std::rc::Rc<std::cell::RefCell<assets::mpak::Mpak>>);
 
+fn pointer_options(
+    options: Option<mlua::Table>,
+) -> Result<assets::mpak::PointerOptions, mlua::Error> {
+    let mut pointer_options = assets::mpak::PointerOptions::default();
+    if let Some(options) = options {
+        if let Some(align) = options.get::<_, Option<u32>>("align")? {
+            if !align.is_power_of_two() {
+                return Err(
+                    anyhow::anyhow!("alignment {:#x} is not a power of two", align).into_lua_err(),
+                );
+            }
+            pointer_options.align = align;
+        }
+        if let Some(thumb) = options.get::<_, Option<bool>>("thumb")? {
+            pointer_options.thumb = thumb;
+        }
+    }
+    Ok(pointer_options)
+}
+
 impl mlua::UserData for Mpak {
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_meta_method(
@@ -45,6 +69,71 @@ impl mlua::UserData for Mpak {
             })
         });
 
+        methods.add_method(
+            "write",
+            |_, this, (rom_addr, contents): (u32, mlua::UserDataRef<Buffer>)| {
+                let mut this = this.0.borrow_mut();
+                this.write(rom_addr, &contents.borrow());
+                Ok(())
+            },
+        );
+
+        methods.add_method("alloc", |_, this, (size, align): (u32, Option<u32>)| {
+            let mut this = this.0.borrow_mut();
+            this.alloc(size, align.unwrap_or(4))
+                .map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method(
+            "find_pointers",
+            |_, this, (rom_addr, options): (u32, Option<mlua::Table>)| {
+                let this = this.0.borrow();
+                Ok(this.find_pointers(rom_addr, pointer_options(options)?))
+            },
+        );
+
+        methods.add_method(
+            "repoint",
+            |_, this, (old_addr, new_addr, options): (u32, u32, Option<mlua::Table>)| {
+                let mut this = this.0.borrow_mut();
+                Ok(this.repoint(old_addr, new_addr, pointer_options(options)?))
+            },
+        );
+
+        methods.add_method("to_rom", |_, this, (fill,): (Option<u8>,)| {
+            let this = this.0.borrow();
+            Ok(Buffer::new(
+                this.to_rom(fill.unwrap_or(0xff))
+                    .map_err(|e| e.into_lua_err())?,
+            ))
+        });
+
+        methods.add_method(
+            "diff_rom",
+            |_, this, (rom, fill): (mlua::UserDataRef<Buffer>, Option<u8>)| {
+                let this = this.0.borrow();
+                Ok(Mpak(std::rc::Rc::new(std::cell::RefCell::new(
+                    this.diff_rom(&rom.borrow(), fill.unwrap_or(0xff))
+                        .map_err(|e| e.into_lua_err())?,
+                ))))
+            },
+        );
+
+        methods.add_method(
+            "pack_into",
+            |_, this, (dat, name): (mlua::UserDataRef<ExeDat>, String)| {
+                dat.0
+                    .lock()
+                    .unwrap()
+                    .write_mpak(
+                        &format!("{}.map", name),
+                        &format!("{}.mpak", name),
+                        &this.0.borrow(),
+                    )
+                    .map_err(|e| e.into_lua_err())
+            },
+        );
+
         methods.add_method("pack", |_, this, (): ()| {
             let this = this.0.borrow();
             let mut map_contents = vec![];
@@ -64,17 +153,24 @@ pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
     table.set(
         "unpack",
         lua.create_function({
-            move |_,
-                  (map_contents, mpak_contents): (
-                mlua::UserDataRef<Buffer>,
-                mlua::UserDataRef<Buffer>,
-            )| {
-                Ok(Mpak(std::rc::Rc::new(std::cell::RefCell::new(
+            move |lua, (source, rest): (mlua::AnyUserData, mlua::Value)| {
+                let mpak = if let Ok(dat) = source.borrow::<ExeDat>() {
+                    let name = lua.unpack::<String>(rest)?;
+                    dat.0
+                        .lock()
+                        .unwrap()
+                        .read_mpak(&format!("{}.map", name), &format!("{}.mpak", name))
+                } else {
+                    let map_contents =
+                        lua.unpack::<mlua::UserDataRef<Buffer>>(mlua::Value::UserData(source))?;
+                    let mpak_contents = lua.unpack::<mlua::UserDataRef<Buffer>>(rest)?;
                     assets::mpak::Mpak::read_from(
                         std::io::Cursor::new(&*map_contents.borrow()),
                         std::io::Cursor::new(&*mpak_contents.borrow()),
-                    )?,
-                ))))
+                    )
+                }
+                .map_err(|e| e.into_lua_err())?;
+                Ok(Mpak(std::rc::Rc::new(std::cell::RefCell::new(mpak))))
             }
         })?,
     )?;
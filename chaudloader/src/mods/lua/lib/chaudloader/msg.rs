@@ -1,4 +1,5 @@
 use crate::{assets, mods::lua::lib::chaudloader::buffer::Buffer};
+use mlua::ExternalError;
 
 pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
     let table = lua.create_table()?;
@@ -6,7 +7,8 @@ pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
     table.set(
         "unpack",
         lua.create_function(|_, (raw,): (mlua::UserDataRef<Buffer>,)| {
-            Ok(assets::msg::unpack(std::io::Cursor::new(&*raw.borrow()))?
+            Ok(assets::msg::unpack(std::io::Cursor::new(&*raw.borrow()))
+                .map_err(|e| e.into_lua_err())?
                 .into_iter()
                 .map(Buffer::new)
                 .collect::<Vec<_>>())
@@ -21,7 +23,8 @@ pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
             assets::msg::pack(
                 &entries.iter().map(|v| v.as_ref()).collect::<Vec<_>>(),
                 &mut buf,
-            )?;
+            )
+            .map_err(|e| e.into_lua_err())?;
             Ok(Buffer::new(buf))
         })?,
     )?;
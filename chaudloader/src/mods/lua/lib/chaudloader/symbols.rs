@@ -0,0 +1,19 @@
+use crate::{mods, symbols};
+
+pub fn new<'a>(
+    lua: &'a mlua::Lua,
+    game_env: &mods::GameEnv,
+    mod_symbols: std::sync::Arc<symbols::SymbolTable>,
+) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+    let game_env = game_env.clone();
+
+    table.set(
+        "resolve",
+        lua.create_function(move |_, (name,): (String,)| {
+            Ok(symbols::resolve(&mod_symbols, &name, &game_env))
+        })?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
@@ -1,29 +1,65 @@
-use crate::mods::lua::lib::chaudloader::buffer::Buffer;
+use crate::{
+    hooks,
+    mods::lua::lib::chaudloader::{buffer::Buffer, gba::Gba},
+    pattern,
+};
 use mlua::ExternalError;
 
-pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
+mod asm;
+
+fn read_process_memory(addr: usize, len: usize) -> Result<Vec<u8>, mlua::Error> {
+    let mut buf = vec![0u8; len];
+    let mut number_of_bytes_read: winapi::shared::basetsd::SIZE_T = 0;
+    unsafe {
+        let current_process = winapi::um::processthreadsapi::GetCurrentProcess();
+        if winapi::um::memoryapi::ReadProcessMemory(
+            current_process,
+            addr as winapi::shared::minwindef::LPCVOID,
+            buf.as_mut_ptr() as winapi::shared::minwindef::LPVOID,
+            buf.len() as winapi::shared::basetsd::SIZE_T,
+            &mut number_of_bytes_read as *mut winapi::shared::basetsd::SIZE_T,
+        ) != winapi::shared::minwindef::TRUE
+        {
+            return Err(get_last_error::Win32Error::get_last_error().into_lua_err());
+        }
+    }
+    buf.drain(number_of_bytes_read as usize..);
+    Ok(buf)
+}
+
+struct Hook {
+    id: hooks::native::HookId,
+    trampoline: usize,
+}
+
+impl mlua::UserData for Hook {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("target", |_, this| Ok(this.id.target()));
+        fields.add_field_method_get("trampoline", |_, this| Ok(this.trampoline));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("enable", |_, this, (): ()| unsafe {
+            hooks::native::set_enabled(this.id, true).map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("disable", |_, this, (): ()| unsafe {
+            hooks::native::set_enabled(this.id, false).map_err(|e| e.into_lua_err())
+        });
+
+        methods.add_method("is_enabled", |_, this, (): ()| {
+            Ok(hooks::native::is_enabled(this.id))
+        });
+    }
+}
+
+pub fn new<'a>(lua: &'a mlua::Lua, name: &str) -> Result<mlua::Value<'a>, mlua::Error> {
     let table = lua.create_table()?;
 
     table.set(
         "read_process_memory",
         lua.create_function(|_, (addr, len): (usize, usize)| {
-            let mut buf = vec![0u8; len];
-            let mut number_of_bytes_read: winapi::shared::basetsd::SIZE_T = 0;
-            unsafe {
-                let current_process = winapi::um::processthreadsapi::GetCurrentProcess();
-                if winapi::um::memoryapi::ReadProcessMemory(
-                    current_process,
-                    addr as winapi::shared::minwindef::LPCVOID,
-                    buf.as_mut_ptr() as winapi::shared::minwindef::LPVOID,
-                    buf.len() as winapi::shared::basetsd::SIZE_T,
-                    &mut number_of_bytes_read as *mut winapi::shared::basetsd::SIZE_T,
-                ) != winapi::shared::minwindef::TRUE
-                {
-                    return Err(get_last_error::Win32Error::get_last_error().into_lua_err());
-                }
-            }
-            buf.drain(number_of_bytes_read as usize..);
-            Ok(Buffer::new(buf))
+            Ok(Buffer::new(read_process_memory(addr, len)?))
         })?,
     )?;
 
@@ -102,5 +138,57 @@ pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
         })?,
     )?;
 
+    table.set(
+        "find_pattern",
+        lua.create_function(|_, (section, pattern): (mlua::Table, String)| {
+            let pattern = pattern
+                .parse::<pattern::Pattern>()
+                .map_err(|e| e.into_lua_err())?;
+            let address = section.get::<_, usize>("address")?;
+            let data = unsafe {
+                std::slice::from_raw_parts(
+                    std::ptr::with_exposed_provenance::<u8>(address),
+                    section.get::<_, usize>("size")?,
+                )
+            };
+            Ok(pattern
+                .find_iter(data)
+                .map(|offset| address + offset)
+                .collect::<Vec<_>>())
+        })?,
+    )?;
+
+    table.set(
+        "resolve_relative",
+        lua.create_function(|_, (addr,): (usize,)| {
+            // No x86-64 instruction is longer than 15 bytes.
+            let code = read_process_memory(addr, 15)?;
+            Ok(pattern::resolve_relative(&code, addr as u64, 0).map(|target| target as usize))
+        })?,
+    )?;
+
+    table.set(
+        "hook",
+        lua.create_function({
+            let name = name.to_string();
+            move |_, (target_addr, detour_addr): (usize, usize)| unsafe {
+                let (id, trampoline) = hooks::native::install(&name, target_addr, detour_addr)
+                    .map_err(|e| e.into_lua_err())?;
+                Ok(Hook { id, trampoline })
+            }
+        })?,
+    )?;
+
+    table.set(
+        "gba",
+        lua.create_function(|_, (state_addr,): (usize,)| unsafe {
+            Ok(Gba::from_state(std::ptr::with_exposed_provenance_mut(
+                state_addr,
+            )))
+        })?,
+    )?;
+
+    table.set("asm", asm::new(lua)?)?;
+
     Ok(mlua::Value::Table(table))
 }
@@ -0,0 +1,44 @@
+use crate::{mods::lua::lib::chaudloader::buffer::Buffer, x86_asm};
+use mlua::ExternalError;
+
+pub fn new<'a>(lua: &'a mlua::Lua) -> Result<mlua::Value<'a>, mlua::Error> {
+    let table = lua.create_table()?;
+
+    table.set(
+        "assemble",
+        lua.create_function(|_, (source, base_addr): (String, usize)| {
+            let assembly =
+                x86_asm::assemble(&source, base_addr as u64).map_err(|e| e.into_lua_err())?;
+            Ok((
+                Buffer::new(assembly.code),
+                assembly
+                    .labels
+                    .into_iter()
+                    .map(|(name, addr)| (name, addr as usize))
+                    .collect::<std::collections::BTreeMap<_, _>>(),
+            ))
+        })?,
+    )?;
+
+    table.set(
+        "disassemble",
+        lua.create_function(
+            |lua, (buf, base_addr): (mlua::UserDataRef<Buffer>, usize)| {
+                let buf = buf.borrow();
+                x86_asm::disassemble(&buf, base_addr as u64)
+                    .into_iter()
+                    .map(|instruction| {
+                        let table = lua.create_table()?;
+                        table.set("address", instruction.address as usize)?;
+                        table.set("size", instruction.bytes.len())?;
+                        table.set("bytes", Buffer::new(instruction.bytes))?;
+                        table.set("text", instruction.text)?;
+                        Ok(table)
+                    })
+                    .collect::<Result<Vec<_>, mlua::Error>>()
+            },
+        )?,
+    )?;
+
+    Ok(mlua::Value::Table(table))
+}
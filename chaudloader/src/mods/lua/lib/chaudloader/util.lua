@@ -2,11 +2,9 @@ local exports = {}
 
 -- Unpacks an .map and .mpak for loading, calls a function on it, then writes it back when complete.
 function exports.edit_mpak(dat, name, cb)
-    local mpak = chaudloader.mpak.unpack(dat:read_file(name .. ".map"), dat:read_file(name .. ".mpak"))
+    local mpak = chaudloader.mpak.unpack(dat, name)
     cb(mpak)
-    local raw_map, raw_mpak = mpak:pack()
-    dat:write_file(name .. ".map", raw_map)
-    dat:write_file(name .. ".mpak", raw_mpak)
+    mpak:pack_into(dat, name)
 end
 
 -- Unpacks msg data, calls a function on it, then writes it back when complete.
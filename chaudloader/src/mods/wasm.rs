@@ -0,0 +1,664 @@
+use crate::{assets, path};
+
+/// Resource limits for running a WASM mod's `init`.
+pub struct Limits {
+    /// Roughly the number of instructions the module may execute.
+    pub fuel: u64,
+
+    /// Maximum size of the module's linear memory and the host objects it holds, in bytes.
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            fuel: 2_000_000_000,
+            memory: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// Host objects that WASM mods refer to by handle.
+enum Object {
+    Buffer(Vec<u8>),
+    ExeDat(std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>),
+    Mpak(assets::mpak::Mpak),
+    List(Vec<Vec<u8>>),
+}
+
+impl Object {
+    fn size(&self) -> usize {
+        match self {
+            Object::Buffer(buf) => buf.len(),
+            Object::ExeDat(_) => 0,
+            Object::Mpak(mpak) => (0..)
+                .map_while(|i| mpak.get_index(i))
+                .map(|(_, entry)| entry.len())
+                .sum(),
+            Object::List(list) => list.iter().map(|entry| entry.len()).sum(),
+        }
+    }
+}
+
+/// Counts the module's linear memory and the host memory it uses together against `Limits::memory`, so the limit can't be dodged by keeping data in host objects.
+struct MemoryBudget {
+    limit: usize,
+    linear_memory: usize,
+    linear_memory_before_grow: usize,
+    host: usize,
+}
+
+impl MemoryBudget {
+    fn charge(&mut self, bytes: usize) -> Result<(), wasmi::Error> {
+        let host = self.host.saturating_add(bytes);
+        if host.saturating_add(self.linear_memory) > self.limit {
+            return Err(error(format!(
+                "memory limit of {} bytes exceeded",
+                self.limit
+            )));
+        }
+        self.host = host;
+        Ok(())
+    }
+
+    fn release(&mut self, bytes: usize) {
+        self.host -= bytes;
+    }
+}
+
+impl wasmi::ResourceLimiter for MemoryBudget {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool, wasmi::errors::MemoryError> {
+        if desired.saturating_add(self.host) > self.limit {
+            return Ok(false);
+        }
+        self.linear_memory_before_grow = current;
+        self.linear_memory = desired;
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: &wasmi::errors::MemoryError) {
+        self.linear_memory = self.linear_memory_before_grow;
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: u32,
+        _desired: u32,
+        _maximum: Option<u32>,
+    ) -> Result<bool, wasmi::errors::TableError> {
+        Ok(true)
+    }
+}
+
+struct Host {
+    name: String,
+    mod_path: std::path::PathBuf,
+    overlays: std::collections::HashMap<
+        String,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
+    >,
+
+    /// Handle `n` is `objects[n - 1]`, so 0 is never a valid handle. Each object is kept with the bytes charged to the budget for it.
+    objects: Vec<Option<(Object, usize)>>,
+    budget: MemoryBudget,
+}
+
+fn error(e: impl std::fmt::Display) -> wasmi::Error {
+    wasmi::Error::new(e.to_string())
+}
+
+impl Host {
+    fn insert(&mut self, object: Object) -> Result<u32, wasmi::Error> {
+        let size = object.size();
+        self.budget.charge(size)?;
+        let index = if let Some(index) = self.objects.iter().position(|o| o.is_none()) {
+            self.objects[index] = Some((object, size));
+            index
+        } else {
+            self.objects.push(Some((object, size)));
+            self.objects.len() - 1
+        };
+        Ok(index as u32 + 1)
+    }
+
+    fn slot(&mut self, handle: u32) -> Result<&mut Option<(Object, usize)>, wasmi::Error> {
+        (handle as usize)
+            .checked_sub(1)
+            .and_then(|index| self.objects.get_mut(index))
+            .filter(|slot| slot.is_some())
+            .ok_or_else(|| error(format!("invalid handle: {}", handle)))
+    }
+
+    fn get_mut(&mut self, handle: u32) -> Result<&mut Object, wasmi::Error> {
+        Ok(&mut self.slot(handle)?.as_mut().unwrap().0)
+    }
+
+    /// Charges `bytes` more to the object behind `handle`, for when it grows.
+    fn charge(&mut self, handle: u32, bytes: usize) -> Result<(), wasmi::Error> {
+        self.slot(handle)?;
+        self.budget.charge(bytes)?;
+        self.slot(handle)?.as_mut().unwrap().1 += bytes;
+        Ok(())
+    }
+
+    fn free(&mut self, handle: u32) -> Result<(), wasmi::Error> {
+        let (_, size) = self.slot(handle)?.take().unwrap();
+        self.budget.release(size);
+        Ok(())
+    }
+
+    fn buffer(&mut self, handle: u32) -> Result<&mut Vec<u8>, wasmi::Error> {
+        match self.get_mut(handle)? {
+            Object::Buffer(buf) => Ok(buf),
+            _ => Err(error(format!("handle {} is not a buffer", handle))),
+        }
+    }
+
+    fn exedat(
+        &mut self,
+        handle: u32,
+    ) -> Result<std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>, wasmi::Error> {
+        match self.get_mut(handle)? {
+            Object::ExeDat(overlay) => Ok(std::sync::Arc::clone(overlay)),
+            _ => Err(error(format!("handle {} is not an exedat", handle))),
+        }
+    }
+
+    fn mpak(&mut self, handle: u32) -> Result<&mut assets::mpak::Mpak, wasmi::Error> {
+        match self.get_mut(handle)? {
+            Object::Mpak(mpak) => Ok(mpak),
+            _ => Err(error(format!("handle {} is not an mpak", handle))),
+        }
+    }
+
+    fn list(&mut self, handle: u32) -> Result<&mut Vec<Vec<u8>>, wasmi::Error> {
+        match self.get_mut(handle)? {
+            Object::List(list) => Ok(list),
+            _ => Err(error(format!("handle {} is not a list", handle))),
+        }
+    }
+}
+
+fn memory(caller: &wasmi::Caller<'_, Host>) -> Result<wasmi::Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(wasmi::Extern::into_memory)
+        .ok_or_else(|| error("module does not export memory"))
+}
+
+/// Splits the caller into its memory and host state.
+fn memory_and_host<'a>(
+    caller: &'a mut wasmi::Caller<'_, Host>,
+) -> Result<(&'a mut [u8], &'a mut Host), wasmi::Error> {
+    Ok(memory(caller)?.data_and_store_mut(caller))
+}
+
+fn guest_slice(memory: &[u8], ptr: u32, len: u32) -> Result<&[u8], wasmi::Error> {
+    memory
+        .get(ptr as usize..ptr as usize + len as usize)
+        .ok_or_else(|| error(format!("{} byte(s) at {:#x} are out of bounds", len, ptr)))
+}
+
+fn guest_str(memory: &[u8], ptr: u32, len: u32) -> Result<&str, wasmi::Error> {
+    std::str::from_utf8(guest_slice(memory, ptr, len)?).map_err(error)
+}
+
+fn write_guest(
+    caller: &mut wasmi::Caller<'_, Host>,
+    ptr: u32,
+    buf: &[u8],
+) -> Result<(), wasmi::Error> {
+    memory(caller)?
+        .write(caller, ptr as usize, buf)
+        .map_err(|_| {
+            error(format!(
+                "{} byte(s) at {:#x} are out of bounds",
+                buf.len(),
+                ptr
+            ))
+        })
+}
+
+fn link(linker: &mut wasmi::Linker<Host>) -> Result<(), wasmi::Error> {
+    const MODULE: &str = "chaudloader";
+
+    linker.func_wrap(
+        MODULE,
+        "log",
+        |mut caller: wasmi::Caller<'_, Host>, ptr: u32, len: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            log::info!("[mod: {}] {}", host.name, guest_str(memory, ptr, len)?);
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "free",
+        |mut caller: wasmi::Caller<'_, Host>, handle: u32| caller.data_mut().free(handle),
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "buffer_new",
+        |mut caller: wasmi::Caller<'_, Host>, ptr: u32, len: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            let buf = guest_slice(memory, ptr, len)?.to_vec();
+            host.insert(Object::Buffer(buf))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "buffer_len",
+        |mut caller: wasmi::Caller<'_, Host>, handle: u32| {
+            Ok(caller.data_mut().buffer(handle)?.len() as u32)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "buffer_read",
+        |mut caller: wasmi::Caller<'_, Host>, handle: u32, ptr: u32| {
+            let buf = std::mem::take(caller.data_mut().buffer(handle)?);
+            let r = write_guest(&mut caller, ptr, &buf);
+            *caller.data_mut().buffer(handle)? = buf;
+            r
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "modfiles_read_file",
+        |mut caller: wasmi::Caller<'_, Host>, ptr: u32, len: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            let path = path::ensure_safe(std::path::Path::new(guest_str(memory, ptr, len)?))
+                .ok_or_else(|| error("cannot read files outside of mod directory"))?;
+            let buf = std::fs::read(host.mod_path.join(path)).map_err(error)?;
+            host.insert(Object::Buffer(buf))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "exedat_open",
+        |mut caller: wasmi::Caller<'_, Host>, ptr: u32, len: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            let name = guest_str(memory, ptr, len)?;
+            let overlay = host
+                .overlays
+                .get(name)
+                .cloned()
+                .ok_or_else(|| error(format!("no such dat file: {}", name)))?;
+            host.insert(Object::ExeDat(overlay))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "exedat_read_file",
+        |mut caller: wasmi::Caller<'_, Host>, exedat: u32, ptr: u32, len: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            let path = guest_str(memory, ptr, len)?;
+            let buf = host
+                .exedat(exedat)?
+                .lock()
+                .unwrap()
+                .read(path)
+                .map_err(error)?
+                .to_vec();
+            host.insert(Object::Buffer(buf))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "exedat_write_file",
+        |mut caller: wasmi::Caller<'_, Host>, exedat: u32, ptr: u32, len: u32, buffer: u32| {
+            let (memory, host) = memory_and_host(&mut caller)?;
+            let path = guest_str(memory, ptr, len)?;
+            let contents = host.buffer(buffer)?.clone();
+            // The overlay keeps the contents after the module is done, so they are never released.
+            host.budget.charge(contents.len())?;
+            host.exedat(exedat)?
+                .lock()
+                .unwrap()
+                .write(path, contents)
+                .map_err(error)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "mpak_unpack",
+        |mut caller: wasmi::Caller<'_, Host>, map: u32, mpak: u32| {
+            let host = caller.data_mut();
+            let map = host.buffer(map)?.clone();
+            let mpak = assets::mpak::Mpak::read_from(
+                std::io::Cursor::new(&map),
+                std::io::Cursor::new(&host.buffer(mpak)?[..]),
+            )
+            .map_err(error)?;
+            host.insert(Object::Mpak(mpak))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "mpak_get",
+        |mut caller: wasmi::Caller<'_, Host>, mpak: u32, rom_addr: u32| {
+            let host = caller.data_mut();
+            let Some(entry) = host.mpak(mpak)?.get(rom_addr).map(|entry| entry.to_vec()) else {
+                return Ok(0);
+            };
+            host.insert(Object::Buffer(entry))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "mpak_set",
+        |mut caller: wasmi::Caller<'_, Host>, mpak: u32, rom_addr: u32, buffer: u32| {
+            let host = caller.data_mut();
+            if buffer == 0 {
+                host.mpak(mpak)?.remove(rom_addr);
+            } else {
+                let contents = host.buffer(buffer)?.clone();
+                host.charge(mpak, contents.len())?;
+                host.mpak(mpak)?.insert(rom_addr, contents);
+            }
+            Ok(())
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "mpak_pack",
+        |mut caller: wasmi::Caller<'_, Host>, mpak: u32, out_ptr: u32| {
+            let host = caller.data_mut();
+            let mut map_contents = vec![];
+            let mut mpak_contents = vec![];
+            host.mpak(mpak)?
+                .write_into(&mut map_contents, &mut mpak_contents)
+                .map_err(error)?;
+            let map = host.insert(Object::Buffer(map_contents))?;
+            let mpak = host.insert(Object::Buffer(mpak_contents))?;
+            write_guest(
+                &mut caller,
+                out_ptr,
+                &[map.to_le_bytes(), mpak.to_le_bytes()].concat(),
+            )
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "msg_unpack",
+        |mut caller: wasmi::Caller<'_, Host>, buffer: u32| {
+            let host = caller.data_mut();
+            let entries = assets::msg::unpack(std::io::Cursor::new(&host.buffer(buffer)?[..]))
+                .map_err(error)?;
+            host.insert(Object::List(entries))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "msg_pack",
+        |mut caller: wasmi::Caller<'_, Host>, list: u32| {
+            let host = caller.data_mut();
+            let mut buf = vec![];
+            {
+                let entries = host.list(list)?;
+                assets::msg::pack(
+                    &entries.iter().map(|v| v.as_ref()).collect::<Vec<_>>(),
+                    &mut buf,
+                )
+                .map_err(error)?;
+            }
+            host.insert(Object::Buffer(buf))
+        },
+    )?;
+
+    linker.func_wrap(MODULE, "list_new", |mut caller: wasmi::Caller<'_, Host>| {
+        caller.data_mut().insert(Object::List(vec![]))
+    })?;
+
+    linker.func_wrap(
+        MODULE,
+        "list_len",
+        |mut caller: wasmi::Caller<'_, Host>, list: u32| {
+            Ok(caller.data_mut().list(list)?.len() as u32)
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "list_get",
+        |mut caller: wasmi::Caller<'_, Host>, list: u32, index: u32| {
+            let host = caller.data_mut();
+            let entry = host
+                .list(list)?
+                .get(index as usize)
+                .cloned()
+                .ok_or_else(|| error(format!("index {} out of range", index)))?;
+            host.insert(Object::Buffer(entry))
+        },
+    )?;
+
+    linker.func_wrap(
+        MODULE,
+        "list_push",
+        |mut caller: wasmi::Caller<'_, Host>, list: u32, buffer: u32| {
+            let host = caller.data_mut();
+            let entry = host.buffer(buffer)?.clone();
+            host.list(list)?;
+            host.charge(list, entry.len())?;
+            host.list(list)?.push(entry);
+            Ok(())
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Runs a WASM mod: instantiates the module and calls its exported `init` function.
+pub fn run(
+    name: &str,
+    wasm: &[u8],
+    overlays: std::collections::HashMap<
+        String,
+        std::sync::Arc<std::sync::Mutex<assets::exedat::Overlay>>,
+    >,
+    limits: &Limits,
+) -> Result<(), anyhow::Error> {
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true);
+    let engine = wasmi::Engine::new(&config);
+    let module = wasmi::Module::new(&engine, wasm)?;
+
+    let mut store = wasmi::Store::new(
+        &engine,
+        Host {
+            name: name.to_string(),
+            mod_path: std::path::Path::new("mods").join(name),
+            overlays,
+            objects: vec![],
+            budget: MemoryBudget {
+                limit: limits.memory,
+                linear_memory: 0,
+                linear_memory_before_grow: 0,
+                host: 0,
+            },
+        },
+    );
+    store.limiter(|host| &mut host.budget);
+    store
+        .set_fuel(limits.fuel)
+        .map_err(|e| anyhow::format_err!("{}", e))?;
+
+    let mut linker = wasmi::Linker::new(&engine);
+    link(&mut linker)?;
+    let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+    instance
+        .get_typed_func::<(), ()>(&store, "init")?
+        .call(&mut store, ())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_wat(wat: &str, limits: &Limits) -> Result<(), anyhow::Error> {
+        run(
+            "test",
+            &wat::parse_str(wat).unwrap(),
+            std::collections::HashMap::new(),
+            limits,
+        )
+    }
+
+    #[test]
+    fn test_msg_round_trip() {
+        // Unpacks an empty msg, adds an entry, packs it, unpacks it again and checks the entry is the same.
+        run_wat(
+            r#"
+            (module
+                (import "chaudloader" "buffer_new" (func $buffer_new (param i32 i32) (result i32)))
+                (import "chaudloader" "buffer_len" (func $buffer_len (param i32) (result i32)))
+                (import "chaudloader" "buffer_read" (func $buffer_read (param i32 i32)))
+                (import "chaudloader" "list_new" (func $list_new (result i32)))
+                (import "chaudloader" "list_push" (func $list_push (param i32 i32)))
+                (import "chaudloader" "list_get" (func $list_get (param i32 i32) (result i32)))
+                (import "chaudloader" "list_len" (func $list_len (param i32) (result i32)))
+                (import "chaudloader" "msg_pack" (func $msg_pack (param i32) (result i32)))
+                (import "chaudloader" "msg_unpack" (func $msg_unpack (param i32) (result i32)))
+                (import "chaudloader" "free" (func $free (param i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "\01\02\03")
+                (func (export "init")
+                    (local $list i32)
+                    (local $entry i32)
+                    (local.set $list (call $list_new))
+                    (call $list_push (local.get $list) (call $buffer_new (i32.const 0) (i32.const 3)))
+                    (local.set $list (call $msg_unpack (call $msg_pack (local.get $list))))
+                    (if (i32.ne (call $list_len (local.get $list)) (i32.const 1)) (then unreachable))
+                    (local.set $entry (call $list_get (local.get $list) (i32.const 0)))
+                    (if (i32.ne (call $buffer_len (local.get $entry)) (i32.const 3)) (then unreachable))
+                    (call $buffer_read (local.get $entry) (i32.const 16))
+                    (if (i32.ne (i32.load8_u (i32.const 18)) (i32.const 3)) (then unreachable))
+                    (call $free (local.get $entry))))
+            "#,
+            &Limits::default(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        let err = run_wat(
+            r#"
+            (module
+                (import "chaudloader" "list_new" (func $list_new (result i32)))
+                (import "chaudloader" "free" (func $free (param i32)))
+                (func (export "init")
+                    (local $list i32)
+                    (local.set $list (call $list_new))
+                    (call $free (local.get $list))
+                    (call $free (local.get $list))))
+            "#,
+            &Limits::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid handle: 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            fuel: 1_000_000,
+            memory: 2 * 65536,
+        };
+
+        assert!(
+            run_wat(
+                r#"(module (func (export "init") (loop $l (br $l))))"#,
+                &limits
+            )
+            .is_err()
+        );
+        assert!(run_wat(r#"(module (memory 4) (func (export "init")))"#, &limits).is_err());
+        assert!(
+            run_wat(
+                r#"(module (memory 1) (func (export "init")
+                    (if (i32.ne (memory.grow (i32.const 4)) (i32.const -1)) (then unreachable))))"#,
+                &limits
+            )
+            .is_ok()
+        );
+        assert!(
+            run_wat(
+                r#"(module
+                    (import "chaudloader" "exedat_open" (func $exedat_open (param i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (data (i32.const 0) "exe1.dat")
+                    (func (export "init") (drop (call $exedat_open (i32.const 0) (i32.const 8)))))"#,
+                &limits
+            )
+            .unwrap_err()
+            .to_string()
+            .contains("no such dat file: exe1.dat")
+        );
+    }
+
+    #[test]
+    fn test_host_memory_limit() {
+        let limits = Limits {
+            fuel: 1_000_000,
+            memory: 2 * 65536,
+        };
+
+        // Each buffer copies the whole page, so the second one doesn't fit next to the module's own page unless the first is freed.
+        let wat = |free: bool| {
+            format!(
+                r#"(module
+                    (import "chaudloader" "buffer_new" (func $buffer_new (param i32 i32) (result i32)))
+                    (import "chaudloader" "free" (func $free (param i32)))
+                    (memory (export "memory") 1)
+                    (func (export "init")
+                        (local $buf i32)
+                        (local.set $buf (call $buffer_new (i32.const 0) (i32.const 65536)))
+                        {}
+                        (drop (call $buffer_new (i32.const 0) (i32.const 65536)))))"#,
+                if free {
+                    "(call $free (local.get $buf))"
+                } else {
+                    ""
+                }
+            )
+        };
+        let err = run_wat(&wat(false), &limits).unwrap_err();
+        assert!(err.to_string().contains("memory limit"), "{}", err);
+        run_wat(&wat(true), &limits).unwrap();
+
+        // Host objects count against growing the module's memory too.
+        assert!(
+            run_wat(
+                r#"(module
+                    (import "chaudloader" "buffer_new" (func $buffer_new (param i32 i32) (result i32)))
+                    (memory (export "memory") 1)
+                    (func (export "init")
+                        (drop (call $buffer_new (i32.const 0) (i32.const 16)))
+                        (if (i32.ne (memory.grow (i32.const 1)) (i32.const -1)) (then unreachable))))"#,
+                &limits
+            )
+            .is_ok()
+        );
+    }
+}
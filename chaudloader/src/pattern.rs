@@ -0,0 +1,203 @@
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ParseError {
+    #[error("pattern is empty")]
+    Empty,
+
+    #[error("invalid byte {0:?} (expected two hex digits or ??)")]
+    InvalidByte(String),
+
+    #[error("pattern has no fixed bytes")]
+    AllWildcards,
+}
+
+/// A byte pattern with wildcards, e.g. `48 89 ?? 24`.
+pub struct Pattern {
+    bytes: Vec<Option<u8>>,
+
+    /// The longest run of fixed bytes, which is searched for first before checking the rest of the pattern.
+    anchor_offset: usize,
+    anchor: memchr::memmem::Finder<'static>,
+}
+
+impl std::str::FromStr for Pattern {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = s
+            .split_whitespace()
+            .map(|b| match b {
+                "?" | "??" => Ok(None),
+                _ if b.len() == 2 => u8::from_str_radix(b, 16)
+                    .map(Some)
+                    .map_err(|_| ParseError::InvalidByte(b.to_string())),
+                _ => Err(ParseError::InvalidByte(b.to_string())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(bytes)
+    }
+}
+
+impl Pattern {
+    pub fn new(bytes: Vec<Option<u8>>) -> Result<Self, ParseError> {
+        if bytes.is_empty() {
+            return Err(ParseError::Empty);
+        }
+
+        let mut anchor_range = 0..0;
+        let mut start = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            if b.is_none() {
+                start = i + 1;
+            } else if i + 1 - start > anchor_range.len() {
+                anchor_range = start..i + 1;
+            }
+        }
+        if anchor_range.is_empty() {
+            return Err(ParseError::AllWildcards);
+        }
+
+        let anchor = bytes[anchor_range.clone()]
+            .iter()
+            .map(|b| b.unwrap())
+            .collect::<Vec<_>>();
+        Ok(Self {
+            anchor_offset: anchor_range.start,
+            anchor: memchr::memmem::Finder::new(&anchor).into_owned(),
+            bytes,
+        })
+    }
+
+    fn matches_at(&self, haystack: &[u8], offset: usize) -> bool {
+        haystack
+            .get(offset..offset + self.bytes.len())
+            .map(|window| {
+                window
+                    .iter()
+                    .zip(self.bytes.iter())
+                    .all(|(b, p)| p.is_none_or(|p| p == *b))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Returns the offsets of all matches in `haystack`, in order. Matches may overlap.
+    pub fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        // memmem's find_iter skips over overlapping anchors, so search again from just after each one.
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let anchor_offset = start + self.anchor.find(haystack.get(start..)?)?;
+            start = anchor_offset + 1;
+            Some(anchor_offset)
+        })
+        .filter_map(|anchor_offset| anchor_offset.checked_sub(self.anchor_offset))
+        .filter(|offset| self.matches_at(haystack, *offset))
+    }
+
+    /// Returns the offset of the first match in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        self.find_iter(haystack).next()
+    }
+}
+
+/// Resolves the target of the relative instruction at `offset` in `code`, which is loaded at `base_addr`: the destination of a `call`/`jmp`/`jcc`, or the address referenced by a RIP-relative memory operand such as `lea rcx, [rip+disp32]`.
+pub fn resolve_relative(code: &[u8], base_addr: u64, offset: usize) -> Option<u64> {
+    let mut decoder = iced_x86::Decoder::with_ip(
+        64,
+        code.get(offset..)?,
+        base_addr + offset as u64,
+        iced_x86::DecoderOptions::NONE,
+    );
+    let instruction = decoder.decode();
+    if instruction.is_invalid() {
+        return None;
+    }
+    if instruction.is_ip_rel_memory_operand() {
+        return Some(instruction.ip_rel_memory_address());
+    }
+    match instruction.op0_kind() {
+        iced_x86::OpKind::NearBranch16
+        | iced_x86::OpKind::NearBranch32
+        | iced_x86::OpKind::NearBranch64 => Some(instruction.near_branch_target()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "48 89 ?? 24".parse::<Pattern>().unwrap().bytes,
+            [Some(0x48), Some(0x89), None, Some(0x24)]
+        );
+        assert_eq!(
+            "48 ? 5C".parse::<Pattern>().unwrap().bytes,
+            [Some(0x48), None, Some(0x5c)]
+        );
+        assert_eq!("".parse::<Pattern>().err(), Some(ParseError::Empty));
+        assert_eq!(
+            "48 8".parse::<Pattern>().err(),
+            Some(ParseError::InvalidByte("8".to_string()))
+        );
+        assert_eq!(
+            "48 zz".parse::<Pattern>().err(),
+            Some(ParseError::InvalidByte("zz".to_string()))
+        );
+        assert_eq!(
+            "?? ??".parse::<Pattern>().err(),
+            Some(ParseError::AllWildcards)
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let haystack = [
+            0x90, 0x48, 0x89, 0x5c, 0x24, 0x10, 0x48, 0x89, 0x74, 0x24, 0x48, 0x89,
+        ];
+        let pattern = "48 89 ?? 24".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.find_iter(&haystack).collect::<Vec<_>>(), [1, 6]);
+        assert_eq!(pattern.find(&haystack), Some(1));
+
+        // The anchor is the longest fixed run, so matches before it must still be found.
+        let pattern = "?? 89 ?? 24 10".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.find_iter(&haystack).collect::<Vec<_>>(), [1]);
+
+        // A leading wildcard can't match before the start.
+        let pattern = "?? 90".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.find(&haystack), None);
+
+        // Partial match at the end.
+        let pattern = "48 89 ?? 24".parse::<Pattern>().unwrap();
+        assert_eq!(pattern.find(&haystack[6..9]), None);
+
+        // Overlapping matches.
+        let pattern = "90 90".parse::<Pattern>().unwrap();
+        assert_eq!(
+            pattern.find_iter(&[0x90, 0x90, 0x90]).collect::<Vec<_>>(),
+            [0, 1]
+        );
+        let pattern = "48 ?? 48".parse::<Pattern>().unwrap();
+        assert_eq!(
+            pattern
+                .find_iter(&[0x48, 0x00, 0x48, 0x00, 0x48])
+                .collect::<Vec<_>>(),
+            [0, 2]
+        );
+    }
+
+    #[test]
+    fn test_resolve_relative() {
+        let code = [
+            0xe8, 0xfb, 0x0f, 0x00, 0x00, // call 0x140002000
+            0x48, 0x8d, 0x0d, 0xf4, 0xff, 0xff, 0xff, // lea rcx, [0x140001000]
+            0xeb, 0xfe, // jmp 0x14000100c
+            0x90, // nop
+        ];
+        assert_eq!(resolve_relative(&code, 0x140001000, 0), Some(0x140002000));
+        assert_eq!(resolve_relative(&code, 0x140001000, 5), Some(0x140001000));
+        assert_eq!(resolve_relative(&code, 0x140001000, 12), Some(0x14000100c));
+        assert_eq!(resolve_relative(&code, 0x140001000, 14), None);
+        assert_eq!(resolve_relative(&code, 0x140001000, 15), None);
+    }
+}
@@ -0,0 +1,368 @@
+use object::LittleEndian as LE;
+use object::read::pe::ImageNtHeaders;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub rva: u32,
+    pub size: u32,
+    pub characteristics: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    pub dll: String,
+
+    /// Either the name or the ordinal of the imported function is known.
+    pub name: Option<String>,
+    pub ordinal: Option<u16>,
+
+    /// The RVA of the import address table slot the loader writes the function's address into.
+    pub slot_rva: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportTarget {
+    Rva(u32),
+
+    /// Forwarded to another DLL, e.g. `NTDLL.RtlAllocateHeap`.
+    Forward(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Export {
+    pub name: Option<String>,
+    pub ordinal: u32,
+    pub target: ExportTarget,
+}
+
+/// A PE image as mapped into memory by the loader.
+#[derive(Clone, Debug, Default)]
+pub struct Image {
+    pub base: usize,
+    pub size: usize,
+    pub sections: Vec<Section>,
+    pub imports: Vec<Import>,
+    pub exports: Vec<Export>,
+}
+
+fn image_data(image: &[u8], rva: u32, size: u32) -> Result<&[u8], anyhow::Error> {
+    image
+        .get(rva as usize..rva as usize + size as usize)
+        .ok_or_else(|| anyhow::anyhow!("data directory at {:#x} is outside of the image", rva))
+}
+
+/// Reads `SizeOfImage` out of the headers at the start of a mapped image.
+pub fn size_of_image(headers: &[u8]) -> Result<usize, anyhow::Error> {
+    let dos_header = object::pe::ImageDosHeader::parse(headers)?;
+    let mut offset = dos_header.nt_headers_offset().into();
+    let (nt_headers, _) = object::pe::ImageNtHeaders64::parse(headers, &mut offset)?;
+    Ok(nt_headers.optional_header.size_of_image.get(LE) as usize)
+}
+
+impl Image {
+    /// Parses a 64-bit PE image mapped at `base`, where `image` covers the whole image (`SizeOfImage` bytes).
+    ///
+    /// Since the image is mapped, RVAs are offsets into `image`, not file offsets.
+    pub fn parse(image: &[u8], base: usize) -> Result<Self, anyhow::Error> {
+        let dos_header = object::pe::ImageDosHeader::parse(image)?;
+        let mut offset = dos_header.nt_headers_offset().into();
+        let (nt_headers, data_directories) =
+            object::pe::ImageNtHeaders64::parse(image, &mut offset)?;
+        let section_table =
+            object::read::pe::SectionTable::parse(nt_headers.file_header(), image, offset)?;
+
+        let sections = section_table
+            .iter()
+            .map(|section| Section {
+                name: String::from_utf8_lossy(section.raw_name()).into_owned(),
+                rva: section.virtual_address.get(LE),
+                size: section.virtual_size.get(LE),
+                characteristics: section.characteristics.get(LE),
+            })
+            .collect();
+
+        let mut imports = vec![];
+        if let Some(dir) = data_directories
+            .get(object::pe::IMAGE_DIRECTORY_ENTRY_IMPORT)
+            .filter(|dir| dir.virtual_address.get(LE) != 0)
+        {
+            let table = object::read::pe::ImportTable::new(image, 0, dir.virtual_address.get(LE));
+            let mut descriptors = table.descriptors()?;
+            while let Some(descriptor) = descriptors.next()? {
+                let dll =
+                    String::from_utf8_lossy(table.name(descriptor.name.get(LE))?).into_owned();
+                // The import address table is overwritten with addresses once loaded, so names have to come from the import lookup table.
+                let first_thunk = descriptor.first_thunk.get(LE);
+                let mut thunks = table.thunks(match descriptor.original_first_thunk.get(LE) {
+                    0 => first_thunk,
+                    rva => rva,
+                })?;
+                let mut slot_rva = first_thunk;
+                while let Some(thunk) = thunks.next::<object::pe::ImageNtHeaders64>()? {
+                    let (name, ordinal) =
+                        match table.import::<object::pe::ImageNtHeaders64>(thunk)? {
+                            object::read::pe::Import::Name(_, name) => {
+                                (Some(String::from_utf8_lossy(name).into_owned()), None)
+                            }
+                            object::read::pe::Import::Ordinal(ordinal) => (None, Some(ordinal)),
+                        };
+                    imports.push(Import {
+                        dll: dll.clone(),
+                        name,
+                        ordinal,
+                        slot_rva,
+                    });
+                    slot_rva += std::mem::size_of::<u64>() as u32;
+                }
+            }
+        }
+
+        let mut exports = vec![];
+        if let Some(dir) = data_directories
+            .get(object::pe::IMAGE_DIRECTORY_ENTRY_EXPORT)
+            .filter(|dir| dir.virtual_address.get(LE) != 0)
+        {
+            let (rva, size) = dir.address_range();
+            let table = object::read::pe::ExportTable::parse(image_data(image, rva, size)?, rva)?;
+            for export in table.exports()? {
+                exports.push(Export {
+                    name: export
+                        .name
+                        .map(|name| String::from_utf8_lossy(name).into_owned()),
+                    ordinal: export.ordinal,
+                    target: match export.target {
+                        object::read::pe::ExportTarget::Address(rva) => ExportTarget::Rva(rva),
+                        object::read::pe::ExportTarget::ForwardByName(dll, name) => {
+                            ExportTarget::Forward(format!(
+                                "{}.{}",
+                                String::from_utf8_lossy(dll),
+                                String::from_utf8_lossy(name)
+                            ))
+                        }
+                        object::read::pe::ExportTarget::ForwardByOrdinal(dll, ordinal) => {
+                            ExportTarget::Forward(format!(
+                                "{}.#{}",
+                                String::from_utf8_lossy(dll),
+                                ordinal
+                            ))
+                        }
+                    },
+                });
+            }
+        }
+
+        Ok(Self {
+            base,
+            size: image.len(),
+            sections,
+            imports,
+            exports,
+        })
+    }
+
+    pub fn rva_to_va(&self, rva: u32) -> Option<usize> {
+        if rva as usize >= self.size {
+            return None;
+        }
+        Some(self.base + rva as usize)
+    }
+
+    pub fn va_to_rva(&self, va: usize) -> Option<u32> {
+        let rva = va.checked_sub(self.base)?;
+        if rva >= self.size {
+            return None;
+        }
+        Some(rva as u32)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    /// Builds a minimal mapped image with a .text section and an .rdata section holding an import and export table.
+    fn make_image() -> Vec<u8> {
+        let mut image = vec![0u8; 0x3000];
+        let w16 = |image: &mut [u8], offset: usize, v: u16| {
+            LittleEndian::write_u16(&mut image[offset..], v)
+        };
+        let w32 = |image: &mut [u8], offset: usize, v: u32| {
+            LittleEndian::write_u32(&mut image[offset..], v)
+        };
+        let w64 = |image: &mut [u8], offset: usize, v: u64| {
+            LittleEndian::write_u64(&mut image[offset..], v)
+        };
+        let wstr = |image: &mut [u8], offset: usize, s: &str| {
+            image[offset..offset + s.len()].copy_from_slice(s.as_bytes())
+        };
+
+        // DOS header.
+        wstr(&mut image, 0, "MZ");
+        w32(&mut image, 0x3c, 0x40);
+
+        // NT headers.
+        wstr(&mut image, 0x40, "PE\0\0");
+        w16(&mut image, 0x44, object::pe::IMAGE_FILE_MACHINE_AMD64);
+        w16(&mut image, 0x46, 2);
+        w16(&mut image, 0x54, 240);
+        w16(&mut image, 0x58, object::pe::IMAGE_NT_OPTIONAL_HDR64_MAGIC);
+        w64(&mut image, 0x58 + 24, 0x140000000);
+        w32(&mut image, 0x58 + 56, 0x3000);
+        w32(&mut image, 0x58 + 108, 16);
+        let data_directory = |index: usize| 0x58 + 112 + index * 8;
+        w32(
+            &mut image,
+            data_directory(object::pe::IMAGE_DIRECTORY_ENTRY_EXPORT),
+            0x2800,
+        );
+        w32(
+            &mut image,
+            data_directory(object::pe::IMAGE_DIRECTORY_ENTRY_EXPORT) + 4,
+            0x100,
+        );
+        w32(
+            &mut image,
+            data_directory(object::pe::IMAGE_DIRECTORY_ENTRY_IMPORT),
+            0x2000,
+        );
+        w32(
+            &mut image,
+            data_directory(object::pe::IMAGE_DIRECTORY_ENTRY_IMPORT) + 4,
+            40,
+        );
+
+        // Section table.
+        for (i, (name, rva, size, characteristics)) in [
+            (
+                ".text",
+                0x1000,
+                0x10,
+                object::pe::IMAGE_SCN_CNT_CODE
+                    | object::pe::IMAGE_SCN_MEM_EXECUTE
+                    | object::pe::IMAGE_SCN_MEM_READ,
+            ),
+            (
+                ".rdata",
+                0x2000,
+                0x1000,
+                object::pe::IMAGE_SCN_CNT_INITIALIZED_DATA | object::pe::IMAGE_SCN_MEM_READ,
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let offset = 0x148 + i * 40;
+            wstr(&mut image, offset, name);
+            w32(&mut image, offset + 8, size);
+            w32(&mut image, offset + 12, rva);
+            w32(&mut image, offset + 36, characteristics);
+        }
+
+        // Import descriptor for KERNEL32.dll, followed by a null descriptor.
+        w32(&mut image, 0x2000, 0x2100);
+        w32(&mut image, 0x2000 + 12, 0x2200);
+        w32(&mut image, 0x2000 + 16, 0x2300);
+        w64(&mut image, 0x2100, 0x2400);
+        w64(&mut image, 0x2108, object::pe::IMAGE_ORDINAL_FLAG64 | 7);
+        // The import address table, as overwritten by the loader.
+        w64(&mut image, 0x2300, 0x7ff800001000);
+        w64(&mut image, 0x2308, 0x7ff800002000);
+        wstr(&mut image, 0x2200, "KERNEL32.dll\0");
+        wstr(&mut image, 0x2402, "CreateFileW\0");
+
+        // Export directory, with one named export and one forwarded by ordinal only.
+        w32(&mut image, 0x2800 + 16, 1);
+        w32(&mut image, 0x2800 + 20, 2);
+        w32(&mut image, 0x2800 + 24, 1);
+        w32(&mut image, 0x2800 + 28, 0x2840);
+        w32(&mut image, 0x2800 + 32, 0x2848);
+        w32(&mut image, 0x2800 + 36, 0x284c);
+        w32(&mut image, 0x2840, 0x1000);
+        w32(&mut image, 0x2844, 0x2860);
+        w32(&mut image, 0x2848, 0x2870);
+        w16(&mut image, 0x284c, 0);
+        wstr(&mut image, 0x2860, "NTDLL.RtlFoo\0");
+        wstr(&mut image, 0x2870, "on_game_load\0");
+
+        image
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(size_of_image(&make_image()[..0x1000]).unwrap(), 0x3000);
+
+        let image = Image::parse(&make_image(), 0x140000000).unwrap();
+        assert_eq!(
+            image.sections,
+            [
+                Section {
+                    name: ".text".to_string(),
+                    rva: 0x1000,
+                    size: 0x10,
+                    characteristics: 0x60000020,
+                },
+                Section {
+                    name: ".rdata".to_string(),
+                    rva: 0x2000,
+                    size: 0x1000,
+                    characteristics: 0x40000040,
+                },
+            ]
+        );
+        assert_eq!(
+            image.imports,
+            [
+                Import {
+                    dll: "KERNEL32.dll".to_string(),
+                    name: Some("CreateFileW".to_string()),
+                    ordinal: None,
+                    slot_rva: 0x2300,
+                },
+                Import {
+                    dll: "KERNEL32.dll".to_string(),
+                    name: None,
+                    ordinal: Some(7),
+                    slot_rva: 0x2308,
+                },
+            ]
+        );
+        assert_eq!(
+            image.exports,
+            [
+                Export {
+                    name: Some("on_game_load".to_string()),
+                    ordinal: 1,
+                    target: ExportTarget::Rva(0x1000),
+                },
+                Export {
+                    name: None,
+                    ordinal: 2,
+                    target: ExportTarget::Forward("NTDLL.RtlFoo".to_string()),
+                },
+            ]
+        );
+        assert_eq!(image.section(".rdata").unwrap().rva, 0x2000);
+        assert!(image.section(".data").is_none());
+    }
+
+    #[test]
+    fn test_rva() {
+        let image = Image::parse(&make_image(), 0x140000000).unwrap();
+        assert_eq!(image.rva_to_va(0x1000), Some(0x140001000));
+        assert_eq!(image.rva_to_va(0x3000), None);
+        assert_eq!(image.va_to_rva(0x140002fff), Some(0x2fff));
+        assert_eq!(image.va_to_rva(0x13fffffff), None);
+        assert_eq!(image.va_to_rva(0x140003000), None);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Image::parse(&[0u8; 0x100], 0).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{assets, mods};
+
+/// How long to wait for the game to evaluate code in a mod's Lua state before giving up on the result.
+const PENDING_EVAL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+type Request = (String, oneshot::Sender<String>);
+
+static SENDER: std::sync::OnceLock<std::sync::mpsc::Sender<Request>> = std::sync::OnceLock::new();
+
+/// Evaluates a line of Lua: as an expression if it parses as one, otherwise as statements.
+///
+/// Returns the results converted with `tostring`, separated by tabs.
+pub fn eval_lua(lua: &mlua::Lua, code: &str) -> Result<String, mlua::Error> {
+    let values = match lua
+        .load(format!("return {}", code))
+        .set_name("=repl")
+        .set_mode(mlua::ChunkMode::Text)
+        .into_function()
+    {
+        Ok(f) => f.call::<_, mlua::MultiValue>(())?,
+        Err(_) => lua
+            .load(code)
+            .set_name("=repl")
+            .set_mode(mlua::ChunkMode::Text)
+            .call::<_, mlua::MultiValue>(())?,
+    };
+    let tostring = lua.globals().get::<_, mlua::Function>("tostring")?;
+    Ok(values
+        .into_iter()
+        .map(|v| tostring.call::<_, String>(v))
+        .collect::<Result<Vec<_>, _>>()?
+        .join("\t"))
+}
+
+/// Handles a line of REPL input. `@name code` evaluates `code` in the persistent mod `name` instead of the REPL's own state.
+fn handle(lua: &mlua::Lua, line: &str) -> String {
+    let line = line.trim();
+    if line.is_empty() {
+        return "".to_string();
+    }
+
+    if let Some(rest) = line.strip_prefix('@') {
+        let (name, code) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        return match mods::lua::queue_eval(name, code).recv_timeout(PENDING_EVAL_TIMEOUT) {
+            Ok(Ok(output)) => output,
+            Ok(Err(e)) => format!("error: {}", e),
+            Err(oneshot::RecvTimeoutError::Timeout) => {
+                "queued: the result will be logged at the next frame or game load".to_string()
+            }
+            Err(oneshot::RecvTimeoutError::Disconnected) => "error: evaluation dropped".to_string(),
+        };
+    }
+
+    match eval_lua(lua, line) {
+        Ok(output) => output,
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+/// Evaluates a line of input on the REPL thread.
+pub fn eval(line: &str) -> String {
+    let Some(sender) = SENDER.get() else {
+        return "error: REPL is not running".to_string();
+    };
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    if sender.send((line.to_string(), reply_sender)).is_err() {
+        return "error: REPL is not running".to_string();
+    }
+    reply_receiver
+        .recv()
+        .unwrap_or_else(|_| "error: REPL is not running".to_string())
+}
+
+/// Makes a token that is unguessable for other processes, using the OS-seeded keys of `RandomState`.
+fn new_token() -> String {
+    use std::hash::{BuildHasher, Hasher};
+
+    (0..2)
+        .map(|_| {
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_u128(
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+/// Whether a line looks like an HTTP request line, e.g. `POST / HTTP/1.1`.
+///
+/// Web pages can send requests to localhost ports, so line-based servers drop these rather than treating the body as commands.
+pub fn is_http_request_line(line: &str) -> bool {
+    line.rsplit_once(' ')
+        .is_some_and(|(_, version)| version.starts_with("HTTP/"))
+}
+
+fn handle_connection(
+    stream: std::net::TcpStream,
+    token: &str,
+    eval: fn(&str) -> String,
+) -> Result<(), std::io::Error> {
+    use std::io::{BufRead, Write};
+
+    let mut writer = stream.try_clone()?;
+    let mut lines = std::io::BufReader::new(stream).lines();
+
+    // Browsers can send requests to localhost too, so the client must first prove it can read the console.
+    let Some(first_line) = lines.next().transpose()? else {
+        return Ok(());
+    };
+    if is_http_request_line(&first_line) {
+        return Ok(());
+    }
+    if first_line.trim() != token {
+        writeln!(writer, "error: invalid token")?;
+        return Ok(());
+    }
+
+    write!(writer, "> ")?;
+    for line in lines {
+        let output = eval(&line?);
+        if !output.is_empty() {
+            writeln!(writer, "{}", output)?;
+        }
+        write!(writer, "> ")?;
+    }
+    Ok(())
+}
+
+/// Serves REPL clients on `listener` in the background, each on its own thread. Clients must send `token` as their first line.
+pub fn serve(listener: std::net::TcpListener, token: String, eval: fn(&str) -> String) {
+    let token = std::sync::Arc::<str>::from(token);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::error!("repl: {}", e);
+                    continue;
+                }
+            };
+            let token = token.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &token, eval) {
+                    log::warn!("repl: connection closed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// Starts the REPL thread, which owns the REPL's Lua state, and serves it on `127.0.0.1:<port>` if a port is given.
+///
+/// Clients of the port must send the token printed to the console as their first line.
+///
+/// The REPL reads the original dat files, so changes made through `chaudloader.exedat` do not reach the game.
+pub fn start(
+    game_env: mods::GameEnv,
+    mut mod_names: Vec<String>,
+    port: Option<u16>,
+) -> Result<(), anyhow::Error> {
+    mod_names.sort();
+    let (sender, receiver) = std::sync::mpsc::channel::<Request>();
+    SENDER
+        .set(sender)
+        .map_err(|_| anyhow::anyhow!("REPL already started"))?;
+
+    std::thread::spawn(move || {
+        let lua = match assets::exedat::scan()
+            .map_err(mlua::Error::external)
+            .and_then(|overlays| {
+                mods::lua::new_repl(
+                    &game_env,
+                    mod_names,
+                    overlays
+                        .into_iter()
+                        .map(|(k, v)| (k, std::sync::Arc::new(std::sync::Mutex::new(v))))
+                        .collect(),
+                )
+            }) {
+            Ok(lua) => lua,
+            Err(e) => {
+                log::error!("repl: failed to init: {}", e);
+                return;
+            }
+        };
+
+        for (line, reply_sender) in receiver {
+            let _ = reply_sender.send(handle(&lua, &line));
+        }
+    });
+
+    if let Some(port) = port {
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port))?;
+        let token = new_token();
+        log::info!(
+            "repl listening on {}, send this token as the first line: {}",
+            listener.local_addr()?,
+            token
+        );
+        serve(listener, token, eval);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_lua() {
+        let lua = mlua::Lua::new();
+        assert_eq!(eval_lua(&lua, "1 + 1").unwrap(), "2");
+        assert_eq!(eval_lua(&lua, "x = 'a'").unwrap(), "");
+        assert_eq!(
+            eval_lua(&lua, "x, nil, {}").unwrap().split('\t').nth(1),
+            Some("nil")
+        );
+        assert_eq!(
+            eval_lua(&lua, "for i = 1, 2 do x = x .. i end return x").unwrap(),
+            "a12"
+        );
+        assert!(
+            eval_lua(&lua, "error('oops')")
+                .unwrap_err()
+                .to_string()
+                .contains("oops")
+        );
+        assert_eq!(handle(&lua, "  "), "");
+        assert!(handle(&lua, "1 +").starts_with("error: "));
+    }
+
+    #[test]
+    fn test_serve() {
+        use std::io::{BufRead, Write};
+
+        let listener = std::net::TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        serve(listener, "secret".to_string(), |line| line.to_uppercase());
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(b"secret\nabc\n\nde\n").unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "> ABC");
+        assert_eq!(lines.next().unwrap().unwrap(), "> > DE");
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream.write_all(b"wrong\nabc\n").unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "error: invalid token");
+        assert!(lines.next().is_none());
+
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\n\r\nsecret\n")
+            .unwrap();
+        let mut lines = std::io::BufReader::new(stream).lines();
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_new_token() {
+        let token = new_token();
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, new_token());
+    }
+}
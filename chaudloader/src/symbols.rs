@@ -0,0 +1,155 @@
+use crate::{hooks, mods};
+
+/// Named addresses in the game executable, from a `symbols` directory:
+///
+/// - `<exe_crc32>.toml` (e.g. `1a2b3c4d.toml`): exact addresses for a specific executable, relative to the image base, as `name = 0x1000`.
+/// - `signatures.toml`: signatures to scan for when there's no exact address, in the same format as the loader's hook signatures.
+#[derive(Default, Debug)]
+pub struct SymbolTable {
+    addresses: std::collections::HashMap<u32, std::collections::HashMap<String, usize>>,
+    signatures: hooks::signatures::Signatures,
+
+    /// Results of signature scans, which don't change for the lifetime of the process.
+    scanned: std::sync::Mutex<std::collections::HashMap<String, Option<usize>>>,
+}
+
+const SIGNATURES_FILE_NAME: &str = "signatures.toml";
+
+impl SymbolTable {
+    /// Loads all symbol files in `dir`. A missing directory is treated as empty.
+    pub fn load(dir: &std::path::Path) -> Result<Self, anyhow::Error> {
+        let mut table = Self::default();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(table);
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+                continue;
+            }
+            let contents = std::fs::read_to_string(&path)?;
+            table
+                .add_file(&path.file_name().unwrap().to_string_lossy(), &contents)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+        }
+        Ok(table)
+    }
+
+    /// Adds the contents of a symbol file named `file_name`.
+    fn add_file(&mut self, file_name: &str, contents: &str) -> Result<(), anyhow::Error> {
+        if file_name == SIGNATURES_FILE_NAME {
+            self.signatures = hooks::signatures::parse(contents)?;
+            return Ok(());
+        }
+
+        let exe_crc32 = file_name
+            .strip_suffix(".toml")
+            .and_then(|stem| u32::from_str_radix(stem, 16).ok())
+            .ok_or_else(|| {
+                anyhow::anyhow!("expected a file named after an exe CRC32 (e.g. 1a2b3c4d.toml)")
+            })?;
+        self.addresses.insert(exe_crc32, toml::from_str(contents)?);
+        Ok(())
+    }
+
+    /// Resolves a symbol for the running executable, by exact address first and signature scan second.
+    pub fn resolve(&self, name: &str, game_env: &mods::GameEnv) -> Option<usize> {
+        if let Some(address) = self
+            .addresses
+            .get(&game_env.exe_crc32)
+            .and_then(|addresses| addresses.get(name))
+        {
+            return Some(game_env.image.base.wrapping_add(*address));
+        }
+
+        let signatures = self.signatures.get(name)?;
+        let mut scanned = self.scanned.lock().unwrap();
+        *scanned.entry(name.to_string()).or_insert_with(|| {
+            let text = game_env.sections.text?;
+            hooks::signatures::resolve(
+                signatures,
+                text,
+                game_env.image.base,
+                game_env.exe_crc32,
+                &[],
+            )
+            .inspect_err(|e| log::warn!("symbol {}: {}", name, e))
+            .ok()
+            .map(|resolved| resolved.address)
+        })
+    }
+}
+
+const LOADER_SYMBOLS_DIR: &str = "symbols";
+
+/// Symbols provided by the loader, in the `symbols` directory of the game's exe folder.
+pub static LOADER_SYMBOLS: std::sync::LazyLock<SymbolTable> = std::sync::LazyLock::new(|| {
+    SymbolTable::load(std::path::Path::new(LOADER_SYMBOLS_DIR))
+        .inspect_err(|e| log::error!("cannot load symbols: {e}"))
+        .unwrap_or_default()
+});
+
+/// Resolves a symbol for a mod, from the mod's own symbols first and the loader's second.
+pub fn resolve(mod_symbols: &SymbolTable, name: &str, game_env: &mods::GameEnv) -> Option<usize> {
+    mod_symbols
+        .resolve(name, game_env)
+        .or_else(|| LOADER_SYMBOLS.resolve(name, game_env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_env(exe_crc32: u32, text: &'static [u8]) -> mods::GameEnv {
+        mods::GameEnv {
+            volume: crate::GameVolume::Vol2,
+            exe_crc32,
+            sections: mods::Sections { text: Some(text) },
+            image: crate::pe::Image {
+                base: 0x140000000,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_add_file_and_resolve() {
+        static TEXT: [u8; 6] = [0x90, 0x90, 0x48, 0x89, 0x5c, 0x24];
+
+        let mut table = SymbolTable::default();
+        table.add_file("1a2b3c4d.toml", "foo = 0x1000\n").unwrap();
+        table
+            .add_file(
+                "signatures.toml",
+                "[[foo]]\npattern = \"ff ff\"\n\n[[bar]]\npattern = \"48 89 ?? 24\"\n",
+            )
+            .unwrap();
+
+        let env = game_env(0x1a2b3c4d, &TEXT);
+        assert_eq!(table.resolve("foo", &env), Some(0x140001000));
+        assert_eq!(table.resolve("bar", &env), Some(TEXT.as_ptr() as usize + 2));
+        assert_eq!(table.resolve("baz", &env), None);
+
+        // Other executables only get signature scans.
+        let env = game_env(0x11111111, &TEXT);
+        assert_eq!(table.resolve("foo", &env), None);
+        assert_eq!(table.resolve("bar", &env), Some(TEXT.as_ptr() as usize + 2));
+
+        assert!(table.add_file("not-a-crc.toml", "").is_err());
+        assert!(table.add_file("1a2b3c4d.toml", "foo = \"bar\"").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_dir() {
+        let table = SymbolTable::load(std::path::Path::new("does-not-exist/symbols")).unwrap();
+        assert!(table.addresses.is_empty());
+        assert!(table.signatures.is_empty());
+    }
+}
@@ -0,0 +1,943 @@
+use crate::gba_asm;
+
+#[derive(thiserror::Error, Debug)]
+#[error("line {line}: {message}")]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+pub struct Assembly {
+    pub code: Vec<u8>,
+
+    /// The addresses of all labels defined in the source.
+    pub labels: std::collections::BTreeMap<String, u64>,
+}
+
+pub struct DisassembledInstruction {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+/// Label addresses depend on instruction sizes, which depend on label addresses (e.g. short vs near jumps), so assembly is repeated until they agree.
+const MAX_PASSES: usize = 16;
+
+static REGISTERS: std::sync::LazyLock<std::collections::HashMap<String, iced_x86::Register>> =
+    std::sync::LazyLock::new(|| {
+        let mut registers = iced_x86::Register::values()
+            .filter(|r| *r != iced_x86::Register::None)
+            .map(|r| (format!("{:?}", r).to_ascii_lowercase(), r))
+            .filter(|(name, _)| !name.starts_with("dontuse"))
+            .collect::<std::collections::HashMap<_, _>>();
+        // iced calls the low bytes of r8-r15 r8l-r15l, but they are usually spelled r8b-r15b.
+        for i in 8..16 {
+            let r = registers[&format!("r{}l", i)];
+            registers.insert(format!("r{}b", i), r);
+        }
+        registers
+    });
+
+/// All encodings usable in 64-bit mode, by lowercase mnemonic.
+static CODES: std::sync::LazyLock<std::collections::HashMap<String, Vec<iced_x86::Code>>> =
+    std::sync::LazyLock::new(|| {
+        let mut codes = std::collections::HashMap::<String, Vec<iced_x86::Code>>::new();
+        for code in iced_x86::Code::values() {
+            let op_code = code.op_code();
+            if !op_code.is_instruction()
+                || !op_code.mode64()
+                || !matches!(
+                    op_code.encoding(),
+                    iced_x86::EncodingKind::Legacy | iced_x86::EncodingKind::VEX
+                )
+            {
+                continue;
+            }
+            codes
+                .entry(format!("{:?}", code.mnemonic()).to_ascii_lowercase())
+                .or_default()
+                .push(code);
+        }
+        codes
+    });
+
+/// Alternative spellings of condition codes, for `j`, `cmov` and `set` instructions.
+const CONDITION_ALIASES: &[(&str, &str)] = &[
+    ("z", "e"),
+    ("nz", "ne"),
+    ("c", "b"),
+    ("nae", "b"),
+    ("nc", "ae"),
+    ("nb", "ae"),
+    ("nbe", "a"),
+    ("na", "be"),
+    ("pe", "p"),
+    ("po", "np"),
+    ("nge", "l"),
+    ("nl", "ge"),
+    ("ng", "le"),
+    ("nle", "g"),
+];
+
+const MEMORY_SIZES: &[(&str, usize)] = &[
+    ("byte", 1),
+    ("word", 2),
+    ("dword", 4),
+    ("qword", 8),
+    ("tword", 10),
+    ("tbyte", 10),
+    ("oword", 16),
+    ("xmmword", 16),
+    ("ymmword", 32),
+];
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Reg(iced_x86::Register),
+    Mem(iced_x86::MemoryOperand, Option<usize>),
+    Imm(i64),
+}
+
+enum StatementKind {
+    Instruction {
+        prefixes: Vec<String>,
+        mnemonic: String,
+        operands: Vec<String>,
+    },
+    Data {
+        size: usize,
+        operands: Vec<String>,
+    },
+}
+
+struct Statement {
+    line: usize,
+    labels: Vec<String>,
+    kind: Option<StatementKind>,
+}
+
+fn canonical_mnemonic(mnemonic: &str) -> String {
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    for prefix in ["j", "cmov", "set"] {
+        if let Some((_, cond)) = mnemonic
+            .strip_prefix(prefix)
+            .and_then(|cond| CONDITION_ALIASES.iter().find(|(alias, _)| *alias == cond))
+        {
+            return format!("{}{}", prefix, cond);
+        }
+    }
+    mnemonic
+}
+
+fn parse_statement(line: usize, s: &str) -> Result<Statement, String> {
+    let mut s = gba_asm::strip_comment(s).trim();
+    let mut labels = vec![];
+    while let Some((name, rest)) = s.split_once(':') {
+        let name = name.trim();
+        // Stop at segment overrides such as `gs:[0x58]`.
+        if name.is_empty()
+            || !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+            || name.contains(char::is_whitespace)
+        {
+            break;
+        }
+        labels.push(name.to_string());
+        s = rest.trim();
+    }
+
+    let mut words = s.splitn(2, char::is_whitespace);
+    let mut mnemonic = words.next().unwrap_or("").to_ascii_lowercase();
+    let mut rest = words.next().unwrap_or("").trim().to_string();
+    let mut prefixes = vec![];
+    while matches!(
+        mnemonic.as_str(),
+        "lock" | "rep" | "repe" | "repz" | "repne" | "repnz"
+    ) {
+        prefixes.push(mnemonic);
+        let mut words = rest.splitn(2, char::is_whitespace);
+        mnemonic = words.next().unwrap_or("").to_ascii_lowercase();
+        rest = words.next().unwrap_or("").trim().to_string();
+    }
+
+    let operands = gba_asm::split_operands(&rest);
+    let kind = match mnemonic.as_str() {
+        "" if prefixes.is_empty() => None,
+        "" => return Err("expected instruction after prefix".to_string()),
+        "db" | "dw" | "dd" | "dq" => Some(StatementKind::Data {
+            size: match mnemonic.as_str() {
+                "db" => 1,
+                "dw" => 2,
+                "dd" => 4,
+                _ => 8,
+            },
+            operands,
+        }),
+        _ => Some(StatementKind::Instruction {
+            prefixes,
+            mnemonic: canonical_mnemonic(&mnemonic),
+            operands,
+        }),
+    };
+
+    Ok(Statement { line, labels, kind })
+}
+
+/// Splits the inside of a memory operand into signed terms, e.g. `rax + rcx*4 - 8` into `+rax`, `+rcx*4` and `-8`.
+fn split_terms(s: &str) -> Vec<(bool, &str)> {
+    let mut terms = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut negative = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '+' | '-' if depth == 0 => {
+                let term = s[start..i].trim();
+                if term.is_empty() {
+                    // A leading sign.
+                    negative ^= c == '-';
+                } else {
+                    terms.push((negative, term));
+                    negative = c == '-';
+                }
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    terms.push((negative, s[start..].trim()));
+    terms
+}
+
+struct Context<'a> {
+    pc: u64,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Context<'_> {
+    fn eval(&self, expr: &str) -> Result<i64, String> {
+        gba_asm::eval_expr(expr, self.pc as i64, self.lookup)
+    }
+
+    fn parse_mem(&self, s: &str) -> Result<Operand, String> {
+        let (prefix, inner) = s.split_once('[').unwrap();
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| format!("invalid memory operand {}", s))?
+            .trim();
+
+        let mut size = None;
+        let mut segment = iced_x86::Register::None;
+        let parse_segment = |s: &str| {
+            let segment = REGISTERS
+                .get(&s.trim().to_ascii_lowercase())
+                .copied()
+                .filter(|r| r.is_segment_register());
+            segment.ok_or_else(|| format!("invalid segment {}", s))
+        };
+        for word in prefix.split_whitespace() {
+            let word = word.to_ascii_lowercase();
+            if let Some((_, n)) = MEMORY_SIZES.iter().find(|(name, _)| *name == word) {
+                size = Some(*n);
+            } else if word == "ptr" {
+            } else if let Some(seg) = word.strip_suffix(':') {
+                segment = parse_segment(seg)?;
+            } else {
+                return Err(format!("invalid memory operand {}", s));
+            }
+        }
+
+        let mut inner = inner;
+        if let Some((seg, rest)) = inner.split_once(':') {
+            segment = parse_segment(seg)?;
+            inner = rest.trim();
+        }
+        let mut absolute = segment != iced_x86::Register::None;
+        if let Some(rest) = inner.strip_prefix("abs ") {
+            absolute = true;
+            inner = rest.trim();
+        } else if let Some(rest) = inner.strip_prefix("rel ") {
+            absolute = false;
+            inner = rest.trim();
+        }
+
+        let mut base = iced_x86::Register::None;
+        let mut index = iced_x86::Register::None;
+        let mut scale = 1;
+        let mut displacement = 0i64;
+        for (negative, term) in split_terms(inner) {
+            let reg = |s: &str| REGISTERS.get(&s.trim().to_ascii_lowercase()).copied();
+            if let Some(r) = reg(term) {
+                if negative {
+                    return Err(format!("cannot subtract register {}", term));
+                }
+                if r == iced_x86::Register::RIP {
+                    return Err(
+                        "use [target] or [rel target] for rip-relative addressing".to_string()
+                    );
+                }
+                if base == iced_x86::Register::None {
+                    base = r;
+                } else if index == iced_x86::Register::None {
+                    index = r;
+                } else {
+                    return Err(format!("too many registers in {}", s));
+                }
+                continue;
+            }
+            let scaled = term
+                .split_once('*')
+                .and_then(|(lhs, rhs)| match (reg(lhs), reg(rhs)) {
+                    (Some(r), None) => Some((r, rhs)),
+                    (None, Some(r)) => Some((r, lhs)),
+                    _ => None,
+                });
+            if let Some((r, factor)) = scaled {
+                if negative || index != iced_x86::Register::None {
+                    return Err(format!("invalid index in {}", s));
+                }
+                index = r;
+                scale = self.eval(factor)?;
+                if ![1, 2, 4, 8].contains(&scale) {
+                    return Err(format!("invalid scale {}", scale));
+                }
+                continue;
+            }
+            let v = self.eval(term)?;
+            displacement = if negative {
+                displacement.wrapping_sub(v)
+            } else {
+                displacement.wrapping_add(v)
+            };
+        }
+
+        let mem = if base == iced_x86::Register::None && index == iced_x86::Register::None {
+            if absolute {
+                iced_x86::MemoryOperand::new(base, index, 1, displacement, 8, false, segment)
+            } else {
+                // Like NASM's `default rel`: bare addresses are encoded relative to the instruction.
+                iced_x86::MemoryOperand::new(
+                    iced_x86::Register::RIP,
+                    index,
+                    1,
+                    displacement,
+                    8,
+                    false,
+                    segment,
+                )
+            }
+        } else {
+            iced_x86::MemoryOperand::new(
+                base,
+                index,
+                scale as u32,
+                displacement,
+                if displacement != 0 { 1 } else { 0 },
+                false,
+                segment,
+            )
+        };
+        Ok(Operand::Mem(mem, size))
+    }
+
+    fn parse_operand(&self, s: &str) -> Result<Operand, String> {
+        if s.contains('[') {
+            return self.parse_mem(s);
+        }
+        if let Some(r) = REGISTERS.get(&s.to_ascii_lowercase()) {
+            return Ok(Operand::Reg(*r));
+        }
+        Ok(Operand::Imm(self.eval(s)?))
+    }
+}
+
+fn operand_matches(kind: iced_x86::OpCodeOperandKind, operand: &Operand) -> bool {
+    use iced_x86::OpCodeOperandKind as K;
+    use iced_x86::Register as R;
+    match operand {
+        Operand::Reg(r) => match kind {
+            K::r8_reg | K::r8_opcode | K::r8_or_mem => r.is_gpr8(),
+            K::r16_reg | K::r16_reg_mem | K::r16_rm | K::r16_opcode | K::r16_or_mem => r.is_gpr16(),
+            K::r32_reg
+            | K::r32_reg_mem
+            | K::r32_rm
+            | K::r32_opcode
+            | K::r32_vvvv
+            | K::r32_or_mem => r.is_gpr32(),
+            K::r64_reg
+            | K::r64_reg_mem
+            | K::r64_rm
+            | K::r64_opcode
+            | K::r64_vvvv
+            | K::r64_or_mem => r.is_gpr64(),
+            K::mm_reg | K::mm_rm | K::mm_or_mem => r.is_mm(),
+            K::xmm_reg | K::xmm_rm | K::xmm_vvvv | K::xmm_is4 | K::xmm_or_mem => r.is_xmm(),
+            K::ymm_reg | K::ymm_rm | K::ymm_vvvv | K::ymm_is4 | K::ymm_or_mem => r.is_ymm(),
+            K::seg_reg => r.is_segment_register(),
+            K::cr_reg => r.is_cr(),
+            K::dr_reg => r.is_dr(),
+            K::sti_opcode => r.is_st(),
+            K::st0 => *r == R::ST0,
+            K::al => *r == R::AL,
+            K::cl => *r == R::CL,
+            K::ax => *r == R::AX,
+            K::dx => *r == R::DX,
+            K::eax => *r == R::EAX,
+            K::rax => *r == R::RAX,
+            K::es => *r == R::ES,
+            K::cs => *r == R::CS,
+            K::ss => *r == R::SS,
+            K::ds => *r == R::DS,
+            K::fs => *r == R::FS,
+            K::gs => *r == R::GS,
+            _ => false,
+        },
+        Operand::Mem(..) => matches!(
+            kind,
+            K::mem
+                | K::r8_or_mem
+                | K::r16_or_mem
+                | K::r32_or_mem
+                | K::r64_or_mem
+                | K::mm_or_mem
+                | K::xmm_or_mem
+                | K::ymm_or_mem
+        ),
+        Operand::Imm(v) => match kind {
+            K::imm8
+            | K::imm8sex16
+            | K::imm8sex32
+            | K::imm8sex64
+            | K::imm16
+            | K::imm32
+            | K::imm32sex64
+            | K::imm64
+            | K::br64_1
+            | K::br64_4 => true,
+            K::imm8_const_1 => *v == 1,
+            _ => false,
+        },
+    }
+}
+
+/// Calls the `Instruction::withN` constructor matching the operand types.
+fn build(code: iced_x86::Code, operands: &[Operand]) -> Result<iced_x86::Instruction, String> {
+    use Operand::*;
+    use iced_x86::Instruction as I;
+
+    if matches!(
+        code.op_code().op_kinds(),
+        [iced_x86::OpCodeOperandKind::br64_1 | iced_x86::OpCodeOperandKind::br64_4]
+    ) {
+        let [Imm(target)] = operands else {
+            return Err("expected branch target".to_string());
+        };
+        return I::with_branch(code, *target as u64).map_err(|e| e.to_string());
+    }
+
+    let imm32 =
+        |v: i64| i32::try_from(v).map_err(|_| "immediate does not fit in 32 bits".to_string());
+
+    // Immediates that don't fit in an i32 are passed as u32 (e.g. `mov eax, 0xffffffff`) or, where the only such encoding is `mov r64, imm64`, as i64.
+    macro_rules! with_imm {
+        ($v:expr, |$imm:ident| $e:expr) => {
+            match i32::try_from($v) {
+                Ok($imm) => $e,
+                Err(_) => match u32::try_from($v) {
+                    Ok($imm) => $e,
+                    Err(_) => return Err("immediate does not fit in 32 bits".to_string()),
+                },
+            }
+        };
+    }
+
+    match *operands {
+        [] => Ok(I::with(code)),
+        [Reg(a)] => I::with1(code, a),
+        [Mem(a, _)] => I::with1(code, a),
+        [Imm(a)] => with_imm!(a, |a| I::with1(code, a)),
+        [Reg(a), Reg(b)] => I::with2(code, a, b),
+        [Reg(a), Mem(b, _)] => I::with2(code, a, b),
+        [Reg(a), Imm(b)] => match (i32::try_from(b), u32::try_from(b)) {
+            (Ok(b), _) => I::with2(code, a, b),
+            (_, Ok(b)) => I::with2(code, a, b),
+            _ => I::with2(code, a, b),
+        },
+        [Mem(a, _), Reg(b)] => I::with2(code, a, b),
+        [Mem(a, _), Imm(b)] => with_imm!(b, |b| I::with2(code, a, b)),
+        [Imm(a), Reg(b)] => with_imm!(a, |a| I::with2(code, a, b)),
+        [Imm(a), Imm(b)] => I::with2(code, imm32(a)?, imm32(b)?),
+        [Reg(a), Reg(b), Reg(c)] => I::with3(code, a, b, c),
+        [Reg(a), Reg(b), Mem(c, _)] => I::with3(code, a, b, c),
+        [Reg(a), Reg(b), Imm(c)] => with_imm!(c, |c| I::with3(code, a, b, c)),
+        [Reg(a), Mem(b, _), Reg(c)] => I::with3(code, a, b, c),
+        [Reg(a), Mem(b, _), Imm(c)] => with_imm!(c, |c| I::with3(code, a, b, c)),
+        [Reg(a), Imm(b), Imm(c)] => I::with3(code, a, imm32(b)?, imm32(c)?),
+        [Mem(a, _), Reg(b), Reg(c)] => I::with3(code, a, b, c),
+        [Mem(a, _), Reg(b), Imm(c)] => with_imm!(c, |c| I::with3(code, a, b, c)),
+        [Reg(a), Reg(b), Reg(c), Reg(d)] => I::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Reg(c), Mem(d, _)] => I::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Reg(c), Imm(d)] => with_imm!(d, |d| I::with4(code, a, b, c, d)),
+        [Reg(a), Reg(b), Mem(c, _), Reg(d)] => I::with4(code, a, b, c, d),
+        [Reg(a), Reg(b), Mem(c, _), Imm(d)] => with_imm!(d, |d| I::with4(code, a, b, c, d)),
+        [Reg(a), Reg(b), Imm(c), Imm(d)] => I::with4(code, a, b, imm32(c)?, imm32(d)?),
+        _ => return Err("unsupported combination of operands".to_string()),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn encode_instruction(
+    ctx: &Context,
+    prefixes: &[String],
+    mnemonic: &str,
+    operands: &[String],
+) -> Result<Vec<u8>, String> {
+    let codes = CODES
+        .get(mnemonic)
+        .ok_or_else(|| format!("unknown instruction {}", mnemonic))?;
+    let operands = operands
+        .iter()
+        .map(|operand| ctx.parse_operand(operand))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // 16-bit forms (e.g. `66 68 iw` for `push imm`) are only used if an operand asks for them.
+    let operand_size_16 = operands.iter().any(|operand| match operand {
+        Operand::Reg(reg) => reg.is_gpr16(),
+        Operand::Mem(_, size) => *size == Some(2),
+        Operand::Imm(_) => false,
+    });
+
+    let mut encoder = iced_x86::Encoder::new(64);
+    let mut candidates = vec![];
+    let mut last_error = None;
+    for code in codes.iter().copied() {
+        let op_code = code.op_code();
+        if (op_code.operand_size() == 16 && !operand_size_16)
+            || op_code.op_count() as usize != operands.len()
+            || !op_code
+                .op_kinds()
+                .iter()
+                .zip(operands.iter())
+                .all(|(kind, operand)| operand_matches(*kind, operand))
+        {
+            continue;
+        }
+
+        let memory_size = op_code.memory_size().size();
+        if operands.iter().any(|operand| {
+            matches!(operand, Operand::Mem(_, Some(size)) if memory_size != 0 && *size != memory_size)
+        }) {
+            continue;
+        }
+
+        let mut instruction = match build(code, &operands) {
+            Ok(instruction) => instruction,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+        for prefix in prefixes {
+            match prefix.as_str() {
+                "lock" => instruction.set_has_lock_prefix(true),
+                "rep" | "repe" | "repz" => instruction.set_has_repe_prefix(true),
+                _ => instruction.set_has_repne_prefix(true),
+            }
+        }
+        match encoder.encode(&instruction, ctx.pc) {
+            Ok(_) => candidates.push((encoder.take_buffer(), memory_size)),
+            Err(e) => last_error = Some(e.to_string()),
+        }
+    }
+
+    if candidates.is_empty() {
+        return Err(match last_error {
+            Some(e) => format!("cannot encode {}: {}", mnemonic, e),
+            None => format!("invalid operands for {}", mnemonic),
+        });
+    }
+
+    if operands
+        .iter()
+        .any(|operand| matches!(operand, Operand::Mem(_, None)))
+    {
+        let mut sizes = candidates
+            .iter()
+            .map(|(_, size)| *size)
+            .filter(|size| *size != 0)
+            .collect::<Vec<_>>();
+        sizes.sort_unstable();
+        sizes.dedup();
+        if sizes.len() > 1 {
+            return Err(format!(
+                "ambiguous operand size for {}: specify it with e.g. dword ptr",
+                mnemonic
+            ));
+        }
+    }
+
+    Ok(candidates
+        .into_iter()
+        .min_by_key(|(buf, _)| buf.len())
+        .unwrap()
+        .0)
+}
+
+fn encode_data(ctx: &Context, size: usize, operands: &[String]) -> Result<Vec<u8>, String> {
+    let mut buf = vec![];
+    for operand in operands {
+        if size == 1 && operand.starts_with('"') {
+            buf.extend(gba_asm::parse_string(operand)?);
+            continue;
+        }
+        let v = ctx.eval(operand)?;
+        if size < 8 {
+            let bits = size as u32 * 8;
+            if v < -(1 << (bits - 1)) || v >= 1 << bits {
+                return Err(format!("value {:#x} does not fit in {} bytes", v, size));
+            }
+        }
+        buf.extend_from_slice(&v.to_le_bytes()[..size]);
+    }
+    Ok(buf)
+}
+
+/// Assembles x86-64 code in Intel syntax, as if it were placed at `base_addr`.
+///
+/// Memory operands without registers (e.g. `[0x140001234]` or `[some_label]`) are RIP-relative to the absolute address given, unless prefixed with `abs` or a segment override (e.g. `gs:[0x58]`). Branch targets are absolute addresses or labels, and the shortest encoding is always picked.
+pub fn assemble(source: &str, base_addr: u64) -> Result<Assembly, Error> {
+    let statements = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            parse_statement(i + 1, line).map_err(|message| Error {
+                line: i + 1,
+                message,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut defined = std::collections::HashSet::new();
+    for statement in statements.iter() {
+        for label in statement.labels.iter() {
+            if !defined.insert(label.clone()) {
+                return Err(Error {
+                    line: statement.line,
+                    message: format!("{} is already defined", label),
+                });
+            }
+        }
+    }
+
+    let mut labels = std::collections::BTreeMap::<String, u64>::new();
+    for pass in 0..MAX_PASSES {
+        let mut code = vec![];
+        let mut new_labels = std::collections::BTreeMap::new();
+        for statement in statements.iter() {
+            let pc = base_addr + code.len() as u64;
+            for label in statement.labels.iter() {
+                new_labels.insert(label.clone(), pc);
+            }
+
+            let lookup = |name: &str| {
+                if !defined.contains(name) {
+                    return None;
+                }
+                // On the first pass, labels further ahead don't have an address yet.
+                Some(labels.get(name).copied().unwrap_or(pc) as i64)
+            };
+            let ctx = Context {
+                pc,
+                lookup: &lookup,
+            };
+            let buf = match &statement.kind {
+                None => continue,
+                Some(StatementKind::Instruction {
+                    prefixes,
+                    mnemonic,
+                    operands,
+                }) => encode_instruction(&ctx, prefixes, mnemonic, operands),
+                Some(StatementKind::Data { size, operands }) => encode_data(&ctx, *size, operands),
+            }
+            .map_err(|message| Error {
+                line: statement.line,
+                message,
+            })?;
+            code.extend(buf);
+        }
+
+        if pass > 0 && new_labels == labels {
+            return Ok(Assembly { code, labels });
+        }
+        labels = new_labels;
+    }
+
+    Err(Error {
+        line: 0,
+        message: "label addresses did not converge".to_string(),
+    })
+}
+
+/// Disassembles x86-64 code into Intel syntax accepted by [`assemble`], as if it were placed at `base_addr`.
+///
+/// Undecodable bytes are returned as single-byte `(bad)` instructions.
+pub fn disassemble(code: &[u8], base_addr: u64) -> Vec<DisassembledInstruction> {
+    use iced_x86::Formatter;
+
+    let mut formatter = iced_x86::IntelFormatter::new();
+    let options = formatter.options_mut();
+    options.set_hex_prefix("0x");
+    options.set_hex_suffix("");
+    options.set_uppercase_hex(false);
+    options.set_space_after_operand_separator(true);
+    options.set_show_branch_size(false);
+    options.set_branch_leading_zeros(false);
+
+    let mut decoder =
+        iced_x86::Decoder::with_ip(64, code, base_addr, iced_x86::DecoderOptions::NONE);
+    let mut instructions = vec![];
+    while decoder.can_decode() {
+        let position = decoder.position();
+        let instruction = decoder.decode();
+        let mut text = String::new();
+        let len = if instruction.is_invalid() {
+            text.push_str("(bad)");
+            decoder.set_position(position + 1).unwrap();
+            decoder.set_ip(base_addr + position as u64 + 1);
+            1
+        } else {
+            formatter.format(&instruction, &mut text);
+            instruction.len()
+        };
+        instructions.push(DisassembledInstruction {
+            address: base_addr + position as u64,
+            bytes: code[position..position + len].to_vec(),
+            text,
+        });
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble() {
+        let assembly = assemble(
+            r#"
+                push rbx            ; save
+                mov rax, 0x1122334455667788
+                mov eax, 0xffffffff
+                lea rcx, [rbx + rsi*8 - 0x10]
+                add dword ptr [rax], 1
+                mov rax, qword ptr gs:[0x58]
+                lock xadd [rcx], edx
+                pop rbx
+                ret
+            "#,
+            0x140001000,
+        )
+        .unwrap();
+        assert_eq!(
+            assembly.code,
+            [
+                0x53, // push rbx
+                0x48, 0xb8, 0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // mov rax, imm64
+                0xb8, 0xff, 0xff, 0xff, 0xff, // mov eax, imm32
+                0x48, 0x8d, 0x4c, 0xf3, 0xf0, // lea
+                0x83, 0x00, 0x01, // add
+                0x65, 0x48, 0x8b, 0x04, 0x25, 0x58, 0x00, 0x00, 0x00, // mov rax, gs:[0x58]
+                0xf0, 0x0f, 0xc1, 0x11, // lock xadd
+                0x5b, // pop rbx
+                0xc3, // ret
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_rip_relative() {
+        let assembly = assemble(
+            "mov rax, [0x140002000]\nlea rcx, [rel data]\ndata: dq 0",
+            0x140001000,
+        )
+        .unwrap();
+        assert_eq!(
+            assembly.code,
+            [
+                0x48, 0x8b, 0x05, 0xf9, 0x0f, 0x00, 0x00, // mov rax, [rip+0xff9]
+                0x48, 0x8d, 0x0d, 0x00, 0x00, 0x00, 0x00, // lea rcx, [rip+0]
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ]
+        );
+        assert_eq!(assembly.labels["data"], 0x14000100e);
+    }
+
+    #[test]
+    fn test_assemble_branches() {
+        let assembly = assemble(
+            r#"
+                top:
+                    test eax, eax
+                    jz done
+                    call 0x140100000
+                    jmp top
+                    .fill: db "ab", 0
+                done:
+                    ret
+            "#,
+            0x140001000,
+        )
+        .unwrap();
+        assert_eq!(
+            assembly.code,
+            [
+                0x85, 0xc0, // test eax, eax
+                0x74, 0x0a, // je done
+                0xe8, 0xf7, 0xef, 0x0f, 0x00, // call
+                0xeb, 0xf5, // jmp top
+                b'a', b'b', 0x00, //
+                0xc3, // ret
+            ]
+        );
+        assert_eq!(assembly.labels["done"], 0x14000100e);
+
+        // Far enough away to need a near jump.
+        let source = format!("jmp far\n{}far: ret", "nop\n".repeat(200));
+        let assembly = assemble(&source, 0).unwrap();
+        assert_eq!(assembly.code[..5], [0xe9, 0xc8, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn test_assemble_operand_size() {
+        assert_eq!(
+            assemble("push 0x1000", 0).unwrap().code,
+            [0x68, 0x00, 0x10, 0x00, 0x00]
+        );
+        assert_eq!(assemble("push 8", 0).unwrap().code, [0x6a, 0x08]);
+        assert_eq!(assemble("push [rax]", 0).unwrap().code, [0xff, 0x30]);
+        assert_eq!(assemble("ret 8", 0).unwrap().code, [0xc2, 0x08, 0x00]);
+
+        // Unless asked for with a 16-bit operand.
+        assert_eq!(assemble("push ax", 0).unwrap().code, [0x66, 0x50]);
+        assert_eq!(
+            assemble("push word ptr [rax]", 0).unwrap().code,
+            [0x66, 0xff, 0x30]
+        );
+        assert_eq!(
+            assemble("mov ax, 0x1000", 0).unwrap().code,
+            [0x66, 0xb8, 0x00, 0x10]
+        );
+    }
+
+    #[test]
+    fn test_assemble_extended_registers() {
+        for i in 8..16u8 {
+            assert_eq!(
+                assemble(&format!("mov r{}b, 1", i), 0).unwrap().code,
+                [0x41, 0xb0 + i - 8, 0x01]
+            );
+            assert_eq!(
+                assemble(&format!("mov r{}l, 1", i), 0).unwrap().code,
+                [0x41, 0xb0 + i - 8, 0x01]
+            );
+            assert_eq!(
+                assemble(&format!("mov r{}w, 1", i), 0).unwrap().code,
+                [0x66, 0x41, 0xb8 + i - 8, 0x01, 0x00]
+            );
+            assert_eq!(
+                assemble(&format!("mov r{}d, 1", i), 0).unwrap().code,
+                [0x41, 0xb8 + i - 8, 0x01, 0x00, 0x00, 0x00]
+            );
+        }
+        assert_eq!(assemble("mov sil, 1", 0).unwrap().code, [0x40, 0xb6, 0x01]);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = assemble("nop\nfrobnicate eax", 0).err().unwrap();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "unknown instruction frobnicate");
+
+        let err = assemble("movzx eax, [rcx]", 0).err().unwrap();
+        assert!(err.message.starts_with("ambiguous operand size"));
+        assert!(assemble("movzx eax, byte ptr [rcx]", 0).is_ok());
+
+        let err = assemble("mov rax, [rip + 8]", 0).err().unwrap();
+        assert!(err.message.contains("[rel target]"));
+
+        let err = assemble("mov eax, undefined", 0).err().unwrap();
+        assert_eq!(err.line, 1);
+
+        let err = assemble("a: nop\na: nop", 0).err().unwrap();
+        assert_eq!(err.line, 2);
+
+        // Out of range of a rip-relative displacement.
+        assert!(assemble("mov rax, [0x7fff00000000]", 0x140001000).is_err());
+    }
+
+    #[test]
+    fn test_disassemble_round_trip() {
+        let source = r#"
+            sub rsp, 0x28
+            mov rax, qword ptr [0x140005000]
+            mov ecx, dword ptr [rax + rbx*4 + 0x10]
+            mov rdx, qword ptr gs:[0x30]
+            call 0x140100000
+            jne 0x140001000
+            movaps xmm0, xmmword ptr [rsp + 0x20]
+            add rsp, 0x28
+            ret
+        "#;
+        let assembly = assemble(source, 0x140001000).unwrap();
+        let instructions = disassemble(&assembly.code, 0x140001000);
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|instruction| instruction.text.as_str())
+                .collect::<Vec<_>>(),
+            [
+                "sub rsp, 0x28",
+                "mov rax, [0x140005000]",
+                "mov ecx, [rax+rbx*4+0x10]",
+                "mov rdx, gs:[0x30]",
+                "call 0x140100000",
+                "jne 0x140001000",
+                "movaps xmm0, [rsp+0x20]",
+                "add rsp, 0x28",
+                "ret",
+            ]
+        );
+        assert_eq!(instructions[1].address, 0x140001004);
+        assert_eq!(
+            instructions[1].bytes,
+            [0x48, 0x8b, 0x05, 0xf5, 0x3f, 0x00, 0x00]
+        );
+
+        let reassembled = instructions
+            .iter()
+            .map(|instruction| instruction.text.clone())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(
+            assemble(&reassembled, 0x140001000).unwrap().code,
+            assembly.code
+        );
+    }
+
+    #[test]
+    fn test_disassemble_bad() {
+        let instructions = disassemble(&[0x90, 0x06, 0xc3], 0x1000);
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|instruction| (instruction.address, instruction.text.as_str()))
+                .collect::<Vec<_>>(),
+            [(0x1000, "nop"), (0x1001, "(bad)"), (0x1002, "ret")]
+        );
+    }
+}
@@ -16,6 +16,7 @@ def make_entries():
     entries = [
         Entry("README.md", "README.md"),
         Entry("API.md", "API.md"),
+        Entry("chaudloader.h", "chaudloader/include/chaudloader.h"),
         Entry("chaudloader.dll", "target/release/chaudloader.dll"),
         Entry("dxgi.dll", "target/release/dxgi.dll"),
         Entry("lua54.dll", "lua54.dll"),
@@ -35,6 +36,7 @@ def make_windows_entries():
     return [
         *make_entries(),
         Entry("install.exe", "target/release/install.exe"),
+        Entry("mpaktool.exe", "target/release/mpaktool.exe"),
     ]
 
 
@@ -0,0 +1,925 @@
+//! Reading, editing and writing the .map + .mpak pairs that hold the games' GBA ROM data, shared by chaudloader and mpaktool.
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("map is truncated: map is only {map_len} bytes, which is too short for its header")]
+    TruncatedHeader { map_len: usize },
+
+    #[error("map is truncated: header declares {count} entries, but map is only {map_len} bytes")]
+    TruncatedMap { count: u32, map_len: usize },
+
+    #[error(
+        "map header has an invalid rom address range: min {rom_addr_min:#010x} is greater than max {rom_addr_max:#010x}"
+    )]
+    InvalidHeaderRange {
+        rom_addr_min: u32,
+        rom_addr_max: u32,
+    },
+
+    #[error(
+        "entry {index} (rom address {rom_addr:#010x}): mpak range {mpak_offset:#x}+{mpak_size:#x} is past the end of the mpak ({mpak_len:#x} bytes)"
+    )]
+    EntryOutOfBounds {
+        index: usize,
+        rom_addr: u32,
+        mpak_offset: u32,
+        mpak_size: u32,
+        mpak_len: u64,
+    },
+
+    #[error(
+        "entry {index} (rom address {rom_addr:#010x}) is outside of the range declared in the map header ({rom_addr_min:#010x}..={rom_addr_max:#010x})"
+    )]
+    RomAddrOutOfRange {
+        index: usize,
+        rom_addr: u32,
+        rom_addr_min: u32,
+        rom_addr_max: u32,
+    },
+
+    #[error("entry {index} (rom address {rom_addr:#010x}) duplicates entry {other_index}")]
+    DuplicateRomAddr {
+        index: usize,
+        rom_addr: u32,
+        other_index: usize,
+    },
+
+    #[error(
+        "entry {index} (rom address {rom_addr:#010x}) overlaps entry {other_index} (rom address {other_rom_addr:#010x}, size {other_size:#x})"
+    )]
+    OverlappingEntries {
+        index: usize,
+        rom_addr: u32,
+        other_index: usize,
+        other_rom_addr: u32,
+        other_size: u32,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AllocError {
+    #[error("cannot allocate zero bytes")]
+    ZeroSize,
+
+    #[error("alignment {align:#x} is not a power of two")]
+    InvalidAlignment { align: u32 },
+
+    #[error("no free rom space for {size:#x} bytes aligned to {align:#x}")]
+    OutOfSpace { size: u32, align: u32 },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RomError {
+    #[error(
+        "entry at rom address {rom_addr:#010x} (size {size:#x}) is outside of the cartridge rom address space"
+    )]
+    EntryOutsideRom { rom_addr: u32, size: usize },
+
+    #[error("rom is {len:#x} bytes, which is larger than the maximum of {max_len:#x} bytes")]
+    RomTooLarge { len: usize, max_len: usize },
+
+    #[error(
+        "rom is only {len:#x} bytes, but the entry at rom address {rom_addr:#010x} ends at offset {end:#x}"
+    )]
+    RomTruncated {
+        len: usize,
+        rom_addr: u32,
+        end: usize,
+    },
+}
+
+/// Start of the GBA cartridge ROM address space.
+pub const ROM_ADDR_START: u64 = 0x08000000;
+
+/// End (exclusive) of the GBA cartridge ROM address space: cartridges are at most 32 MiB.
+pub const ROM_ADDR_END: u64 = 0x0a000000;
+
+/// When diffing a ROM, runs of changed bytes outside of entries separated by fewer than this many fill bytes are merged into a single entry.
+const DIFF_MERGE_DISTANCE: usize = 16;
+
+/// Options for [`Mpak::find_pointers`] and [`Mpak::repoint`].
+#[derive(Clone, Copy, Debug)]
+pub struct PointerOptions {
+    /// Only consider pointers stored at ROM addresses that are a multiple of this. Must be a power of two.
+    pub align: u32,
+
+    /// Also match Thumb pointers to the address, i.e. the address with its lowest bit set.
+    pub thumb: bool,
+}
+
+impl Default for PointerOptions {
+    fn default() -> Self {
+        Self {
+            align: 4,
+            thumb: false,
+        }
+    }
+}
+
+/// Entries are reference counted, so clones of an mpak are cheap until they are modified.
+#[derive(Clone)]
+pub struct Mpak {
+    entries: indexmap::IndexMap<u32, std::sync::Arc<[u8]>>,
+}
+
+struct MapHeader {
+    count: u32,
+    rom_addr_min: u32,
+    rom_addr_max: u32,
+}
+
+struct MapEntry {
+    rom_addr: u32,
+    mpak_offset: u32,
+    mpak_size: u32,
+}
+
+impl MapHeader {
+    const SIZE: usize = 12;
+
+    pub fn read_from(mut r: impl std::io::Read) -> Result<Self, std::io::Error> {
+        Ok(MapHeader {
+            count: r.read_u32::<byteorder::LittleEndian>()?,
+            rom_addr_min: r.read_u32::<byteorder::LittleEndian>()?,
+            rom_addr_max: r.read_u32::<byteorder::LittleEndian>()?,
+        })
+    }
+
+    pub fn write_into(&self, mut w: impl std::io::Write) -> Result<(), std::io::Error> {
+        w.write_u32::<byteorder::LittleEndian>(self.count)?;
+        w.write_u32::<byteorder::LittleEndian>(self.rom_addr_min)?;
+        w.write_u32::<byteorder::LittleEndian>(self.rom_addr_max)?;
+        Ok(())
+    }
+}
+
+impl MapEntry {
+    const SIZE: usize = 12;
+
+    pub fn read_from(mut r: impl std::io::Read) -> Result<Self, std::io::Error> {
+        Ok(MapEntry {
+            rom_addr: r.read_u32::<byteorder::LittleEndian>()?,
+            mpak_offset: r.read_u32::<byteorder::LittleEndian>()?,
+            mpak_size: r.read_u32::<byteorder::LittleEndian>()?,
+        })
+    }
+
+    pub fn write_into(&self, mut w: impl std::io::Write) -> Result<(), std::io::Error> {
+        w.write_u32::<byteorder::LittleEndian>(self.rom_addr)?;
+        w.write_u32::<byteorder::LittleEndian>(self.mpak_offset)?;
+        w.write_u32::<byteorder::LittleEndian>(self.mpak_size)?;
+        Ok(())
+    }
+}
+
+/// Adds runs of bytes in `rom[gap]` that differ from `fill` to `entries`, merging runs that are close together.
+fn diff_gap(
+    rom: &[u8],
+    fill: u8,
+    gap: std::ops::Range<usize>,
+    entries: &mut indexmap::IndexMap<u32, std::sync::Arc<[u8]>>,
+) {
+    let mut insert = |run: std::ops::Range<usize>| {
+        entries.insert(
+            (ROM_ADDR_START as usize + run.start) as u32,
+            rom[run].into(),
+        );
+    };
+
+    let mut run: Option<std::ops::Range<usize>> = None;
+    for i in gap.filter(|i| rom[*i] != fill) {
+        run = match run {
+            Some(run) if i - run.end < DIFF_MERGE_DISTANCE => Some(run.start..i + 1),
+            Some(run) => {
+                insert(run);
+                Some(i..i + 1)
+            }
+            None => Some(i..i + 1),
+        };
+    }
+    if let Some(run) = run {
+        insert(run);
+    }
+}
+
+impl Mpak {
+    pub fn read_from(
+        mut map_reader: impl std::io::Read,
+        mut mpak_reader: impl std::io::Read + std::io::Seek,
+    ) -> Result<Self, Error> {
+        let mut map = vec![];
+        map_reader.read_to_end(&mut map)?;
+        let mut map_reader = std::io::Cursor::new(&map);
+
+        let mpak_len = mpak_reader.seek(std::io::SeekFrom::End(0))?;
+
+        if map.len() < MapHeader::SIZE {
+            return Err(Error::TruncatedHeader { map_len: map.len() });
+        }
+        let header = MapHeader::read_from(&mut map_reader)?;
+        // Check the map is large enough for all of its entries before trusting the count.
+        if ((map.len() - MapHeader::SIZE) / MapEntry::SIZE) < header.count as usize {
+            return Err(Error::TruncatedMap {
+                count: header.count,
+                map_len: map.len(),
+            });
+        }
+        if header.count > 0 && header.rom_addr_min > header.rom_addr_max {
+            return Err(Error::InvalidHeaderRange {
+                rom_addr_min: header.rom_addr_min,
+                rom_addr_max: header.rom_addr_max,
+            });
+        }
+
+        // Read the entire mpak into memory: who cares, it's not very expensive.
+        let mut entries = indexmap::IndexMap::with_capacity(header.count as usize);
+        for index in 0..header.count as usize {
+            let entry = MapEntry::read_from(&mut map_reader)?;
+
+            if entry.mpak_offset as u64 + entry.mpak_size as u64 > mpak_len {
+                return Err(Error::EntryOutOfBounds {
+                    index,
+                    rom_addr: entry.rom_addr,
+                    mpak_offset: entry.mpak_offset,
+                    mpak_size: entry.mpak_size,
+                    mpak_len,
+                });
+            }
+
+            if entry.rom_addr < header.rom_addr_min || entry.rom_addr > header.rom_addr_max {
+                return Err(Error::RomAddrOutOfRange {
+                    index,
+                    rom_addr: entry.rom_addr,
+                    rom_addr_min: header.rom_addr_min,
+                    rom_addr_max: header.rom_addr_max,
+                });
+            }
+
+            if let Some(other_index) = entries.get_index_of(&entry.rom_addr) {
+                return Err(Error::DuplicateRomAddr {
+                    index,
+                    rom_addr: entry.rom_addr,
+                    other_index,
+                });
+            }
+
+            let mut buf = vec![0; entry.mpak_size as usize];
+            mpak_reader.seek(std::io::SeekFrom::Start(entry.mpak_offset as u64))?;
+            mpak_reader.read_exact(buf.as_mut_slice())?;
+            entries.insert(entry.rom_addr, buf.into());
+        }
+
+        let mpak = Self { entries };
+        mpak.check_overlaps()?;
+        Ok(mpak)
+    }
+
+    /// Checks that no two entries cover the same ROM addresses.
+    fn check_overlaps(&self) -> Result<(), Error> {
+        let mut ranges = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, (rom_addr, contents))| (*rom_addr as u64, contents.len() as u64, index))
+            .collect::<Vec<_>>();
+        ranges.sort_unstable();
+        for pair in ranges.windows(2) {
+            let (other_rom_addr, other_size, other_index) = pair[0];
+            let (rom_addr, _, index) = pair[1];
+            if other_rom_addr + other_size > rom_addr {
+                return Err(Error::OverlappingEntries {
+                    index,
+                    rom_addr: rom_addr as u32,
+                    other_index,
+                    other_rom_addr: other_rom_addr as u32,
+                    other_size: other_size as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, rom_addr: u32, contents: Vec<u8>) -> Option<std::sync::Arc<[u8]>> {
+        self.entries.insert(rom_addr, contents.into())
+    }
+
+    pub fn remove(&mut self, rom_addr: u32) -> Option<std::sync::Arc<[u8]>> {
+        self.entries.remove(&rom_addr)
+    }
+
+    pub fn get(&self, rom_addr: u32) -> Option<&[u8]> {
+        self.entries.get(&rom_addr).map(|v| &v[..])
+    }
+
+    /// Writes `data` at the given ROM address.
+    ///
+    /// Parts of the range covered by existing entries are patched in place, and any parts not covered by an entry are inserted as new entries.
+    pub fn write(&mut self, rom_addr: u32, data: &[u8]) {
+        let start = rom_addr as u64;
+        let end = start + data.len() as u64;
+
+        let mut covered = vec![];
+        for (entry_addr, contents) in self.entries.iter_mut() {
+            let entry_start = *entry_addr as u64;
+            let overlap_start = start.max(entry_start);
+            let overlap_end = end.min(entry_start + contents.len() as u64);
+            if overlap_start >= overlap_end {
+                continue;
+            }
+            std::sync::Arc::make_mut(contents)
+                [(overlap_start - entry_start) as usize..(overlap_end - entry_start) as usize]
+                .copy_from_slice(
+                    &data[(overlap_start - start) as usize..(overlap_end - start) as usize],
+                );
+            covered.push(overlap_start..overlap_end);
+        }
+        covered.sort_unstable_by_key(|range| range.start);
+
+        let mut cursor = start;
+        for range in covered.into_iter().chain(std::iter::once(end..end)) {
+            if range.start > cursor {
+                self.entries.insert(
+                    cursor as u32,
+                    data[(cursor - start) as usize..(range.start - start) as usize].into(),
+                );
+            }
+            cursor = cursor.max(range.end);
+        }
+    }
+
+    pub fn get_index(&self, index: usize) -> Option<(u32, &[u8])> {
+        self.entries.get_index(index).map(|(k, v)| (*k, &v[..]))
+    }
+
+    /// Finds a free ROM address range of the given size and alignment and reserves it.
+    ///
+    /// Gaps in the cartridge ROM address space are tried in ascending order: the space before the first entry, between entries and past the last entry. The range is reserved by inserting a zero-filled entry at the returned address, so it is packed along with the rest of the mpak and any later allocation from the same data will not overlap it. The caller is expected to replace the entry with its own contents, no larger than `size`.
+    pub fn alloc(&mut self, size: u32, align: u32) -> Result<u32, AllocError> {
+        if size == 0 {
+            return Err(AllocError::ZeroSize);
+        }
+        if !align.is_power_of_two() {
+            return Err(AllocError::InvalidAlignment { align });
+        }
+
+        let align_up = |addr: u64| (addr + align as u64 - 1) & !(align as u64 - 1);
+
+        let mut ranges = self
+            .entries
+            .iter()
+            .map(|(rom_addr, contents)| (*rom_addr as u64, contents.len() as u64))
+            .collect::<Vec<_>>();
+        ranges.sort_unstable();
+
+        // The end of the address space is treated as one more entry, so the space past the last entry is just another gap.
+        let mut cursor = ROM_ADDR_START;
+        let rom_addr = ranges
+            .iter()
+            .copied()
+            .chain(std::iter::once((ROM_ADDR_END, 0)))
+            .find_map(|(rom_addr, len)| {
+                let candidate = align_up(cursor);
+                if candidate + size as u64 <= rom_addr.min(ROM_ADDR_END) {
+                    return Some(candidate);
+                }
+                cursor = cursor.max(rom_addr + len);
+                None
+            })
+            .ok_or(AllocError::OutOfSpace { size, align })? as u32;
+
+        self.entries.insert(rom_addr, vec![0; size as usize].into());
+        Ok(rom_addr)
+    }
+
+    /// Calls `f` with the ROM address of every pointer to `target`, along with the entry it is in and its offset in that entry.
+    fn for_each_pointer(
+        &self,
+        target: u32,
+        options: PointerOptions,
+        mut f: impl FnMut(u32, usize, u32),
+    ) {
+        let align = options.align.max(1) as u64;
+        for (index, (rom_addr, contents)) in self.entries.iter().enumerate() {
+            let first = ((*rom_addr as u64 + align - 1) & !(align - 1)) - *rom_addr as u64;
+            for offset in (first as usize..contents.len().saturating_sub(3)).step_by(align as usize)
+            {
+                let value = byteorder::LittleEndian::read_u32(&contents[offset..offset + 4]);
+                if value == target || (options.thumb && value == target | 1) {
+                    f(rom_addr + offset as u32, index, value);
+                }
+            }
+        }
+    }
+
+    /// Finds the ROM addresses of all pointers to `target` in all entries, in entry order.
+    pub fn find_pointers(&self, target: u32, options: PointerOptions) -> Vec<u32> {
+        let mut pointers = vec![];
+        self.for_each_pointer(target, options, |pointer_addr, _, _| {
+            pointers.push(pointer_addr)
+        });
+        pointers
+    }
+
+    /// Rewrites all pointers to `old_addr` to point to `new_addr` instead, returning how many were rewritten.
+    ///
+    /// If Thumb pointers are matched, the Thumb bit is preserved on the new pointer.
+    pub fn repoint(&mut self, old_addr: u32, new_addr: u32, options: PointerOptions) -> usize {
+        let mut pointers = vec![];
+        self.for_each_pointer(old_addr, options, |pointer_addr, index, value| {
+            pointers.push((pointer_addr, index, value))
+        });
+
+        for (pointer_addr, index, value) in pointers.iter().copied() {
+            let (rom_addr, contents) = self.entries.get_index_mut(index).unwrap();
+            let offset = (pointer_addr - *rom_addr) as usize;
+            // Only copies the entry the first time, if it is shared with another mpak.
+            byteorder::LittleEndian::write_u32(
+                &mut std::sync::Arc::make_mut(contents)[offset..offset + 4],
+                new_addr | (value & !old_addr & 1),
+            );
+        }
+
+        pointers.len()
+    }
+
+    /// Returns the ROM offset range of every entry, sorted by address.
+    fn rom_ranges(&self) -> Result<Vec<(u32, std::ops::Range<usize>)>, RomError> {
+        let mut ranges = self
+            .entries
+            .iter()
+            .map(|(rom_addr, contents)| {
+                let start = (*rom_addr as u64)
+                    .checked_sub(ROM_ADDR_START)
+                    .filter(|start| ROM_ADDR_START + start + contents.len() as u64 <= ROM_ADDR_END)
+                    .ok_or(RomError::EntryOutsideRom {
+                        rom_addr: *rom_addr,
+                        size: contents.len(),
+                    })? as usize;
+                Ok((*rom_addr, start..start + contents.len()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        ranges.sort_unstable_by_key(|(_, range)| range.start);
+        Ok(ranges)
+    }
+
+    /// Builds a GBA ROM image out of all entries, with the gaps between them filled with `fill`.
+    ///
+    /// The image starts at the beginning of the cartridge address space and ends at the end of the last entry.
+    pub fn to_rom(&self, fill: u8) -> Result<Vec<u8>, RomError> {
+        let ranges = self.rom_ranges()?;
+        let mut rom = vec![fill; ranges.iter().map(|(_, range)| range.end).max().unwrap_or(0)];
+        for (rom_addr, range) in ranges {
+            rom[range].copy_from_slice(&self.entries[&rom_addr]);
+        }
+        Ok(rom)
+    }
+
+    /// Diffs an edited ROM image against this mpak, returning the entries needed to turn this mpak into the edited ROM.
+    ///
+    /// Entries with any changed bytes are returned in full, at their original address. Bytes outside of any entry that differ from `fill` (i.e. from what [`Mpak::to_rom`] would have produced) are returned as new entries, with nearby runs merged together. The returned entries never overlap this mpak's entries, so they can be inserted into it as is.
+    pub fn diff_rom(&self, rom: &[u8], fill: u8) -> Result<Mpak, RomError> {
+        let max_len = (ROM_ADDR_END - ROM_ADDR_START) as usize;
+        if rom.len() > max_len {
+            return Err(RomError::RomTooLarge {
+                len: rom.len(),
+                max_len,
+            });
+        }
+
+        let ranges = self.rom_ranges()?;
+        let mut entries = indexmap::IndexMap::new();
+
+        let mut gap_start = 0;
+        for (rom_addr, range) in ranges {
+            if range.end > rom.len() {
+                return Err(RomError::RomTruncated {
+                    len: rom.len(),
+                    rom_addr,
+                    end: range.end,
+                });
+            }
+            diff_gap(rom, fill, gap_start..range.start, &mut entries);
+            gap_start = range.end;
+
+            let contents = &rom[range];
+            if contents != &self.entries[&rom_addr][..] {
+                entries.insert(rom_addr, contents.into());
+            }
+        }
+        diff_gap(rom, fill, gap_start..rom.len(), &mut entries);
+
+        Ok(Mpak { entries })
+    }
+
+    pub fn write_into(
+        &self,
+        mut map_writer: impl std::io::Write,
+        mut mpak_writer: impl std::io::Write,
+    ) -> Result<(), std::io::Error> {
+        MapHeader {
+            count: self.entries.len() as u32,
+            rom_addr_min: self.entries.keys().min().copied().unwrap_or(0),
+            rom_addr_max: self.entries.keys().max().copied().unwrap_or(0),
+        }
+        .write_into(&mut map_writer)?;
+
+        let mut mpak_offset = 0;
+        for (rom_addr, entry) in self.entries.iter() {
+            MapEntry {
+                rom_addr: *rom_addr,
+                mpak_offset: mpak_offset as u32,
+                mpak_size: entry.len() as u32,
+            }
+            .write_into(&mut map_writer)?;
+            mpak_writer.write_all(entry)?;
+            mpak_offset += entry.len();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_map(rom_addr_min: u32, rom_addr_max: u32, entries: &[(u32, u32, u32)]) -> Vec<u8> {
+        let mut map = vec![];
+        MapHeader {
+            count: entries.len() as u32,
+            rom_addr_min,
+            rom_addr_max,
+        }
+        .write_into(&mut map)
+        .unwrap();
+        for (rom_addr, mpak_offset, mpak_size) in entries.iter().copied() {
+            MapEntry {
+                rom_addr,
+                mpak_offset,
+                mpak_size,
+            }
+            .write_into(&mut map)
+            .unwrap();
+        }
+        map
+    }
+
+    fn read(map: &[u8], mpak: &[u8]) -> Result<Mpak, Error> {
+        Mpak::read_from(std::io::Cursor::new(map), std::io::Cursor::new(mpak))
+    }
+
+    #[test]
+    fn test_read_write_roundtrip() {
+        let mpak = read(
+            &make_map(
+                0x08000000,
+                0x08000010,
+                &[(0x08000000, 0, 4), (0x08000010, 4, 2)],
+            ),
+            b"abcdef",
+        )
+        .unwrap();
+        assert_eq!(mpak.get(0x08000000), Some(&b"abcd"[..]));
+        assert_eq!(mpak.get(0x08000010), Some(&b"ef"[..]));
+
+        let mut map_contents = vec![];
+        let mut mpak_contents = vec![];
+        mpak.write_into(&mut map_contents, &mut mpak_contents)
+            .unwrap();
+        let mpak = read(&map_contents, &mpak_contents).unwrap();
+        assert_eq!(mpak.get(0x08000000), Some(&b"abcd"[..]));
+        assert_eq!(mpak.get(0x08000010), Some(&b"ef"[..]));
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        assert!(matches!(
+            read(&[0; 4], b"").err().unwrap(),
+            Error::TruncatedHeader { map_len: 4 }
+        ));
+
+        let mut map = make_map(0x08000000, 0x08000000, &[(0x08000000, 0, 1)]);
+        map[0] = 0xff;
+        assert!(matches!(
+            read(&map, b"a").err().unwrap(),
+            Error::TruncatedMap { count: 0xff, .. }
+        ));
+    }
+
+    #[test]
+    fn test_read_entry_out_of_bounds() {
+        assert!(matches!(
+            read(
+                &make_map(
+                    0x08000000,
+                    0x08000010,
+                    &[(0x08000000, 0, 4), (0x08000010, 4, 0xffffffff)]
+                ),
+                b"abcdef",
+            )
+            .err()
+            .unwrap(),
+            Error::EntryOutOfBounds {
+                index: 1,
+                rom_addr: 0x08000010,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_read_rom_addr_out_of_range() {
+        assert!(matches!(
+            read(
+                &make_map(
+                    0x08000000,
+                    0x08000004,
+                    &[(0x08000000, 0, 4), (0x08000010, 4, 2)]
+                ),
+                b"abcdef",
+            )
+            .err()
+            .unwrap(),
+            Error::RomAddrOutOfRange { index: 1, .. }
+        ));
+        assert!(matches!(
+            read(
+                &make_map(0x08000010, 0x08000000, &[(0x08000000, 0, 4)]),
+                b"abcd"
+            )
+            .err()
+            .unwrap(),
+            Error::InvalidHeaderRange { .. }
+        ));
+    }
+
+    #[test]
+    fn test_read_duplicate_and_overlapping() {
+        assert!(matches!(
+            read(
+                &make_map(
+                    0x08000000,
+                    0x08000000,
+                    &[(0x08000000, 0, 4), (0x08000000, 4, 2)]
+                ),
+                b"abcdef",
+            )
+            .err()
+            .unwrap(),
+            Error::DuplicateRomAddr {
+                index: 1,
+                other_index: 0,
+                ..
+            }
+        ));
+        assert!(matches!(
+            read(
+                &make_map(
+                    0x08000000,
+                    0x08000002,
+                    &[(0x08000002, 4, 2), (0x08000000, 0, 4)]
+                ),
+                b"abcdef",
+            )
+            .err()
+            .unwrap(),
+            Error::OverlappingEntries {
+                index: 0,
+                rom_addr: 0x08000002,
+                other_index: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_alloc() {
+        let mut mpak = read(
+            &make_map(
+                0x08000000,
+                0x08000010,
+                &[(0x08000000, 0, 4), (0x08000010, 4, 2)],
+            ),
+            b"abcdef",
+        )
+        .unwrap();
+
+        // Fits in the gap between the two entries.
+        assert_eq!(mpak.alloc(8, 4).unwrap(), 0x08000004);
+        assert_eq!(mpak.get(0x08000004), Some(&[0u8; 8][..]));
+
+        // The rest of the gap is too small for this alignment, so it goes past the end.
+        assert_eq!(mpak.alloc(4, 8).unwrap(), 0x08000018);
+
+        // The remaining 4 bytes of the gap are still usable.
+        assert_eq!(mpak.alloc(4, 4).unwrap(), 0x0800000c);
+
+        // Allocations are packed like any other entry, so they are still reserved after a roundtrip.
+        let mut map_contents = vec![];
+        let mut mpak_contents = vec![];
+        mpak.write_into(&mut map_contents, &mut mpak_contents)
+            .unwrap();
+        let mut mpak = read(&map_contents, &mpak_contents).unwrap();
+        assert_eq!(mpak.alloc(1, 1).unwrap(), 0x08000012);
+    }
+
+    #[test]
+    fn test_alloc_errors() {
+        let mut mpak = read(&make_map(0, 0, &[]), b"").unwrap();
+        assert!(matches!(mpak.alloc(0, 4), Err(AllocError::ZeroSize)));
+        assert!(matches!(
+            mpak.alloc(4, 3),
+            Err(AllocError::InvalidAlignment { align: 3 })
+        ));
+        assert_eq!(mpak.alloc(0x01000000, 4).unwrap(), 0x08000000);
+        assert_eq!(mpak.alloc(0x01000000, 4).unwrap(), 0x09000000);
+        assert!(matches!(
+            mpak.alloc(1, 1),
+            Err(AllocError::OutOfSpace { size: 1, align: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_alloc_gap_bounds() {
+        let mut mpak = read(
+            &make_map(
+                0x08000010,
+                0x0a000100,
+                &[(0x08000010, 0, 4), (0x09fffff0, 4, 4), (0x0a000100, 8, 4)],
+            ),
+            b"abcdefghijkl",
+        )
+        .unwrap();
+
+        // The space before the first entry is used.
+        assert_eq!(mpak.alloc(8, 4).unwrap(), 0x08000000);
+
+        // The only aligned address left is the end of the address space, which is in the gap before the entry past it.
+        assert!(matches!(
+            mpak.alloc(4, 0x02000000),
+            Err(AllocError::OutOfSpace {
+                size: 4,
+                align: 0x02000000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_find_pointers_and_repoint() {
+        let mut mpak = read(
+            &make_map(
+                0x08000000,
+                0x08000010,
+                &[(0x08000000, 0, 12), (0x08000010, 12, 7)],
+            ),
+            b"\x00\x01\x00\x08\x01\x01\x00\x08\x00\x00\x00\x00\
+              \xff\x00\x01\x00\x08\x00\x00",
+        )
+        .unwrap();
+
+        assert_eq!(
+            mpak.find_pointers(0x08000100, PointerOptions::default()),
+            vec![0x08000000]
+        );
+        assert_eq!(
+            mpak.find_pointers(
+                0x08000100,
+                PointerOptions {
+                    thumb: true,
+                    ..Default::default()
+                }
+            ),
+            vec![0x08000000, 0x08000004]
+        );
+        assert_eq!(
+            mpak.find_pointers(
+                0x08000100,
+                PointerOptions {
+                    align: 1,
+                    ..Default::default()
+                }
+            ),
+            vec![0x08000000, 0x08000011]
+        );
+
+        assert_eq!(
+            mpak.repoint(
+                0x08000100,
+                0x08000200,
+                PointerOptions {
+                    thumb: true,
+                    ..Default::default()
+                }
+            ),
+            2
+        );
+        assert_eq!(
+            mpak.get(0x08000000),
+            Some(&b"\x00\x02\x00\x08\x01\x02\x00\x08\x00\x00\x00\x00"[..])
+        );
+        // The unaligned pointer is left alone.
+        assert_eq!(
+            mpak.get(0x08000010),
+            Some(&b"\xff\x00\x01\x00\x08\x00\x00"[..])
+        );
+    }
+
+    #[test]
+    fn test_to_rom_and_diff_rom() {
+        let mpak = read(
+            &make_map(
+                0x08000000,
+                0x08000010,
+                &[(0x08000004, 0, 4), (0x08000010, 4, 2)],
+            ),
+            b"abcdef",
+        )
+        .unwrap();
+
+        let mut rom = mpak.to_rom(0xff).unwrap();
+        assert_eq!(
+            rom,
+            b"\xff\xff\xff\xffabcd\xff\xff\xff\xff\xff\xff\xff\xffef"
+        );
+        assert_eq!(mpak.diff_rom(&rom, 0xff).unwrap().entries.len(), 0);
+
+        rom[0x05] = b'X';
+        rom[0x09] = b'Y';
+        rom[0x0b] = b'Z';
+        rom.extend_from_slice(b"gh");
+        rom.extend_from_slice(&[0xff; DIFF_MERGE_DISTANCE]);
+        rom.extend_from_slice(b"ij");
+        let diff = mpak.diff_rom(&rom, 0xff).unwrap();
+        assert_eq!(
+            diff.entries
+                .iter()
+                .map(|(rom_addr, contents)| (*rom_addr, &contents[..]))
+                .collect::<Vec<_>>(),
+            vec![
+                (0x08000004, &b"aXcd"[..]),
+                (0x08000009, &b"Y\xffZ"[..]),
+                (0x08000012, &b"gh"[..]),
+                (0x08000024, &b"ij"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rom_errors() {
+        let mpak = read(
+            &make_map(0x04000000, 0x04000000, &[(0x04000000, 0, 1)]),
+            b"a",
+        )
+        .unwrap();
+        assert!(matches!(
+            mpak.to_rom(0).err().unwrap(),
+            RomError::EntryOutsideRom {
+                rom_addr: 0x04000000,
+                ..
+            }
+        ));
+
+        let mpak = read(
+            &make_map(0x08000000, 0x08000000, &[(0x08000000, 0, 4)]),
+            b"abcd",
+        )
+        .unwrap();
+        assert!(matches!(
+            mpak.diff_rom(b"ab", 0).err().unwrap(),
+            RomError::RomTruncated { len: 2, end: 4, .. }
+        ));
+    }
+
+    #[test]
+    fn test_write() {
+        let mut mpak = read(
+            &make_map(
+                0x08000000,
+                0x08000008,
+                &[(0x08000000, 0, 4), (0x08000008, 4, 4)],
+            ),
+            b"abcdefgh",
+        )
+        .unwrap();
+
+        // Inside a single entry.
+        mpak.write(0x08000001, b"XY");
+        assert_eq!(mpak.get(0x08000000), Some(&b"aXYd"[..]));
+
+        // Spanning both entries and the gap between them, and past the end.
+        mpak.write(0x08000002, b"0123456789");
+        assert_eq!(mpak.get(0x08000000), Some(&b"aX01"[..]));
+        assert_eq!(mpak.get(0x08000004), Some(&b"2345"[..]));
+        assert_eq!(mpak.get(0x08000008), Some(&b"6789"[..]));
+        assert_eq!(mpak.entries.len(), 3);
+
+        mpak.write(0x0800000a, b"!!!");
+        assert_eq!(mpak.get(0x08000008), Some(&b"67!!"[..]));
+        assert_eq!(mpak.get(0x0800000c), Some(&b"!"[..]));
+    }
+}
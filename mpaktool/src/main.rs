@@ -0,0 +1,75 @@
+use clap::Parser;
+
+#[derive(clap::Parser)]
+#[command(about = "Converts between .map + .mpak pairs and GBA ROM images.")]
+enum Args {
+    /// Builds a GBA ROM image out of a .map + .mpak pair.
+    Export {
+        map: std::path::PathBuf,
+        mpak: std::path::PathBuf,
+        /// Path to write the ROM image to.
+        rom: std::path::PathBuf,
+        /// Byte to fill gaps between entries with.
+        #[arg(long, default_value_t = 0xff)]
+        fill: u8,
+    },
+
+    /// Diffs an edited GBA ROM image against a .map + .mpak pair and writes the changed entries out as a new .map + .mpak pair.
+    Diff {
+        map: std::path::PathBuf,
+        mpak: std::path::PathBuf,
+        /// Path to the edited ROM image.
+        rom: std::path::PathBuf,
+        /// Path to write the .map of changed entries to.
+        out_map: std::path::PathBuf,
+        /// Path to write the .mpak of changed entries to.
+        out_mpak: std::path::PathBuf,
+        /// Byte the gaps between entries were filled with when the ROM image was exported.
+        #[arg(long, default_value_t = 0xff)]
+        fill: u8,
+    },
+}
+
+fn read_mpak(map: &std::path::Path, mpak: &std::path::Path) -> Result<mpak::Mpak, anyhow::Error> {
+    Ok(mpak::Mpak::read_from(
+        std::io::BufReader::new(std::fs::File::open(map)?),
+        std::io::BufReader::new(std::fs::File::open(mpak)?),
+    )?)
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    match Args::parse() {
+        Args::Export {
+            map,
+            mpak,
+            rom,
+            fill,
+        } => {
+            let rom_contents = read_mpak(&map, &mpak)?.to_rom(fill)?;
+            std::fs::write(&rom, &rom_contents)?;
+            println!("wrote {} ({} bytes)", rom.display(), rom_contents.len());
+        }
+        Args::Diff {
+            map,
+            mpak,
+            rom,
+            out_map,
+            out_mpak,
+            fill,
+        } => {
+            let diff = read_mpak(&map, &mpak)?.diff_rom(&std::fs::read(&rom)?, fill)?;
+            let mut map_contents = vec![];
+            let mut mpak_contents = vec![];
+            diff.write_into(&mut map_contents, &mut mpak_contents)?;
+            std::fs::write(&out_map, &map_contents)?;
+            std::fs::write(&out_mpak, &mpak_contents)?;
+            let mut count = 0;
+            while let Some((rom_addr, contents)) = diff.get_index(count) {
+                println!("{:#010x}: {} bytes", rom_addr, contents.len());
+                count += 1;
+            }
+            println!("wrote {} changed entries", count);
+        }
+    }
+    Ok(())
+}